@@ -1,13 +1,18 @@
+use crate::blocks::BlockDefinition;
+use crate::blueprint::BlueprintInstance;
 use crate::codegen::generator::CodeGenerator;
+use crate::diagnostics::LoadDiagnostic;
+use crate::error::{AutomationError, CompileDiagnostic};
+use crate::ha_registry::Registries;
+use crate::history::{AutomationDiff, AutomationVersion};
 use crate::rhai::engine::ScriptEngine;
+use crate::storage::{AutomationRepository, YamlAutomationRepository};
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::io::Error;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::fs;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
@@ -25,6 +30,14 @@ pub struct Automation {
     pub updated_at: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub compilation_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Slash-separated folder path such as `downstairs/lights`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// Set when the workspace is rendered from a blueprint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blueprint: Option<BlueprintInstance>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +63,10 @@ pub struct AutomationCreate {
     pub triggers: Vec<TriggerDefinition>,
     pub workspace: Value,
     pub conditions: Vec<ConditionDefinition>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub folder: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,14 +78,276 @@ pub struct AutomationUpdate {
     pub triggers: Vec<TriggerDefinition>,
     pub workspace: Value,
     pub conditions: Vec<ConditionDefinition>,
+    /// Kept as they are when absent
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Kept as it is when absent; `null` moves the automation out of its folder
+    #[serde(default, deserialize_with = "present")]
+    pub folder: Option<Option<String>>,
 }
 
+/// Tells a field given as `null` (`Some(None)`) apart from a missing one (`None`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Whether an automation's script compiled when it was last loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompileStatus {
+    Ok,
+    Error,
+}
+
+impl std::str::FromStr for CompileStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ok" => Ok(Self::Ok),
+            "error" => Ok(Self::Error),
+            other => Err(format!("Unknown compile status: {}", other)),
+        }
+    }
+}
+
+/// Criteria for listing automations; unset criteria match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AutomationFilter {
+    /// Text matched against name, description and tags
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub q: Option<String>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub tag: Option<String>,
+    /// Matches the folder and everything below it
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub folder: Option<String>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub enabled: Option<bool>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub status: Option<CompileStatus>,
+    /// Entity id used by any block in the workspace
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub entity: Option<String>,
+}
+
+/// Form selects submit an empty string for "any".
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match Option::<String>::deserialize(deserializer)?
+        .as_deref()
+        .map(str::trim)
+    {
+        None | Some("") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Trim, drop empty and duplicate tags, keeping them sorted.
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let tags: BTreeSet<String> = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.into_iter().collect()
+}
+
+/// Strip surrounding and repeated slashes; an empty path means no folder.
+pub fn normalize_folder(folder: Option<String>) -> Option<String> {
+    let folder = folder?
+        .split('/')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    (!folder.is_empty()).then_some(folder)
+}
+
+/// What tells the entities an automation refers to apart from its other field values.
+#[derive(Debug, Default)]
+pub struct EntityIndex<'a> {
+    /// Names of the `field_entity` arguments, by block type
+    entity_fields: HashMap<String, HashSet<String>>,
+    /// Resolves blocks that act on every entity of an area
+    registries: Option<&'a Registries>,
+}
+
+impl<'a> EntityIndex<'a> {
+    pub fn new(blocks: &[BlockDefinition], registries: Option<&'a Registries>) -> Self {
+        let entity_fields = blocks
+            .iter()
+            .map(|block| {
+                let names = block
+                    .arguments()
+                    .filter(|arg| arg.r#type == "field_entity")
+                    .map(|arg| arg.name.clone())
+                    .collect();
+                (block.r#type.clone(), names)
+            })
+            .collect();
+        Self {
+            entity_fields,
+            registries,
+        }
+    }
+
+    fn is_entity_field(&self, block_type: &str, name: &str) -> bool {
+        // `entity_id` is what generated service blocks call their entity field
+        name == "ENTITY_ID"
+            || name == "entity_id"
+            || self
+                .entity_fields
+                .get(block_type)
+                .is_some_and(|names| names.contains(name))
+    }
+}
+
+/// Entity ids in the `entity_id` or `target.entity_id` of JSON service or event data.
+fn data_entities(data: &str, entities: &mut BTreeSet<String>) {
+    let Ok(data) = serde_json::from_str::<Value>(data) else {
+        return;
+    };
+    for entity_id in [&data["entity_id"], &data["target"]["entity_id"]] {
+        match entity_id {
+            Value::String(ids) => entities.extend(
+                ids.split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string),
+            ),
+            Value::Array(ids) => {
+                entities.extend(ids.iter().filter_map(Value::as_str).map(str::to_string))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Entities referenced by the blocks anywhere in a workspace.
+fn collect_entities(value: &Value, index: &EntityIndex, entities: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(fields) = object.get("fields").and_then(|f| f.as_object()) {
+                let block_type = object.get("type").and_then(Value::as_str).unwrap_or("");
+                let field = |name: &str| {
+                    fields
+                        .get(name)
+                        .and_then(|field| field.get("value").unwrap_or(field).as_str())
+                };
+                for name in fields.keys() {
+                    let Some(value) = field(name) else {
+                        continue;
+                    };
+                    if index.is_entity_field(block_type, name) {
+                        entities.insert(value.to_string());
+                    } else if name == "DATA" {
+                        data_entities(value, entities);
+                    }
+                }
+                if block_type == "ha_area_action" {
+                    let area =
+                        index
+                            .registries
+                            .zip(field("AREA"))
+                            .and_then(|(registries, area)| {
+                                Some((registries, registries.find_area(area)?))
+                            });
+                    if let Some((registries, area)) = area {
+                        let domain = field("DOMAIN").filter(|domain| !domain.is_empty());
+                        entities.extend(registries.area_entities(&area.area_id, domain));
+                    }
+                }
+            }
+            for child in object.values() {
+                collect_entities(child, index, entities);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_entities(item, index, entities);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Automation {
+    /// Entity ids referenced by the blocks of this automation.
+    pub fn referenced_entities(&self, index: &EntityIndex) -> BTreeSet<String> {
+        let mut entities = BTreeSet::new();
+        collect_entities(&self.workspace, index, &mut entities);
+        entities
+    }
+
+    pub fn matches(&self, filter: &AutomationFilter, index: &EntityIndex) -> bool {
+        if let Some(q) = &filter.q {
+            let q = q.to_lowercase();
+            let found = self.name.to_lowercase().contains(&q)
+                || self
+                    .description
+                    .as_ref()
+                    .is_some_and(|d| d.to_lowercase().contains(&q))
+                || self.tags.iter().any(|t| t.to_lowercase().contains(&q));
+            if !found {
+                return false;
+            }
+        }
+        if let Some(tag) = &filter.tag {
+            if !self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                return false;
+            }
+        }
+        if let Some(folder) = normalize_folder(filter.folder.clone()) {
+            let inside = self.folder.as_ref().is_some_and(|f| {
+                f == &folder
+                    || f.strip_prefix(&folder)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            });
+            if !inside {
+                return false;
+            }
+        }
+        if filter
+            .enabled
+            .is_some_and(|enabled| enabled != self.enabled)
+        {
+            return false;
+        }
+        if let Some(status) = filter.status {
+            let compiled = self.compilation_error.is_none();
+            if compiled != (status == CompileStatus::Ok) {
+                return false;
+            }
+        }
+        if let Some(entity) = &filter.entity {
+            if !self.referenced_entities(index).contains(entity) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Number of versions kept per automation unless configured otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 50;
+
 #[derive(Debug, Clone)]
 pub struct AutomationStore {
     automations: Arc<RwLock<HashMap<String, Automation>>>,
-    storage_path: PathBuf,
+    repository: Arc<dyn AutomationRepository>,
     code_generator: CodeGenerator,
     script_engine: ScriptEngine,
+    /// Maximum number of stored versions per automation, `None` keeps all
+    history_limit: Option<usize>,
 }
 
 impl AutomationStore {
@@ -76,7 +355,7 @@ impl AutomationStore {
         Self::with_storage_path(block_store, Self::default_storage_path()?).await
     }
 
-    fn default_storage_path() -> std::io::Result<PathBuf> {
+    pub fn default_storage_path() -> std::io::Result<PathBuf> {
         if cfg!(debug_assertions) {
             // In debug mode, use the project root directory
             let mut path = std::env::current_dir()?;
@@ -94,89 +373,204 @@ impl AutomationStore {
         block_store: crate::blocks::BlockStore,
         storage_path: PathBuf,
     ) -> std::io::Result<Self> {
-        // Ensure the storage directory exists
-        tracing::debug!("Creating storage directory: {:?}", storage_path);
-        fs::create_dir_all(&storage_path).await?;
+        let repository = YamlAutomationRepository::open(storage_path).await?;
+        Self::with_repository(block_store, Arc::new(repository)).await
+    }
 
+    pub async fn with_repository(
+        block_store: crate::blocks::BlockStore,
+        repository: Arc<dyn AutomationRepository>,
+    ) -> std::io::Result<Self> {
         let store = Self {
             automations: Arc::new(RwLock::new(HashMap::new())),
-            storage_path,
+            repository,
             code_generator: CodeGenerator::new(block_store),
             script_engine: ScriptEngine::new(),
+            history_limit: Some(DEFAULT_HISTORY_LIMIT),
         };
 
         // Load existing automations
         store.load_automations().await?;
+        store.verify_scripts().await?;
 
         Ok(store)
     }
 
+    pub fn with_history_limit(mut self, history_limit: Option<usize>) -> Self {
+        self.history_limit = history_limit;
+        self
+    }
+
+    /// Files that failed to load when the store was opened.
+    pub fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.repository.diagnostics()
+    }
+
     async fn load_automations(&self) -> std::io::Result<()> {
         let mut automations = self.automations.write().await;
+        for automation in self.repository.load().await? {
+            automations.insert(automation.id.clone(), automation);
+        }
+        Ok(())
+    }
 
-        let mut entries = fs::read_dir(&self.storage_path).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if path.extension().map_or(false, |ext| ext == "yaml") {
-                if let Ok(content) = fs::read_to_string(&path).await {
-                    if let Ok(automation) = serde_yaml::from_str::<Automation>(&content) {
-                        automations.insert(automation.id.clone(), automation);
-                    }
+    /// Recompile scripts that do not match their automation, e.g. after a crash between writes.
+    async fn verify_scripts(&self) -> std::io::Result<()> {
+        let mut automations = self.automations.write().await;
+
+        for automation in automations.values_mut() {
+            if self.repository.script_is_current(automation).await? {
+                continue;
+            }
+
+            tracing::warn!(
+                "Script for automation {} does not match version {}, recompiling",
+                automation.id,
+                automation.version
+            );
+            match self.compile_automation_script(automation).await {
+                Ok(()) => {}
+                Err(e @ AutomationError::Compile(_)) => {
+                    tracing::error!("{}", e);
+                    automation.compilation_error = Some(e.to_string());
                 }
+                Err(e) => return Err(e.into()),
             }
         }
 
-        Ok(())
+        // Forget scripts of automations that no longer exist
+        self.repository
+            .retain_scripts(&automations.keys().cloned().collect())
+            .await
     }
 
-    async fn save_automation(&self, automation: &mut Automation) -> std::io::Result<()> {
-        tracing::debug!("Saving automation to storage path: {:?}", self.storage_path);
-
+    async fn save_automation(&self, automation: &mut Automation) -> Result<(), AutomationError> {
         // First try to compile the Rhai script
-        match self.compile_automation_script(automation).await {
-            Ok(_) => {
-                automation.compilation_error = None;
+        if let Err(e) = self.compile_automation_script(automation).await {
+            tracing::error!("{}", e);
+            if let AutomationError::Compile(_) = e {
+                automation.compilation_error = Some(e.to_string());
             }
-            Err(e) => {
-                let error_msg = format!("Script compilation error: {}", e);
-                tracing::error!("{}", error_msg.clone());
-                automation.compilation_error = Some(error_msg.clone());
-                return Err(Error::new(std::io::ErrorKind::Other, error_msg));
+            return Err(e);
+        }
+        automation.compilation_error = None;
+
+        // If compilation succeeded, save the automation
+        self.repository.save(automation).await?;
+        Ok(self.prune_history(&automation.id).await?)
+    }
+
+    /// Drop the oldest versions beyond the history limit.
+    async fn prune_history(&self, id: &str) -> std::io::Result<()> {
+        if let Some(limit) = self.history_limit {
+            let versions = self.repository.versions(id).await?;
+            if versions.len() > limit {
+                for version in &versions[..versions.len() - limit] {
+                    self.repository.delete_version(id, *version).await?;
+                }
             }
         }
+        Ok(())
+    }
 
-        // If compilation succeeded, save the YAML
-        let yaml = serde_yaml::to_string(&automation)
-            .map_err(|e| Error::new(std::io::ErrorKind::Other, e))?;
+    /// All stored versions of an automation, newest first.
+    pub async fn versions(&self, id: &str) -> std::io::Result<Option<Vec<AutomationVersion>>> {
+        let Some(current) = self.get(id).await else {
+            return Ok(None);
+        };
 
-        let file_path = self.storage_path.join(format!("{}.yaml", automation.id));
-        tracing::debug!("Writing to file: {:?}", file_path);
-        fs::write(&file_path, yaml).await?;
+        let mut versions: Vec<_> = self
+            .repository
+            .history(id)
+            .await?
+            .iter()
+            .filter(|automation| automation.version != current.version)
+            .map(AutomationVersion::from)
+            .collect();
+        // Automations saved before history existed have no snapshot of their current version
+        versions.push(AutomationVersion::from(&current));
+        versions.reverse();
+
+        Ok(Some(versions))
+    }
 
-        Ok(())
+    pub async fn get_version(&self, id: &str, version: i32) -> std::io::Result<Option<Automation>> {
+        if let Some(current) = self.get(id).await {
+            if current.version == version {
+                return Ok(Some(current));
+            }
+        }
+        self.repository.get_version(id, version).await
+    }
+
+    pub async fn diff(
+        &self,
+        id: &str,
+        from: i32,
+        to: i32,
+    ) -> std::io::Result<Option<AutomationDiff>> {
+        match (
+            self.get_version(id, from).await?,
+            self.get_version(id, to).await?,
+        ) {
+            (Some(from), Some(to)) => Ok(Some(crate::history::diff(&from, &to))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Save the content of an earlier version as a new version.
+    pub async fn rollback(
+        &self,
+        id: &str,
+        version: i32,
+    ) -> Result<Option<Automation>, AutomationError> {
+        let (Some(current), Some(target)) =
+            (self.get(id).await, self.get_version(id, version).await?)
+        else {
+            return Ok(None);
+        };
+
+        // The workspace of an instance was rendered from the inputs of the same version,
+        // so they are restored together and the next propagation starts from them
+        self.save_update(
+            id,
+            AutomationUpdate {
+                name: target.name,
+                description: target.description,
+                enabled: current.enabled,
+                version: current.version,
+                triggers: target.triggers,
+                workspace: target.workspace,
+                conditions: target.conditions,
+                tags: Some(target.tags),
+                folder: Some(target.folder),
+            },
+            Some(target.blueprint),
+        )
+        .await
     }
 
-    pub async fn compile_automation_script(&self, automation: &Automation) -> std::io::Result<()> {
+    pub async fn compile_automation_script(
+        &self,
+        automation: &Automation,
+    ) -> Result<(), AutomationError> {
         // Generate Rhai code from the automation's workspace
         let context: HashMap<String, Value> = HashMap::new(); // TODO: Extract context from workspace
         let generated_code = self
             .code_generator
             .generate_code(&automation.workspace, &context)
             .await
-            .map_err(|e| Error::new(std::io::ErrorKind::Other, e))?;
+            .map_err(|e| AutomationError::Compile(CompileDiagnostic::generate(e)))?;
 
         // Validate the generated code compiles
-        self.script_engine.compile(&generated_code).map_err(|e| {
-            Error::new(
-                std::io::ErrorKind::Other,
-                format!("Script compilation error: {}", e),
-            )
-        })?;
-
-        // Save the compiled script
-        let script_path = self.storage_path.join(format!("{}.rhai", automation.id));
-        tracing::debug!("Writing Rhai script to: {:?}", script_path);
-        fs::write(script_path, generated_code).await
+        self.script_engine
+            .compile(&generated_code)
+            .map_err(|e| AutomationError::Compile(ScriptEngine::compile_diagnostic(&e)))?;
+
+        Ok(self
+            .repository
+            .save_script(automation, &generated_code)
+            .await?)
     }
 
     pub async fn list(&self) -> Vec<Automation> {
@@ -184,12 +578,75 @@ impl AutomationStore {
         automations.values().cloned().collect()
     }
 
+    /// Automations matching the filter, ordered by folder and name.
+    ///
+    /// With `registries`, blocks acting on an area match the entities in it.
+    pub async fn search(
+        &self,
+        filter: &AutomationFilter,
+        registries: Option<&Registries>,
+    ) -> Vec<Automation> {
+        let blocks = match filter.entity {
+            Some(_) => self.code_generator.block_store().list().await,
+            None => Vec::new(),
+        };
+        let index = EntityIndex::new(&blocks, registries);
+        let automations = self.automations.read().await;
+        let mut found: Vec<_> = automations
+            .values()
+            .filter(|a| a.matches(filter, &index))
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| {
+            (&a.folder, a.name.to_lowercase()).cmp(&(&b.folder, b.name.to_lowercase()))
+        });
+        found
+    }
+
+    /// Every tag in use, sorted.
+    pub async fn tags(&self) -> Vec<String> {
+        let automations = self.automations.read().await;
+        normalize_tags(
+            automations
+                .values()
+                .flat_map(|a| a.tags.iter().cloned())
+                .collect(),
+        )
+    }
+
+    /// Every folder in use, sorted.
+    pub async fn folders(&self) -> Vec<String> {
+        let automations = self.automations.read().await;
+        let folders: BTreeSet<String> = automations
+            .values()
+            .filter_map(|a| a.folder.clone())
+            .collect();
+        folders.into_iter().collect()
+    }
+
     pub async fn get(&self, id: &str) -> Option<Automation> {
         let automations = self.automations.read().await;
         automations.get(id).cloned()
     }
 
-    pub async fn create(&self, data: AutomationCreate) -> std::io::Result<Automation> {
+    pub async fn create(&self, data: AutomationCreate) -> Result<Automation, AutomationError> {
+        self.insert(data, None).await
+    }
+
+    /// Create an automation whose workspace was rendered from a blueprint.
+    pub async fn create_instance(
+        &self,
+        data: AutomationCreate,
+        instance: BlueprintInstance,
+    ) -> Result<Automation, AutomationError> {
+        self.insert(data, Some(instance)).await
+    }
+
+    async fn insert(
+        &self,
+        data: AutomationCreate,
+        blueprint: Option<BlueprintInstance>,
+    ) -> Result<Automation, AutomationError> {
         let now = Utc::now();
         let mut automation = Automation {
             id: Uuid::new_v4().to_string(),
@@ -203,6 +660,9 @@ impl AutomationStore {
             created_at: now,
             updated_at: now,
             compilation_error: None,
+            tags: normalize_tags(data.tags),
+            folder: normalize_folder(data.folder),
+            blueprint,
         };
 
         self.save_automation(&mut automation).await?;
@@ -213,20 +673,68 @@ impl AutomationStore {
         Ok(automation)
     }
 
+    /// Automations instantiated from the given blueprint, ordered by name.
+    pub async fn instances_of(&self, blueprint_id: &str) -> Vec<Automation> {
+        let automations = self.automations.read().await;
+        let mut instances: Vec<_> = automations
+            .values()
+            .filter(|a| {
+                a.blueprint
+                    .as_ref()
+                    .is_some_and(|b| b.blueprint_id == blueprint_id)
+            })
+            .cloned()
+            .collect();
+        instances.sort_by(|a, b| a.name.cmp(&b.name));
+        instances
+    }
+
+    /// Replace the workspace of a blueprint instance as a new version.
+    pub async fn apply_instance(
+        &self,
+        id: &str,
+        instance: BlueprintInstance,
+        workspace: Value,
+    ) -> Result<Option<Automation>, AutomationError> {
+        let mut automations = self.automations.write().await;
+
+        let Some(mut automation) = automations.get(id).cloned() else {
+            return Ok(None);
+        };
+        automation.version += 1;
+        automation.workspace = workspace;
+        automation.blueprint = Some(instance);
+        automation.updated_at = Utc::now();
+
+        self.save_automation(&mut automation).await?;
+        automations.insert(id.to_string(), automation.clone());
+        Ok(Some(automation))
+    }
+
     pub async fn update(
         &self,
         id: &str,
         data: AutomationUpdate,
-    ) -> std::io::Result<Option<Automation>> {
+    ) -> Result<Option<Automation>, AutomationError> {
+        self.save_update(id, data, None).await
+    }
+
+    /// Apply an update, replacing the blueprint reference when `blueprint` is given.
+    async fn save_update(
+        &self,
+        id: &str,
+        data: AutomationUpdate,
+        blueprint: Option<Option<BlueprintInstance>>,
+    ) -> Result<Option<Automation>, AutomationError> {
         let mut automations = self.automations.write().await;
 
         if let Some(existing) = automations.get(id) {
             // Version check
             if data.version != existing.version {
-                return Err(Error::new(
-                    std::io::ErrorKind::Other,
-                    "Version mismatch - automation has been modified",
-                ));
+                return Err(AutomationError::VersionConflict {
+                    expected: data.version,
+                    current: existing.version,
+                });
             }
 
             let mut updated = Automation {
@@ -241,6 +749,15 @@ impl AutomationStore {
                 created_at: existing.created_at,
                 updated_at: Utc::now(),
                 compilation_error: None,
+                tags: match data.tags {
+                    Some(tags) => normalize_tags(tags),
+                    None => existing.tags.clone(),
+                },
+                folder: match data.folder {
+                    Some(folder) => normalize_folder(folder),
+                    None => existing.folder.clone(),
+                },
+                blueprint: blueprint.unwrap_or_else(|| existing.blueprint.clone()),
             };
 
             self.save_automation(&mut updated).await?;
@@ -251,28 +768,22 @@ impl AutomationStore {
         }
     }
 
-    pub async fn delete(&self, id: &str) -> std::io::Result<bool> {
+    pub async fn delete(&self, id: &str) -> Result<bool, AutomationError> {
         let mut automations = self.automations.write().await;
         let was_present = automations.remove(id).is_some();
 
         if was_present {
-            // Delete YAML file
-            let yaml_path = self.storage_path.join(format!("{}.yaml", id));
-            if yaml_path.exists() {
-                fs::remove_file(&yaml_path).await?;
-            }
-
-            // Delete Rhai script
-            let rhai_path = self.storage_path.join(format!("{}.rhai", id));
-            if rhai_path.exists() {
-                fs::remove_file(&rhai_path).await?;
-            }
+            self.repository.delete(id).await?;
         }
 
         Ok(was_present)
     }
 
-    pub async fn toggle(&self, id: &str, enabled: bool) -> std::io::Result<Option<Automation>> {
+    pub async fn toggle(
+        &self,
+        id: &str,
+        enabled: bool,
+    ) -> Result<Option<Automation>, AutomationError> {
         let mut automations = self.automations.write().await;
 
         if let Some(mut automation) = automations.get(id).cloned() {
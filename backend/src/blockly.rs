@@ -1,5 +1,22 @@
 use crate::blocks::BlockDefinition;
+use crate::persist::write_atomic;
+use crate::storage::GitWorkTree;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::RwLock;
+
+/// Subdirectory of the blocks directory holding toolbox layouts.
+pub const TOOLBOX_DIR: &str = "toolbox";
+
+/// Layout used when no layout name is requested.
+pub const DEFAULT_LAYOUT: &str = "default";
+
+/// The shipped default layout, built in for installs without a `blocks` directory.
+const SHIPPED_DEFAULT_LAYOUT: &str = include_str!("../blocks/toolbox/default.yaml");
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolboxCategory {
@@ -66,616 +83,450 @@ impl BlocklyToolbox {
     }
 }
 
-pub fn create_default_toolbox(blocks: &[BlockDefinition]) -> BlocklyToolbox {
-    let mut toolbox = BlocklyToolbox::new();
-
-    // Add standard Blockly categories
-    toolbox.add_category(ToolboxCategory {
-        kind: "category".to_string(),
-        name: "Logic".to_string(),
-        categorystyle: Some("logic_category".to_string()),
-        colour: None,
-        custom: None,
-        contents: vec![
-            ToolboxItem::Block {
-                r#type: "controls_if".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "logic_compare".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "logic_operation".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "logic_negate".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "logic_boolean".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "logic_ternary".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-        ],
-    });
-
-    toolbox.add_category(ToolboxCategory {
-        kind: "category".to_string(),
-        name: "Loops".to_string(),
-        categorystyle: Some("loop_category".to_string()),
-        colour: None,
-        custom: None,
-        contents: vec![
-            ToolboxItem::Block {
-                r#type: "controls_repeat_ext".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "controls_whileUntil".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "controls_for".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "controls_forEach".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "controls_flow_statements".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-        ],
-    });
-
-    toolbox.add_category(ToolboxCategory {
-        kind: "category".to_string(),
-        name: "Math".to_string(),
-        categorystyle: Some("math_category".to_string()),
-        colour: None,
-        custom: None,
-        contents: vec![
-            ToolboxItem::Block {
-                r#type: "math_number".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "math_arithmetic".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "math_single".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "math_round".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "math_modulo".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "math_constrain".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "math_random_int".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "math_random_float".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-        ],
-    });
-
-    toolbox.add_category(ToolboxCategory {
-        kind: "category".to_string(),
-        name: "Lists".to_string(),
-        categorystyle: Some("list_category".to_string()),
-        colour: None,
-        custom: None,
-        contents: vec![
-            ToolboxItem::Block {
-                r#type: "lists_create_empty".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_create_with".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_repeat".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_length".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_isEmpty".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_indexOf".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_getIndex".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_setIndex".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_getSublist".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_sort".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "lists_reverse".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-        ],
-    });
-
-    toolbox.add_category(ToolboxCategory {
-        kind: "category".to_string(),
-        name: "Text".to_string(),
-        categorystyle: Some("text_category".to_string()),
-        colour: None,
-        custom: None,
-        contents: vec![
-            ToolboxItem::Block {
-                r#type: "text".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_join".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_append".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_length".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_isEmpty".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_indexOf".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_charAt".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_getSubstring".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_changeCase".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_trim".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_print".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_prompt_ext".to_string(),
-                disabled: Some(true),
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_prompt".to_string(),
-                disabled: Some(true),
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_count".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_replace".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "text_reverse".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-        ],
-    });
-
-    toolbox.add_category(ToolboxCategory {
-        kind: "category".to_string(),
-        name: "Variables".to_string(),
-        categorystyle: Some("variable_category".to_string()),
-        colour: None,
-        custom: None,
-        contents: vec![
-            ToolboxItem::Block {
-                r#type: "variables_get".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "variables_set".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "variables_get_dynamic".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "variables_set_dynamic".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-        ],
-    });
-
-    toolbox.add_category(ToolboxCategory {
-        kind: "category".to_string(),
-        name: "Procedures".to_string(),
-        categorystyle: Some("procedure_category".to_string()),
-        colour: None,
-        custom: None,
-        contents: vec![
-            ToolboxItem::Block {
-                r#type: "procedures_defreturn".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "procedures_defnoreturn".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "procedures_callreturn".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "procedures_callnoreturn".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "procedures_ifreturn".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "procedures_mutatorarg".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-            ToolboxItem::Block {
-                r#type: "procedures_mutatorcontainer".to_string(),
-                disabled: None,
-                gap: None,
-                fields: None,
-                inputs: None,
-                mutation: None,
-            },
-        ],
-    });
-
-    // Helper function to create block items
-    let create_block_item = |block: &BlockDefinition| ToolboxItem::Block {
+/// Persisted toolbox definition, rendered against the current block set.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ToolboxLayout {
+    #[serde(default)]
+    pub categories: Vec<LayoutCategory>,
+    /// Block types that never appear in the toolbox
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hidden: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct LayoutCategory {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub categorystyle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colour: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<String>,
+    #[serde(default)]
+    pub contents: Vec<ToolboxItem>,
+    /// Append all blocks whose `BlockDefinition.category` matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_category: Option<String>,
+    /// Append user-defined blocks not placed in any other category
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_user_blocks: bool,
+}
+
+fn block_item(block: &BlockDefinition) -> ToolboxItem {
+    ToolboxItem::Block {
         r#type: block.r#type.clone(),
         disabled: None,
         gap: None,
         fields: None,
         inputs: None,
         mutation: None,
-    };
-
-    // Filter blocks into categories
-    let (ha_blocks, user_blocks): (Vec<_>, Vec<_>) = blocks.iter().partition(|b| b.id.is_none());
-
-    // Add Home Assistant Triggers category
-    let trigger_blocks: Vec<_> = ha_blocks
-        .iter()
-        .filter(|b| b.r#type.contains("trigger"))
-        .map(|b| create_block_item(b))
-        .collect();
-
-    if !trigger_blocks.is_empty() {
-        toolbox.add_category(ToolboxCategory {
-            kind: "category".to_string(),
-            name: "Triggers".to_string(),
-            categorystyle: None,
-            colour: Some("#5b80a5".to_string()), // 230 hue -> blue
-            custom: None,
-            contents: trigger_blocks,
-        });
     }
+}
+
+impl ToolboxLayout {
+    /// Layout used when no default layout file exists: the shipped default, plus one
+    /// category for each block category it does not include.
+    pub fn fallback(blocks: &[BlockDefinition]) -> Self {
+        let mut layout: Self = serde_yaml::from_str(SHIPPED_DEFAULT_LAYOUT)
+            .expect("shipped default toolbox layout is valid");
+
+        let included: HashSet<&str> = layout
+            .categories
+            .iter()
+            .filter_map(|c| c.include_category.as_deref())
+            .collect();
+        let mut names: Vec<String> = blocks
+            .iter()
+            .filter(|b| b.id.is_none())
+            .filter_map(|b| b.category.clone())
+            .filter(|name| !included.contains(name.as_str()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        names.sort();
+
+        // Before the user blocks, which come last
+        let position = layout
+            .categories
+            .iter()
+            .position(|c| c.include_user_blocks)
+            .unwrap_or(layout.categories.len());
+        layout.categories.splice(
+            position..position,
+            names.into_iter().map(|name| LayoutCategory {
+                include_category: Some(name.clone()),
+                name,
+                ..Default::default()
+            }),
+        );
+        layout
+    }
+
+    pub fn render(&self, blocks: &[BlockDefinition]) -> BlocklyToolbox {
+        let hidden: HashSet<&str> = self.hidden.iter().map(String::as_str).collect();
+        let is_hidden = |item: &ToolboxItem| match item {
+            ToolboxItem::Block { r#type, .. } => hidden.contains(r#type.as_str()),
+            _ => false,
+        };
+
+        let mut blocks: Vec<&BlockDefinition> = blocks
+            .iter()
+            .filter(|b| !hidden.contains(b.r#type.as_str()))
+            .collect();
+        blocks.sort_by(|a, b| a.r#type.cmp(&b.r#type));
+
+        // Blocks placed explicitly are never pulled in a second time
+        let mut placed: HashSet<String> = self
+            .categories
+            .iter()
+            .flat_map(|c| c.contents.iter())
+            .filter_map(|item| match item {
+                ToolboxItem::Block { r#type, .. } => Some(r#type.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let mut contents: Vec<Vec<ToolboxItem>> = Vec::with_capacity(self.categories.len());
+        for category in &self.categories {
+            let mut items: Vec<ToolboxItem> = category
+                .contents
+                .iter()
+                .filter(|item| !is_hidden(item))
+                .cloned()
+                .collect();
+
+            if let Some(include) = &category.include_category {
+                for block in &blocks {
+                    if block.category.as_ref() == Some(include)
+                        && placed.insert(block.r#type.clone())
+                    {
+                        items.push(block_item(block));
+                    }
+                }
+            }
+
+            contents.push(items);
+        }
+
+        // User blocks go last so that category includes take precedence
+        for (category, items) in self.categories.iter().zip(contents.iter_mut()) {
+            if category.include_user_blocks {
+                for block in &blocks {
+                    if block.id.is_some() && placed.insert(block.r#type.clone()) {
+                        items.push(block_item(block));
+                    }
+                }
+            }
+        }
+
+        let mut toolbox = BlocklyToolbox::new();
+        for (category, items) in self.categories.iter().zip(contents) {
+            if items.is_empty() {
+                continue;
+            }
+            toolbox.add_category(ToolboxCategory {
+                kind: "category".to_string(),
+                name: category.name.clone(),
+                categorystyle: category.categorystyle.clone(),
+                colour: category.colour.clone(),
+                custom: category.custom.clone(),
+                contents: items,
+            });
+        }
+
+        toolbox
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolboxStore {
+    layouts: Arc<RwLock<HashMap<String, ToolboxLayout>>>,
+    layouts_dir: PathBuf,
+    /// Commits layout changes when the layouts live in a git repository
+    git: Option<GitWorkTree>,
+}
+
+impl ToolboxStore {
+    pub async fn new() -> std::io::Result<Self> {
+        Self::with_layouts_dir(PathBuf::from("blocks").join(TOOLBOX_DIR)).await
+    }
+
+    pub async fn with_layouts_dir(layouts_dir: PathBuf) -> std::io::Result<Self> {
+        fs::create_dir_all(&layouts_dir).await?;
+
+        let mut layouts = HashMap::new();
+        let mut entries = fs::read_dir(&layouts_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let (Some(name), Some(ext)) = (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|s| s.to_str()),
+            ) else {
+                continue;
+            };
+
+            let content = fs::read_to_string(&path).await?;
+            let parsed = match ext {
+                "yaml" | "yml" => {
+                    serde_yaml::from_str::<ToolboxLayout>(&content).map_err(|e| e.to_string())
+                }
+                "json" => {
+                    serde_json::from_str::<ToolboxLayout>(&content).map_err(|e| e.to_string())
+                }
+                _ => continue,
+            };
+
+            match parsed {
+                Ok(layout) => {
+                    tracing::info!("Loaded toolbox layout: {} from {}", name, path.display());
+                    layouts.insert(name.to_string(), layout);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to parse toolbox layout {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        Ok(Self {
+            layouts: Arc::new(RwLock::new(layouts)),
+            layouts_dir,
+            git: None,
+        })
+    }
+
+    pub fn with_git(mut self, git: GitWorkTree) -> Self {
+        self.git = Some(git);
+        self
+    }
+
+    fn layout_paths(&self, name: &str) -> Vec<PathBuf> {
+        ["yaml", "yml", "json"]
+            .iter()
+            .map(|ext| self.layouts_dir.join(format!("{}.{}", name, ext)))
+            .collect()
+    }
+
+    async fn commit(&self, paths: Vec<PathBuf>, message: String) -> std::io::Result<()> {
+        match &self.git {
+            Some(git) => git.commit(&paths, message).await,
+            None => Ok(()),
+        }
+    }
+
+    fn validate_name(name: &str) -> std::io::Result<()> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid toolbox layout name: {}", name),
+            ))
+        }
+    }
+
+    pub async fn list(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.layouts.read().await.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub async fn get(&self, name: &str) -> Option<ToolboxLayout> {
+        self.layouts.read().await.get(name).cloned()
+    }
+
+    /// The named layout, falling back to a generated one for the default layout.
+    pub async fn get_or_default(
+        &self,
+        name: &str,
+        blocks: &[BlockDefinition],
+    ) -> Option<ToolboxLayout> {
+        match self.get(name).await {
+            Some(layout) => Some(layout),
+            None if name == DEFAULT_LAYOUT => Some(ToolboxLayout::fallback(blocks)),
+            None => None,
+        }
+    }
+
+    /// Render the named layout, falling back to a generated one for the default layout.
+    pub async fn render(&self, name: &str, blocks: &[BlockDefinition]) -> Option<BlocklyToolbox> {
+        self.get_or_default(name, blocks)
+            .await
+            .map(|layout| layout.render(blocks))
+    }
+
+    pub async fn save(&self, name: &str, layout: ToolboxLayout) -> std::io::Result<()> {
+        Self::validate_name(name)?;
+
+        let yaml = serde_yaml::to_string(&layout).map_err(|e| {
+            Error::other(format!("Failed to serialize toolbox layout to YAML: {}", e))
+        })?;
+
+        let mut layouts = self.layouts.write().await;
+        let paths = self.layout_paths(name);
+        // Drop other representations so the saved YAML wins on the next load
+        for path in &paths[1..] {
+            if path.exists() {
+                fs::remove_file(path).await?;
+            }
+        }
+        write_atomic(&paths[0], yaml).await?;
+        let action = if layouts.insert(name.to_string(), layout).is_some() {
+            "Update"
+        } else {
+            "Create"
+        };
+
+        self.commit(paths, format!("{} toolbox layout '{}'", action, name))
+            .await
+    }
+
+    pub async fn delete(&self, name: &str) -> std::io::Result<bool> {
+        Self::validate_name(name)?;
+
+        let mut layouts = self.layouts.write().await;
+        if layouts.remove(name).is_none() {
+            return Ok(false);
+        }
+
+        let paths = self.layout_paths(name);
+        for path in &paths {
+            if path.exists() {
+                fs::remove_file(path).await?;
+            }
+        }
+
+        self.commit(paths, format!("Delete toolbox layout '{}'", name))
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Add Home Assistant Conditions category
-    let condition_blocks: Vec<_> = ha_blocks
-        .iter()
-        .filter(|b| b.r#type.contains("condition"))
-        .map(|b| create_block_item(b))
-        .collect();
-
-    if !condition_blocks.is_empty() {
-        toolbox.add_category(ToolboxCategory {
-            kind: "category".to_string(),
-            name: "Conditions".to_string(),
-            categorystyle: None,
-            colour: Some("#59a869".to_string()), // 120 hue -> green
-            custom: None,
-            contents: condition_blocks,
-        });
+    fn block(r#type: &str, category: Option<&str>, user: bool) -> BlockDefinition {
+        BlockDefinition {
+            r#type: r#type.to_string(),
+            category: category.map(str::to_string),
+            id: user.then(|| "user".to_string()),
+            ..Default::default()
+        }
     }
 
-    // Add Home Assistant Actions category
-    let action_blocks: Vec<_> = ha_blocks
-        .iter()
-        .filter(|b| !b.r#type.contains("trigger") && !b.r#type.contains("condition"))
-        .map(|b| create_block_item(b))
-        .collect();
-
-    if !action_blocks.is_empty() {
-        toolbox.add_category(ToolboxCategory {
-            kind: "category".to_string(),
-            name: "Actions".to_string(),
-            categorystyle: None,
-            colour: Some("#a5995b".to_string()), // 60 hue -> yellow
-            custom: None,
-            contents: action_blocks,
-        });
+    fn block_types(category: &ToolboxCategory) -> Vec<&str> {
+        category
+            .contents
+            .iter()
+            .filter_map(|item| match item {
+                ToolboxItem::Block { r#type, .. } => Some(r#type.as_str()),
+                _ => None,
+            })
+            .collect()
     }
 
-    // Add User Blocks category if any exist
-    if !user_blocks.is_empty() {
-        let user_block_items: Vec<_> = user_blocks.iter().map(|b| create_block_item(b)).collect();
-
-        toolbox.add_category(ToolboxCategory {
-            kind: "category".to_string(),
-            name: "User Blocks".to_string(),
-            categorystyle: Some("user_category".to_string()),
-            colour: None,
-            custom: None,
-            contents: user_block_items,
-        });
+    #[test]
+    fn test_layout_render() {
+        let layout: ToolboxLayout = serde_yaml::from_str(
+            r#"
+categories:
+  - name: Logic
+    categorystyle: logic_category
+    contents:
+      - kind: block
+        type: controls_if
+      - kind: sep
+      - kind: label
+        text: Custom
+      - kind: block
+        type: my_logic
+  - name: Triggers
+    include_category: Triggers
+  - name: Empty
+    include_category: Nothing
+  - name: Mine
+    include_user_blocks: true
+hidden:
+  - ha_time_trigger
+"#,
+        )
+        .unwrap();
+
+        let blocks = vec![
+            block("ha_state_trigger", Some("Triggers"), false),
+            block("ha_time_trigger", Some("Triggers"), false),
+            block("my_logic", None, true),
+            block("my_action", Some("Triggers"), true),
+            block("my_other", None, true),
+        ];
+
+        let toolbox = layout.render(&blocks);
+        let names: Vec<_> = toolbox.contents.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["Logic", "Triggers", "Mine"]);
+
+        assert_eq!(toolbox.contents[0].contents.len(), 4);
+        assert_eq!(
+            block_types(&toolbox.contents[1]),
+            vec!["ha_state_trigger", "my_action"]
+        );
+        assert_eq!(block_types(&toolbox.contents[2]), vec!["my_other"]);
     }
 
-    toolbox
+    #[tokio::test]
+    async fn test_layout_store_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = ToolboxStore::with_layouts_dir(temp_dir.path().to_path_buf())
+            .await
+            .unwrap();
+
+        // The shipped default layout is used until one is saved
+        let blocks = vec![
+            block("ha_state_trigger", Some("Triggers"), false),
+            block("weather_forecast", Some("Weather"), false),
+            block("my_action", None, true),
+        ];
+        let toolbox = store.render(DEFAULT_LAYOUT, &blocks).await.unwrap();
+        let names: Vec<_> = toolbox.contents.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names[0], "Logic");
+        assert_eq!(names[names.len() - 2..], ["Weather", "User Blocks"]);
+        let triggers = toolbox
+            .contents
+            .iter()
+            .find(|c| c.name == "Triggers")
+            .unwrap();
+        assert_eq!(block_types(triggers), vec!["ha_state_trigger"]);
+        assert!(store.render("family", &blocks).await.is_none());
+
+        let layout = ToolboxLayout {
+            categories: vec![LayoutCategory {
+                name: "Simple".to_string(),
+                include_category: Some("Triggers".to_string()),
+                ..Default::default()
+            }],
+            hidden: vec![],
+        };
+        store.save("family", layout).await.unwrap();
+        assert!(temp_dir.path().join("family.yaml").exists());
+        assert!(store
+            .save("../escape", ToolboxLayout::default())
+            .await
+            .is_err());
+
+        // Reload from disk
+        let store = ToolboxStore::with_layouts_dir(temp_dir.path().to_path_buf())
+            .await
+            .unwrap();
+        assert_eq!(store.list().await, vec!["family".to_string()]);
+        let toolbox = store.render("family", &blocks).await.unwrap();
+        assert_eq!(toolbox.contents[0].name, "Simple");
+        assert_eq!(toolbox.contents.len(), 1);
+
+        assert!(store.delete("family").await.unwrap());
+        assert!(!store.delete("family").await.unwrap());
+        assert!(!temp_dir.path().join("family.yaml").exists());
+    }
 }
@@ -1,14 +1,15 @@
+use crate::diagnostics::LoadDiagnostic;
+use crate::error::BlockError;
+use crate::storage::{BlockRepository, YamlBlockRepository};
 use chrono::{DateTime, Utc};
-use log::{error, info};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use walkdir::WalkDir;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct BlockArgument {
     pub r#type: String,
     pub name: String,
@@ -18,6 +19,16 @@ pub struct BlockArgument {
     pub options: Option<Vec<Vec<String>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default: Option<String>,
+    // Numeric constraints for field_number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precision: Option<f64>,
+    // Domain filter for field_entity pickers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -27,10 +38,9 @@ pub struct BlockDefinition {
     pub message0: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub args0: Option<Vec<BlockArgument>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub message1: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub args1: Option<Vec<BlockArgument>>,
+    /// Rows after the first, serialized as `message1`/`args1`, `message2`/`args2`, ...
+    #[serde(flatten, with = "message_rows")]
+    pub extra_rows: Vec<MessageRow>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub output: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -58,13 +68,184 @@ pub struct BlockDefinition {
     pub modified: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rhai_template: Option<String>,
+    /// Example cases checked against `rhai_template`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tests: Option<Vec<BlockTestCase>>,
+}
+
+/// An example rendering of a block, used to test its Rhai template.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BlockTestCase {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Field values by field name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: HashMap<String, Value>,
+    /// Rendered code for value and statement inputs, including `NEXT`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub inputs: HashMap<String, String>,
+    /// Expected rendered Rhai, compared ignoring trailing whitespace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    /// Expected value when the rendered script is evaluated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_result: Option<Value>,
+}
+
+/// An additional Blockly message row (`messageN` with its `argsN`).
+#[derive(Debug, Clone, Default)]
+pub struct MessageRow {
+    pub message: String,
+    pub args: Option<Vec<BlockArgument>>,
+}
+
+mod message_rows {
+    use super::{BlockArgument, MessageRow};
+    use serde::de::Error as _;
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde_json::Value;
+    use std::collections::{BTreeMap, HashMap};
+
+    pub fn serialize<S: Serializer>(rows: &[MessageRow], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for (index, row) in rows.iter().enumerate() {
+            map.serialize_entry(&format!("message{}", index + 1), &row.message)?;
+            if let Some(args) = &row.args {
+                map.serialize_entry(&format!("args{}", index + 1), args)?;
+            }
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<MessageRow>, D::Error> {
+        // Receives every key not claimed by another field; only messageN/argsN are kept
+        let extra = HashMap::<String, Value>::deserialize(deserializer)?;
+
+        let mut rows: BTreeMap<usize, MessageRow> = BTreeMap::new();
+        for (key, value) in extra {
+            if let Some(index) = key
+                .strip_prefix("message")
+                .and_then(|n| n.parse::<usize>().ok())
+            {
+                if index == 0 {
+                    continue;
+                }
+                let message = value
+                    .as_str()
+                    .ok_or_else(|| D::Error::custom(format!("{} must be a string", key)))?;
+                rows.entry(index).or_default().message = message.to_string();
+            } else if let Some(index) = key
+                .strip_prefix("args")
+                .and_then(|n| n.parse::<usize>().ok())
+            {
+                if index == 0 {
+                    continue;
+                }
+                let args: Vec<BlockArgument> = serde_json::from_value(value)
+                    .map_err(|e| D::Error::custom(format!("{}: {}", key, e)))?;
+                rows.entry(index).or_default().args = Some(args);
+            }
+        }
+
+        // Rows must be numbered contiguously from 1
+        if let Some((&last, _)) = rows.iter().next_back() {
+            if last != rows.len() {
+                return Err(D::Error::custom(format!(
+                    "message rows must be numbered contiguously, found message{} without all rows before it",
+                    last
+                )));
+            }
+        }
+
+        Ok(rows.into_values().collect())
+    }
+}
+
+/// Collect the `%N` placeholders referenced by a Blockly message.
+fn message_placeholders(message: &str) -> Vec<usize> {
+    let mut placeholders = Vec::new();
+    let mut chars = message.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        let mut digits = String::new();
+        while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+            digits.push(*d);
+            chars.next();
+        }
+        if let Ok(n) = digits.parse() {
+            placeholders.push(n);
+        }
+    }
+    placeholders
+}
+
+/// Block types and template names become file names, so they are limited to ASCII
+/// letters, digits and underscores.
+pub fn is_valid_block_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl BlockDefinition {
+    /// All message rows in order, starting with `message0`/`args0`.
+    pub fn rows(&self) -> Vec<(&str, &[BlockArgument])> {
+        let mut rows = vec![(self.message0.as_str(), self.args0.as_deref().unwrap_or(&[]))];
+        rows.extend(
+            self.extra_rows
+                .iter()
+                .map(|row| (row.message.as_str(), row.args.as_deref().unwrap_or(&[]))),
+        );
+        rows
+    }
+
+    /// Arguments of every message row.
+    pub fn arguments(&self) -> impl Iterator<Item = &BlockArgument> {
+        self.args0.iter().flatten().chain(
+            self.extra_rows
+                .iter()
+                .flat_map(|row| row.args.iter().flatten()),
+        )
+    }
+
+    /// Check the type name, and that each row's `%N` placeholders match its arguments
+    /// one to one. Categories are display names and may be anything.
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_valid_block_name(&self.r#type) {
+            return Err(format!(
+                "Invalid block type {:?}: only letters, digits and underscores are allowed",
+                self.r#type
+            ));
+        }
+        for (index, (message, args)) in self.rows().into_iter().enumerate() {
+            let mut placeholders = message_placeholders(message);
+            placeholders.sort_unstable();
+
+            let expected: Vec<usize> = (1..=args.len()).collect();
+            if placeholders != expected {
+                return Err(format!(
+                    "Block {}: message{} references {:?} but args{} has {} argument(s)",
+                    self.r#type,
+                    index,
+                    placeholders,
+                    index,
+                    args.len()
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct BlockStore {
     blocks: Arc<RwLock<HashMap<String, BlockDefinition>>>,
-    blocks_dir: PathBuf,
+    repository: Arc<dyn BlockRepository>,
     builtin_templates: Arc<RwLock<HashMap<String, String>>>,
+    imported_templates: Arc<std::sync::RwLock<HashSet<String>>>,
 }
 
 impl BlockStore {
@@ -73,101 +254,25 @@ impl BlockStore {
     }
 
     pub async fn with_blocks_dir(blocks_dir: PathBuf) -> Result<Self, std::io::Error> {
-        let mut blocks = HashMap::new();
-        let mut builtin_templates = HashMap::new();
-
-        // Load built-in Rhai templates
-        let builtin_dir = blocks_dir.join("builtin");
-        if builtin_dir.exists() {
-            for entry in WalkDir::new(&builtin_dir)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|e| e.ok())
-            {
-                if entry.path().extension().map_or(false, |ext| ext == "rhai") {
-                    match fs::read_to_string(entry.path()) {
-                        Ok(content) => {
-                            let block_type = entry
-                                .path()
-                                .file_stem()
-                                .unwrap()
-                                .to_string_lossy()
-                                .to_string();
-                            info!(
-                                "Loaded built-in template: {} from {}",
-                                block_type,
-                                entry.path().display()
-                            );
-                            builtin_templates.insert(block_type, content);
-                        }
-                        Err(e) => {
-                            error!("Failed to read {}: {}", entry.path().display(), e);
-                        }
-                    }
-                }
-            }
-        }
+        Self::with_repository(Arc::new(YamlBlockRepository::new(blocks_dir))).await
+    }
 
-        // Load custom block YAML files
-        for entry in WalkDir::new(&blocks_dir)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if entry
-                .path()
-                .extension()
-                .map_or(false, |ext| ext == "yaml" || ext == "yml")
-            {
-                match fs::read_to_string(entry.path()) {
-                    Ok(content) => match serde_yaml::from_str::<BlockDefinition>(&content) {
-                        Ok(block) => {
-                            info!(
-                                "Loaded block: {} from {}",
-                                block.r#type,
-                                entry.path().display()
-                            );
-                            blocks.insert(block.r#type.clone(), block);
-                        }
-                        Err(e) => {
-                            error!(
-                                "Failed to parse block from {}: {}",
-                                entry.path().display(),
-                                e
-                            );
-                        }
-                    },
-                    Err(e) => {
-                        error!("Failed to read {}: {}", entry.path().display(), e);
-                    }
-                }
-            }
-        }
+    pub async fn with_repository(
+        repository: Arc<dyn BlockRepository>,
+    ) -> Result<Self, std::io::Error> {
+        let stored = repository.load().await?;
 
         Ok(Self {
-            blocks: Arc::new(RwLock::new(blocks)),
-            blocks_dir,
-            builtin_templates: Arc::new(RwLock::new(builtin_templates)),
+            blocks: Arc::new(RwLock::new(stored.blocks)),
+            repository,
+            builtin_templates: Arc::new(RwLock::new(stored.templates)),
+            imported_templates: Arc::new(std::sync::RwLock::new(stored.imported_templates)),
         })
     }
 
-    async fn save_block_to_yaml(&self, block: &BlockDefinition) -> Result<(), std::io::Error> {
-        let category_dir = match &block.category {
-            Some(category) => self.blocks_dir.join(category.to_lowercase()),
-            None => self.blocks_dir.join("custom"),
-        };
-
-        fs::create_dir_all(&category_dir)?;
-
-        let file_path = category_dir.join(format!("{}.yaml", block.r#type));
-        let yaml = serde_yaml::to_string(&block).map_err(|e| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to serialize block to YAML: {}", e),
-            )
-        })?;
-
-        fs::write(file_path, yaml)
+    /// Files that failed to load when the store was opened.
+    pub fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.repository.diagnostics()
     }
 
     pub async fn list(&self) -> Vec<BlockDefinition> {
@@ -199,7 +304,48 @@ impl BlockStore {
         None
     }
 
-    pub async fn create_or_update(&self, mut block: BlockDefinition) -> Result<(), std::io::Error> {
+    /// Whether a block or built-in template with this type exists.
+    pub async fn contains(&self, block_type: &str) -> bool {
+        self.blocks.read().await.contains_key(block_type)
+            || self.builtin_templates.read().await.contains_key(block_type)
+    }
+
+    pub async fn builtin_template(&self, block_type: &str) -> Option<String> {
+        self.builtin_templates.read().await.get(block_type).cloned()
+    }
+
+    /// Whether a built-in template was stored by `save_builtin_template`.
+    pub fn is_imported_template(&self, block_type: &str) -> bool {
+        self.imported_templates.read().unwrap().contains(block_type)
+    }
+
+    /// Store a built-in Rhai template, e.g. one imported from a block pack.
+    pub async fn save_builtin_template(
+        &self,
+        block_type: &str,
+        template: &str,
+    ) -> Result<(), BlockError> {
+        if !is_valid_block_name(block_type) {
+            return Err(BlockError::Invalid(format!(
+                "Invalid template name {:?}",
+                block_type
+            )));
+        }
+        self.repository.save_template(block_type, template).await?;
+
+        self.imported_templates
+            .write()
+            .unwrap()
+            .insert(block_type.to_string());
+        let mut builtin_templates = self.builtin_templates.write().await;
+        builtin_templates.insert(block_type.to_string(), template.to_string());
+
+        Ok(())
+    }
+
+    pub async fn create_or_update(&self, mut block: BlockDefinition) -> Result<(), BlockError> {
+        block.validate().map_err(BlockError::Invalid)?;
+
         let now = Utc::now();
 
         // Handle user-defined block metadata
@@ -209,8 +355,7 @@ impl BlockStore {
         }
         block.modified = Some(now);
 
-        // Save to YAML file
-        self.save_block_to_yaml(&block).await?;
+        self.repository.save_block(&block).await?;
 
         // Update in-memory store
         let mut blocks = self.blocks.write().await;
@@ -219,18 +364,10 @@ impl BlockStore {
         Ok(())
     }
 
-    pub async fn delete(&self, block_type: &str) -> Result<bool, std::io::Error> {
+    pub async fn delete(&self, block_type: &str) -> Result<bool, BlockError> {
         let mut blocks = self.blocks.write().await;
         if let Some(block) = blocks.remove(block_type) {
-            // Remove YAML file
-            let category_dir = match &block.category {
-                Some(category) => self.blocks_dir.join(category.to_lowercase()),
-                None => self.blocks_dir.join("custom"),
-            };
-            let file_path = category_dir.join(format!("{}.yaml", block_type));
-            if file_path.exists() {
-                fs::remove_file(file_path)?;
-            }
+            self.repository.delete_block(&block).await?;
             Ok(true)
         } else {
             Ok(false)
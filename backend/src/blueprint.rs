@@ -0,0 +1,461 @@
+use crate::automation::{Automation, AutomationCreate, AutomationStore};
+use crate::diagnostics::LoadDiagnostic;
+use crate::error::{AutomationError, BlueprintError};
+use crate::selectors::Selector;
+use crate::storage::{BlueprintRepository, YamlBlueprintRepository};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Prefix of workspace values that are replaced by an input, as in `!input motion_sensor`.
+pub const INPUT_TAG: &str = "!input ";
+
+/// A value an instance has to provide, such as the entity to watch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlueprintInput {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Home Assistant selector, e.g. `{"entity": {"domain": "light"}}`
+    pub selector: Value,
+    /// Inputs without a default are required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+}
+
+impl BlueprintInput {
+    fn selector(&self) -> Selector {
+        Selector::from_value(&self.selector).unwrap_or(Selector::Text)
+    }
+}
+
+/// A workspace with `!input` placeholders shared by several automations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blueprint {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub inputs: Vec<BlueprintInput>,
+    pub workspace: Value,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlueprintCreate {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub inputs: Vec<BlueprintInput>,
+    pub workspace: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlueprintUpdate {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub inputs: Vec<BlueprintInput>,
+    pub workspace: Value,
+    pub version: i32,
+}
+
+/// Reference from an automation to the blueprint it was rendered from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlueprintInstance {
+    pub blueprint_id: String,
+    /// Blueprint version the workspace was last rendered from
+    pub blueprint_version: i32,
+    pub inputs: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InstantiateRequest {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub inputs: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub folder: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstanceFailure {
+    pub automation_id: String,
+    pub name: String,
+    pub error: String,
+}
+
+/// Outcome of re-rendering the instances of a changed blueprint.
+#[derive(Debug, Default, Serialize)]
+pub struct PropagationReport {
+    pub updated: Vec<String>,
+    /// Instances that keep their previous workspace
+    pub failed: Vec<InstanceFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlueprintChange {
+    pub blueprint: Blueprint,
+    pub instances: PropagationReport,
+}
+
+fn placeholder(value: &str) -> Option<&str> {
+    value.strip_prefix(INPUT_TAG).map(str::trim)
+}
+
+fn collect_placeholders<'a>(value: &'a Value, names: &mut BTreeSet<&'a str>) {
+    match value {
+        Value::String(s) => names.extend(placeholder(s)),
+        Value::Array(items) => items.iter().for_each(|v| collect_placeholders(v, names)),
+        Value::Object(object) => object.values().for_each(|v| collect_placeholders(v, names)),
+        _ => {}
+    }
+}
+
+fn substitute(value: &mut Value, inputs: &BTreeMap<String, Value>) {
+    match value {
+        Value::String(s) => {
+            if let Some(input) = placeholder(s).and_then(|name| inputs.get(name)) {
+                *value = input.clone();
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| substitute(v, inputs)),
+        Value::Object(object) => object.values_mut().for_each(|v| substitute(v, inputs)),
+        _ => {}
+    }
+}
+
+impl Blueprint {
+    /// Check that inputs are unique, defaults fit their selectors and every placeholder is declared.
+    pub fn validate(&self) -> Result<(), BlueprintError> {
+        let mut declared = BTreeSet::new();
+        for input in &self.inputs {
+            if input.name.trim().is_empty() {
+                return Err(BlueprintError::Invalid("Input name is empty".to_string()));
+            }
+            if !declared.insert(input.name.as_str()) {
+                return Err(BlueprintError::Invalid(format!(
+                    "Input {} is declared twice",
+                    input.name
+                )));
+            }
+            if let Some(default) = &input.default {
+                input.selector().field_value(default).map_err(|message| {
+                    BlueprintError::Invalid(format!(
+                        "Default of input {} is invalid: {}",
+                        input.name, message
+                    ))
+                })?;
+            }
+        }
+
+        let mut used = BTreeSet::new();
+        collect_placeholders(&self.workspace, &mut used);
+        if let Some(undeclared) = used.difference(&declared).next() {
+            return Err(BlueprintError::Invalid(format!(
+                "Workspace uses undeclared input {}",
+                undeclared
+            )));
+        }
+        Ok(())
+    }
+
+    /// Workspace with every placeholder replaced by the given or default input value.
+    pub fn render(&self, inputs: &BTreeMap<String, Value>) -> Result<Value, BlueprintError> {
+        if let Some(unknown) = inputs
+            .keys()
+            .find(|name| !self.inputs.iter().any(|i| &i.name == *name))
+        {
+            return Err(BlueprintError::InvalidInput {
+                input: unknown.clone(),
+                message: "not declared by the blueprint".to_string(),
+            });
+        }
+
+        let mut values = BTreeMap::new();
+        for input in &self.inputs {
+            let value = inputs
+                .get(&input.name)
+                .or(input.default.as_ref())
+                .ok_or_else(|| BlueprintError::InvalidInput {
+                    input: input.name.clone(),
+                    message: "a value is required".to_string(),
+                })?;
+            let value = input.selector().field_value(value).map_err(|message| {
+                BlueprintError::InvalidInput {
+                    input: input.name.clone(),
+                    message,
+                }
+            })?;
+            values.insert(input.name.clone(), value);
+        }
+
+        let mut workspace = self.workspace.clone();
+        substitute(&mut workspace, &values);
+        Ok(workspace)
+    }
+
+    fn instance(&self, inputs: BTreeMap<String, Value>) -> BlueprintInstance {
+        BlueprintInstance {
+            blueprint_id: self.id.clone(),
+            blueprint_version: self.version,
+            inputs,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlueprintStore {
+    blueprints: Arc<RwLock<HashMap<String, Blueprint>>>,
+    repository: Arc<dyn BlueprintRepository>,
+}
+
+impl BlueprintStore {
+    /// Blueprints are kept next to the automations directory.
+    pub async fn new() -> std::io::Result<Self> {
+        Self::with_blueprints_dir(Self::default_blueprints_dir()?).await
+    }
+
+    pub fn default_blueprints_dir() -> std::io::Result<PathBuf> {
+        Ok(AutomationStore::default_storage_path()?.with_file_name("blueprints"))
+    }
+
+    pub async fn with_blueprints_dir(blueprints_dir: PathBuf) -> std::io::Result<Self> {
+        Self::with_repository(Arc::new(
+            YamlBlueprintRepository::open(blueprints_dir).await?,
+        ))
+        .await
+    }
+
+    pub async fn with_repository(
+        repository: Arc<dyn BlueprintRepository>,
+    ) -> std::io::Result<Self> {
+        let blueprints = repository
+            .load()
+            .await?
+            .into_iter()
+            .map(|blueprint| (blueprint.id.clone(), blueprint))
+            .collect();
+        Ok(Self {
+            blueprints: Arc::new(RwLock::new(blueprints)),
+            repository,
+        })
+    }
+
+    /// Blueprints that failed to load.
+    pub fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.repository.diagnostics()
+    }
+
+    pub async fn list(&self) -> Vec<Blueprint> {
+        let mut blueprints: Vec<_> = self.blueprints.read().await.values().cloned().collect();
+        blueprints.sort_by(|a, b| a.name.cmp(&b.name));
+        blueprints
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Blueprint> {
+        self.blueprints.read().await.get(id).cloned()
+    }
+
+    pub async fn create(&self, data: BlueprintCreate) -> Result<Blueprint, BlueprintError> {
+        let now = Utc::now();
+        let blueprint = Blueprint {
+            id: Uuid::new_v4().to_string(),
+            name: data.name,
+            description: data.description,
+            inputs: data.inputs,
+            workspace: data.workspace,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+        };
+        blueprint.validate()?;
+
+        let mut blueprints = self.blueprints.write().await;
+        self.repository.save(&blueprint).await?;
+        blueprints.insert(blueprint.id.clone(), blueprint.clone());
+        Ok(blueprint)
+    }
+
+    pub async fn update(
+        &self,
+        id: &str,
+        data: BlueprintUpdate,
+    ) -> Result<Blueprint, BlueprintError> {
+        let mut blueprints = self.blueprints.write().await;
+        let existing = blueprints
+            .get(id)
+            .ok_or_else(|| BlueprintError::NotFound(id.to_string()))?;
+        if data.version != existing.version {
+            return Err(BlueprintError::VersionConflict {
+                expected: data.version,
+                current: existing.version,
+            });
+        }
+
+        let blueprint = Blueprint {
+            id: id.to_string(),
+            name: data.name,
+            description: data.description,
+            inputs: data.inputs,
+            workspace: data.workspace,
+            version: existing.version + 1,
+            created_at: existing.created_at,
+            updated_at: Utc::now(),
+        };
+        blueprint.validate()?;
+
+        self.repository.save(&blueprint).await?;
+        blueprints.insert(id.to_string(), blueprint.clone());
+        Ok(blueprint)
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), BlueprintError> {
+        let mut blueprints = self.blueprints.write().await;
+        let blueprint = blueprints
+            .get(id)
+            .ok_or_else(|| BlueprintError::NotFound(id.to_string()))?;
+        self.repository.delete(blueprint).await?;
+        blueprints.remove(id);
+        Ok(())
+    }
+}
+
+/// Create an automation from a blueprint with concrete input values.
+pub async fn instantiate(
+    blueprints: &BlueprintStore,
+    automations: &AutomationStore,
+    blueprint_id: &str,
+    request: InstantiateRequest,
+) -> Result<Automation, BlueprintError> {
+    let blueprint = blueprints
+        .get(blueprint_id)
+        .await
+        .ok_or_else(|| BlueprintError::NotFound(blueprint_id.to_string()))?;
+    let workspace = blueprint.render(&request.inputs)?;
+
+    Ok(automations
+        .create_instance(
+            AutomationCreate {
+                name: request.name,
+                description: request.description,
+                triggers: vec![],
+                workspace,
+                conditions: vec![],
+                tags: request.tags,
+                folder: request.folder,
+            },
+            blueprint.instance(request.inputs),
+        )
+        .await?)
+}
+
+/// Change the input values of a blueprint instance and re-render its workspace.
+pub async fn set_inputs(
+    blueprints: &BlueprintStore,
+    automations: &AutomationStore,
+    automation_id: &str,
+    inputs: BTreeMap<String, Value>,
+) -> Result<Automation, BlueprintError> {
+    let automation = automations
+        .get(automation_id)
+        .await
+        .ok_or_else(|| AutomationError::NotFound(automation_id.to_string()))?;
+    let instance = automation
+        .blueprint
+        .ok_or_else(|| BlueprintError::NotAnInstance(automation_id.to_string()))?;
+    let blueprint = blueprints
+        .get(&instance.blueprint_id)
+        .await
+        .ok_or(BlueprintError::NotFound(instance.blueprint_id))?;
+
+    let workspace = blueprint.render(&inputs)?;
+    automations
+        .apply_instance(automation_id, blueprint.instance(inputs), workspace)
+        .await?
+        .ok_or_else(|| AutomationError::NotFound(automation_id.to_string()).into())
+}
+
+/// Re-render and recompile every instance of a blueprint after it changed.
+pub async fn propagate(automations: &AutomationStore, blueprint: &Blueprint) -> PropagationReport {
+    let mut report = PropagationReport::default();
+
+    for automation in automations.instances_of(&blueprint.id).await {
+        let Some(instance) = automation.blueprint else {
+            continue;
+        };
+        let result = match blueprint.render(&instance.inputs) {
+            Ok(workspace) => automations
+                .apply_instance(
+                    &automation.id,
+                    blueprint.instance(instance.inputs),
+                    workspace,
+                )
+                .await
+                .map_err(BlueprintError::from),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(_) => report.updated.push(automation.id),
+            Err(e) => {
+                tracing::warn!(
+                    "Blueprint {} could not be applied to automation {}: {}",
+                    blueprint.name,
+                    automation.name,
+                    e
+                );
+                report.failed.push(InstanceFailure {
+                    automation_id: automation.id,
+                    name: automation.name,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    report
+}
+
+/// Save a blueprint and carry the change over to all of its instances.
+pub async fn update(
+    blueprints: &BlueprintStore,
+    automations: &AutomationStore,
+    id: &str,
+    data: BlueprintUpdate,
+) -> Result<BlueprintChange, BlueprintError> {
+    let blueprint = blueprints.update(id, data).await?;
+    let instances = propagate(automations, &blueprint).await;
+    Ok(BlueprintChange {
+        blueprint,
+        instances,
+    })
+}
+
+/// Delete a blueprint that no automation is instantiated from.
+pub async fn delete(
+    blueprints: &BlueprintStore,
+    automations: &AutomationStore,
+    id: &str,
+) -> Result<(), BlueprintError> {
+    let instances = automations.instances_of(id).await;
+    if !instances.is_empty() {
+        return Err(BlueprintError::InUse(
+            instances.into_iter().map(|a| a.name).collect(),
+        ));
+    }
+    blueprints.remove(id).await
+}
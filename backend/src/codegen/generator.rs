@@ -1,5 +1,7 @@
 use crate::blocks::BlockStore;
-use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext};
+use handlebars::{
+    Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderErrorReason,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -20,11 +22,14 @@ impl CodeGenerator {
     pub fn new(block_store: BlockStore) -> Self {
         let mut handlebars = Handlebars::new();
         handlebars.set_strict_mode(true);
+        // The output is Rhai, and nested block code must be inserted verbatim
+        handlebars.register_escape_fn(handlebars::no_escape);
 
         // Register helpers for our template syntax
         handlebars.register_helper("switch", Box::new(Self::switch_helper));
         handlebars.register_helper("case", Box::new(Self::case_helper));
         handlebars.register_helper("each", Box::new(Self::each_helper));
+        handlebars.register_helper("rhai_string", Box::new(Self::rhai_string_helper));
 
         Self {
             handlebars,
@@ -68,6 +73,27 @@ impl CodeGenerator {
         Ok(())
     }
 
+    /// `{{rhai_string FIELD}}`: the field as a double-quoted Rhai string literal, for
+    /// free text such as JSON or templates that may contain quotes, backticks or `${`.
+    fn rhai_string_helper(
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let value = h
+            .param(0)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("rhai_string", 0))?
+            .value();
+        let text = match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        out.write(&rhai_string_literal(&text))?;
+        Ok(())
+    }
+
     pub async fn generate_code(
         &self,
         workspace: &Value,
@@ -79,16 +105,23 @@ impl CodeGenerator {
             .and_then(|b| b.get("blocks"))
             .and_then(|b| b.get("blocks"))
             .and_then(|b| b.as_array())
+            // Blockly's own serialization format
+            .or_else(|| {
+                workspace
+                    .get("blocks")
+                    .and_then(|b| b.get("blocks"))
+                    .and_then(|b| b.as_array())
+            })
             // Fallback to flat structure (old format)
             .or_else(|| workspace.get("blocks").and_then(|b| b.as_array()))
             .ok_or_else(|| "No blocks found in workspace".to_string())?;
 
-        if blocks.is_empty() {
-            return Ok(String::new());
+        // Each top-level stack, e.g. one per trigger, becomes its own section of the script
+        let mut sections = Vec::new();
+        for block in blocks {
+            sections.push(self.generate_block_code(block, context).await?);
         }
-
-        // Start with the first block
-        self.generate_block_code(&blocks[0], context).await
+        Ok(sections.join("\n"))
     }
 
     fn generate_block_code<'a>(
@@ -111,22 +144,21 @@ impl CodeGenerator {
 
             let template = block_def
                 .rhai_template
+                .clone()
                 .ok_or_else(|| format!("No Rhai template found for block type: {}", block_type))?;
 
             // Initialize field values with empty strings for all expected inputs
             let mut field_values = HashMap::new();
-            if let Some(args) = &block_def.args0 {
-                for arg in args {
-                    field_values.insert(arg.name.clone(), Value::String(String::new()));
-                }
+            for arg in block_def.arguments() {
+                field_values.insert(arg.name.clone(), Value::String(String::new()));
             }
 
             // Extract field values from the block
             if let Some(fields) = block.get("fields").and_then(|f| f.as_object()) {
                 for (key, value) in fields {
-                    if let Some(field_value) = value.get("value") {
-                        field_values.insert(key.clone(), field_value.clone());
-                    }
+                    // Blockly saves plain values, older workspaces wrap them in `{"value": ...}`
+                    let field_value = value.get("value").unwrap_or(value);
+                    field_values.insert(key.clone(), field_value.clone());
                 }
             }
 
@@ -183,11 +215,42 @@ impl CodeGenerator {
             }
 
             // Render the template with the field values
-            let rendered = self
-                .handlebars
-                .render_template(&template, &field_values)
-                .map_err(|e| format!("Template rendering error: {}", e))?;
-            Ok(rendered)
+            self.render_template(&template, &field_values)
         })
     }
+
+    pub fn render_template(
+        &self,
+        template: &str,
+        values: &HashMap<String, Value>,
+    ) -> Result<String, String> {
+        self.handlebars
+            .render_template(template, values)
+            .map_err(|e| format!("Template rendering error: {}", e))
+    }
+
+    pub fn block_store(&self) -> &BlockStore {
+        &self.block_store
+    }
+}
+
+/// Quote `text` as a Rhai string literal.
+///
+/// Like a JSON string, except that Rhai has no `\b` and `\f` escapes.
+pub fn rhai_string_literal(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_control() => literal.push_str(&format!("\\u{:04x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
 }
@@ -1,5 +1,6 @@
 pub mod generator;
 pub mod template;
+pub mod testing;
 
 pub use generator::*;
 pub use template::*;
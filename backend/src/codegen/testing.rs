@@ -0,0 +1,124 @@
+use super::generator::CodeGenerator;
+use crate::blocks::{BlockDefinition, BlockTestCase};
+use crate::rhai::engine::ScriptEngine;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Outcome of a single example case embedded in a block definition.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockTestOutcome {
+    pub block_type: String,
+    pub name: String,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rendered: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CodeGenerator {
+    /// Run the example cases of one block, or of every block when `block_type` is `None`.
+    pub async fn run_block_tests(&self, block_type: Option<&str>) -> Vec<BlockTestOutcome> {
+        let mut blocks = match block_type {
+            Some(block_type) => self
+                .block_store()
+                .get(block_type)
+                .await
+                .into_iter()
+                .collect(),
+            None => self.block_store().list().await,
+        };
+        blocks.sort_by(|a, b| a.r#type.cmp(&b.r#type));
+
+        let engine = ScriptEngine::new();
+        let mut outcomes = Vec::new();
+        for block in &blocks {
+            for (index, case) in block.tests.iter().flatten().enumerate() {
+                outcomes.push(self.run_block_test(&engine, block, index, case));
+            }
+        }
+        outcomes
+    }
+
+    fn run_block_test(
+        &self,
+        engine: &ScriptEngine,
+        block: &BlockDefinition,
+        index: usize,
+        case: &BlockTestCase,
+    ) -> BlockTestOutcome {
+        let mut outcome = BlockTestOutcome {
+            block_type: block.r#type.clone(),
+            name: case.name.clone().unwrap_or_else(|| format!("#{}", index)),
+            passed: false,
+            rendered: None,
+            result: None,
+            error: None,
+        };
+
+        let Some(template) = &block.rhai_template else {
+            outcome.error = Some("Block has no Rhai template".to_string());
+            return outcome;
+        };
+
+        // Seed values the same way the generator does before applying the case
+        let mut values: HashMap<String, Value> = block
+            .arguments()
+            .map(|arg| (arg.name.clone(), Value::String(String::new())))
+            .collect();
+        values.extend(case.fields.clone());
+        values.extend(
+            case.inputs
+                .iter()
+                .map(|(key, code)| (key.clone(), Value::String(code.clone()))),
+        );
+
+        let rendered = match self.render_template(template, &values) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                outcome.error = Some(e);
+                return outcome;
+            }
+        };
+        outcome.rendered = Some(rendered.clone());
+
+        if let Some(expected) = &case.expected {
+            if expected.trim_end() != rendered.trim_end() {
+                outcome.error = Some(format!(
+                    "Rendered code does not match expected:\n{}",
+                    expected.trim_end()
+                ));
+                return outcome;
+            }
+        }
+
+        if let Some(expected_result) = &case.expected_result {
+            let result = engine
+                .eval_script(&rendered)
+                .map_err(|e| e.to_string())
+                .and_then(|d| rhai::serde::from_dynamic::<Value>(&d).map_err(|e| e.to_string()));
+            match result {
+                Ok(result) => {
+                    outcome.result = Some(result.clone());
+                    if &result != expected_result {
+                        outcome.error = Some(format!(
+                            "Expected result {} but got {}",
+                            expected_result, result
+                        ));
+                        return outcome;
+                    }
+                }
+                Err(e) => {
+                    outcome.error = Some(e);
+                    return outcome;
+                }
+            }
+        }
+
+        outcome.passed = true;
+        outcome
+    }
+}
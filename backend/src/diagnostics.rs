@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Subdirectory that broken files are moved to so they are neither loaded nor overwritten.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticSource {
+    Automation,
+    Block,
+    BuiltinTemplate,
+    Blueprint,
+}
+
+/// A file that could not be loaded, or loaded with problems.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadDiagnostic {
+    pub source: DiagnosticSource,
+    pub path: String,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    /// Where the file was moved to; `None` when it was still loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantined: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl LoadDiagnostic {
+    pub fn new(source: DiagnosticSource, path: &Path, error: impl ToString) -> Self {
+        Self {
+            source,
+            path: path.display().to_string(),
+            error: error.to_string(),
+            line: None,
+            column: None,
+            quarantined: None,
+            recorded_at: Utc::now(),
+        }
+    }
+
+    /// Diagnostic for a YAML parse error, keeping its position in the file.
+    pub fn from_yaml_error(
+        source: DiagnosticSource,
+        path: &Path,
+        error: &serde_yaml::Error,
+    ) -> Self {
+        let mut diagnostic = Self::new(source, path, error);
+        if let Some(location) = error.location() {
+            diagnostic.line = Some(location.line());
+            diagnostic.column = Some(location.column());
+        }
+        diagnostic
+    }
+}
+
+/// Load problems collected by a store, shared between its clones.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    entries: Arc<RwLock<Vec<LoadDiagnostic>>>,
+}
+
+impl Diagnostics {
+    pub fn record(&self, diagnostic: LoadDiagnostic) {
+        tracing::error!(
+            "Problem loading {}: {}{}",
+            diagnostic.path,
+            diagnostic.error,
+            diagnostic
+                .quarantined
+                .as_ref()
+                .map(|q| format!(" (moved to {})", q))
+                .unwrap_or_default()
+        );
+        self.entries.write().unwrap().push(diagnostic);
+    }
+
+    pub fn list(&self) -> Vec<LoadDiagnostic> {
+        self.entries.read().unwrap().clone()
+    }
+}
+
+/// Path inside `root/quarantine/` for a broken file, timestamped so repeated failures are kept.
+fn quarantine_path(root: &Path, path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    root.join(QUARANTINE_DIR).join(format!(
+        "{}.{}",
+        file_name,
+        Utc::now().format("%Y%m%dT%H%M%S%.3f")
+    ))
+}
+
+/// Move a broken file into quarantine and note where it went.
+pub fn quarantine(root: &Path, mut diagnostic: LoadDiagnostic) -> LoadDiagnostic {
+    let path = PathBuf::from(&diagnostic.path);
+    let target = quarantine_path(root, &path);
+    let moved = std::fs::create_dir_all(target.parent().unwrap_or(root))
+        .and_then(|_| std::fs::rename(&path, &target));
+    match moved {
+        Ok(()) => diagnostic.quarantined = Some(target.display().to_string()),
+        Err(e) => tracing::error!("Failed to quarantine {}: {}", diagnostic.path, e),
+    }
+    diagnostic
+}
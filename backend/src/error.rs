@@ -0,0 +1,449 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::io::ErrorKind;
+
+/// Where turning a workspace into a script failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompileStage {
+    /// Rendering the block templates
+    Generate,
+    /// Parsing the generated Rhai
+    Compile,
+}
+
+/// A problem found while compiling an automation, with its position in the generated script.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompileDiagnostic {
+    pub stage: CompileStage,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+impl CompileDiagnostic {
+    pub fn generate(message: impl Into<String>) -> Self {
+        Self {
+            stage: CompileStage::Generate,
+            message: message.into(),
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn from_parse_error(error: &rhai::ParseError) -> Self {
+        let position = error.position();
+        Self {
+            stage: CompileStage::Compile,
+            message: error.err_type().to_string(),
+            line: position.line(),
+            column: position.position(),
+        }
+    }
+}
+
+impl std::fmt::Display for CompileDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, " (line {}, column {})", line, column),
+            (Some(line), None) => write!(f, " (line {})", line),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AutomationError {
+    NotFound(String),
+    /// The update was based on a version that is no longer current
+    VersionConflict {
+        expected: i32,
+        current: i32,
+    },
+    Compile(CompileDiagnostic),
+    Storage(std::io::Error),
+}
+
+impl std::fmt::Display for AutomationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "Automation {} not found", id),
+            Self::VersionConflict { expected, current } => write!(
+                f,
+                "Version mismatch - automation has been modified (expected version {}, current version {})",
+                expected, current
+            ),
+            Self::Compile(diagnostic) => write!(f, "Script compilation error: {}", diagnostic),
+            Self::Storage(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AutomationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for AutomationError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Storage(e)
+    }
+}
+
+/// For callers that only deal in I/O results, such as importers.
+impl From<AutomationError> for std::io::Error {
+    fn from(e: AutomationError) -> Self {
+        match e {
+            AutomationError::Storage(e) => e,
+            AutomationError::NotFound(_) => Self::new(ErrorKind::NotFound, e.to_string()),
+            AutomationError::Compile(_) => Self::new(ErrorKind::InvalidData, e.to_string()),
+            AutomationError::VersionConflict { .. } => Self::other(e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockError {
+    NotFound(String),
+    /// The definition failed validation
+    Invalid(String),
+    Storage(std::io::Error),
+}
+
+impl std::fmt::Display for BlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(block_type) => write!(f, "Block {} not found", block_type),
+            Self::Invalid(message) => write!(f, "Invalid block: {}", message),
+            Self::Storage(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BlockError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl From<BlockError> for std::io::Error {
+    fn from(e: BlockError) -> Self {
+        match e {
+            BlockError::Storage(e) => e,
+            BlockError::NotFound(_) => Self::new(ErrorKind::NotFound, e.to_string()),
+            BlockError::Invalid(_) => Self::new(ErrorKind::InvalidInput, e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BlueprintError {
+    NotFound(String),
+    VersionConflict {
+        expected: i32,
+        current: i32,
+    },
+    /// The blueprint definition itself is inconsistent
+    Invalid(String),
+    /// A value given for an input does not fit its selector
+    InvalidInput {
+        input: String,
+        message: String,
+    },
+    /// Automations are still instantiated from the blueprint
+    InUse(Vec<String>),
+    /// The automation was not created from a blueprint
+    NotAnInstance(String),
+    Automation(AutomationError),
+    Storage(std::io::Error),
+}
+
+impl std::fmt::Display for BlueprintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "Blueprint {} not found", id),
+            Self::VersionConflict { expected, current } => write!(
+                f,
+                "Version mismatch - blueprint has been modified (expected version {}, current version {})",
+                expected, current
+            ),
+            Self::Invalid(message) => write!(f, "Invalid blueprint: {}", message),
+            Self::InvalidInput { input, message } => {
+                write!(f, "Invalid value for input {}: {}", input, message)
+            }
+            Self::InUse(ids) => write!(
+                f,
+                "Blueprint is used by {} automation(s): {}",
+                ids.len(),
+                ids.join(", ")
+            ),
+            Self::NotAnInstance(id) => {
+                write!(f, "Automation {} is not instantiated from a blueprint", id)
+            }
+            Self::Automation(e) => e.fmt(f),
+            Self::Storage(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlueprintError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Automation(e) => Some(e),
+            Self::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BlueprintError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl From<AutomationError> for BlueprintError {
+    fn from(e: AutomationError) -> Self {
+        Self::Automation(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum HaRestError {
+    /// Home Assistant could not be reached
+    Request(reqwest::Error),
+    /// Home Assistant answered with an error status
+    Status { status: u16, message: String },
+    /// The response did not have the expected shape
+    InvalidResponse(String),
+    /// The query was rejected before sending it
+    InvalidQuery(String),
+    /// There is no REST API, as with the simulator
+    Unavailable,
+}
+
+impl std::fmt::Display for HaRestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "Home Assistant request failed: {}", e),
+            Self::Status { status, message } => {
+                write!(f, "Home Assistant returned {}: {}", status, message)
+            }
+            Self::InvalidResponse(message) => {
+                write!(f, "Unexpected response from Home Assistant: {}", message)
+            }
+            Self::InvalidQuery(message) => write!(f, "Invalid query: {}", message),
+            Self::Unavailable => write!(f, "The Home Assistant REST API is not available"),
+        }
+    }
+}
+
+impl std::error::Error for HaRestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for HaRestError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Self::InvalidResponse(e.to_string())
+        } else {
+            Self::Request(e)
+        }
+    }
+}
+
+/// JSON body of every API error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Stable, machine-readable error code such as `version_conflict`
+    pub error: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<CompileDiagnostic>,
+    /// Version to base a retried update on after a conflict
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_version: Option<i32>,
+}
+
+/// An error response from an API handler.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ErrorBody,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, error: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: ErrorBody {
+                error,
+                message: message.into(),
+                diagnostics: Vec::new(),
+                current_version: None,
+            },
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+impl From<AutomationError> for ApiError {
+    fn from(e: AutomationError) -> Self {
+        let message = e.to_string();
+        match e {
+            AutomationError::NotFound(_) => Self::not_found(message),
+            AutomationError::VersionConflict { current, .. } => {
+                let mut error = Self::new(StatusCode::CONFLICT, "version_conflict", message);
+                error.body.current_version = Some(current);
+                error
+            }
+            AutomationError::Compile(diagnostic) => {
+                let mut error =
+                    Self::new(StatusCode::UNPROCESSABLE_ENTITY, "compile_error", message);
+                error.body.diagnostics.push(diagnostic);
+                error
+            }
+            AutomationError::Storage(e) => e.into(),
+        }
+    }
+}
+
+impl From<BlockError> for ApiError {
+    fn from(e: BlockError) -> Self {
+        let message = e.to_string();
+        match e {
+            BlockError::NotFound(_) => Self::not_found(message),
+            BlockError::Invalid(_) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_block", message)
+            }
+            BlockError::Storage(e) => e.into(),
+        }
+    }
+}
+
+impl From<BlueprintError> for ApiError {
+    fn from(e: BlueprintError) -> Self {
+        let message = e.to_string();
+        match e {
+            BlueprintError::NotFound(_) => Self::not_found(message),
+            BlueprintError::VersionConflict { current, .. } => {
+                let mut error = Self::new(StatusCode::CONFLICT, "version_conflict", message);
+                error.body.current_version = Some(current);
+                error
+            }
+            BlueprintError::Invalid(_) => Self::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_blueprint",
+                message,
+            ),
+            BlueprintError::InvalidInput { .. } => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_input", message)
+            }
+            BlueprintError::InUse(_) => {
+                Self::new(StatusCode::CONFLICT, "blueprint_in_use", message)
+            }
+            BlueprintError::NotAnInstance(_) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "not_an_instance", message)
+            }
+            BlueprintError::Automation(e) => e.into(),
+            BlueprintError::Storage(e) => e.into(),
+        }
+    }
+}
+
+impl From<HaRestError> for ApiError {
+    fn from(e: HaRestError) -> Self {
+        let message = e.to_string();
+        match e {
+            // E.g. a template that does not render
+            HaRestError::Status { status: 400, .. } | HaRestError::InvalidQuery(_) => {
+                Self::bad_request(message)
+            }
+            HaRestError::Status { status: 404, .. } => Self::not_found(message),
+            HaRestError::Unavailable => Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "home_assistant_unavailable",
+                message,
+            ),
+            _ => {
+                tracing::warn!("{}", message);
+                Self::new(StatusCode::BAD_GATEWAY, "home_assistant_error", message)
+            }
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), "invalid_body", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.status(), "invalid_query", rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::new(rejection.status(), "invalid_path", rejection.body_text())
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            // Rejected input, e.g. an unparsable pack or layout
+            ErrorKind::InvalidInput => Self::bad_request(e.to_string()),
+            ErrorKind::NotFound => Self::not_found(e.to_string()),
+            _ => {
+                tracing::error!("Request failed: {}", e);
+                Self::internal(e.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Drop-in replacements for axum's `Json`, `Query` and `Path` whose rejections are
+//! [`ApiError`]s, so a malformed request gets the same JSON error body as any other error.
+
+use crate::error::ApiError;
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use serde_json::{json, Value};
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Rename {
+        name: String,
+    }
+
+    #[tokio::test]
+    async fn test_rejections_are_api_errors() {
+        let app = Router::new()
+            .route(
+                "/items/{id}",
+                post(|Path(id): Path<i32>, Json(body): Json<Rename>| async move {
+                    Json(json!({ "id": id, "name": body.name }))
+                }),
+            )
+            .route(
+                "/items",
+                get(|Query(query): Query<Rename>| async move { query.name }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let http = reqwest::Client::new();
+
+        let response = http
+            .post(format!("{}/items/1", url))
+            .header("content-type", "application/json")
+            .body(r#"{"name": "Kitchen""#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "invalid_body");
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to parse the request body as JSON"));
+
+        let response = http
+            .post(format!("{}/items/1", url))
+            .json(&json!({ "title": "Kitchen" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "invalid_body");
+        assert!(body["message"].as_str().unwrap().contains("name"));
+
+        let response = http
+            .post(format!("{}/items/first", url))
+            .json(&json!({ "name": "Kitchen" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "invalid_path");
+
+        let response = http.get(format!("{}/items", url)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "invalid_query");
+
+        // Successful requests are unaffected
+        let response = http
+            .post(format!("{}/items/1", url))
+            .json(&json!({ "name": "Kitchen" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.json::<Value>().await.unwrap(),
+            json!({ "id": 1, "name": "Kitchen" })
+        );
+    }
+}
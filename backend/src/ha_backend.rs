@@ -0,0 +1,51 @@
+use crate::ha_client::{Action, EntityState, EventSubscription, TemplateSubscription};
+use crate::ha_health::ConnectionStatus;
+use crate::ha_registry::Registries;
+use crate::ha_subscription::{EntityFilter, StateSubscription};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::sync::watch;
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Where states, services and events come from: a real Home Assistant through
+/// [`HaClient`](crate::ha_client::HaClient), or the in-process
+/// [`HaSimulator`](crate::ha_simulator::HaSimulator).
+#[async_trait]
+pub trait HaBackend: std::fmt::Debug + Send + Sync {
+    async fn get_state(&self, entity_id: &str) -> Option<EntityState>;
+
+    async fn get_all_states(&self) -> HashMap<String, EntityState>;
+
+    /// Every service, keyed by `domain.service`.
+    async fn get_all_actions(&self) -> HashMap<String, Action>;
+
+    /// Revision of the service catalog, changing whenever services are added, removed or updated.
+    fn watch_services(&self) -> watch::Receiver<u64>;
+
+    async fn get_registries(&self) -> Registries;
+
+    /// Call a service; the targeted entities are given as `entity_id` in `data`.
+    async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        data: Value,
+    ) -> Result<(), BackendError>;
+
+    /// State changes of the entities matching `filter`.
+    fn subscribe_states(&self, filter: EntityFilter) -> StateSubscription;
+
+    /// Events of one type from the event bus.
+    async fn subscribe_events(&self, event_type: &str) -> Result<EventSubscription, BackendError>;
+
+    /// Renders of a template, again whenever an entity it uses changes.
+    async fn subscribe_template(
+        &self,
+        template: &str,
+        variables: Option<Value>,
+    ) -> Result<TemplateSubscription, BackendError>;
+
+    fn status(&self) -> ConnectionStatus;
+}
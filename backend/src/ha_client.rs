@@ -1,29 +1,201 @@
+use async_trait::async_trait;
 use futures::{SinkExt, StreamExt};
+use reqwest::Url;
+use rustls::pki_types::{pem::PemObject, CertificateDer};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::error::Error;
-use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Notify, RwLock};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{connect_async_tls_with_config, tungstenite::protocol::Message, Connector};
+
+use crate::ha_backend::{BackendError, HaBackend};
+use crate::ha_health::{
+    ConnectionHealth, ConnectionState, ConnectionStatus, DEFAULT_PING_INTERVAL,
+    DEFAULT_PING_TIMEOUT,
+};
+use crate::ha_registry::{Registries, Registry};
+use crate::ha_subscription::{EntityFilter, StateSubscribers, StateSubscription, DEFAULT_CAPACITY};
+
+/// How long to wait for the result of a websocket command.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// First delay before reconnecting, doubled after every failed attempt.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Integrations register their services in bursts; refetch once the burst is over.
+const SERVICES_RELOAD_DELAY: Duration = Duration::from_millis(250);
+
+/// Registries change in bursts too, with hundreds of entity updates while Home Assistant starts.
+const REGISTRY_RELOAD_DELAY: Duration = Duration::from_millis(250);
+
+/// Events fired by Home Assistant when the service catalog changes
+const SERVICE_EVENTS: [&str; 2] = ["service_registered", "service_removed"];
+
+/// Websocket API of Core behind the Supervisor proxy, reachable from add-ons.
+pub const SUPERVISOR_WEBSOCKET_URL: &str = "ws://supervisor/core/websocket";
+
+/// Websocket endpoint of Home Assistant and the certificates to trust for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HaEndpoint {
+    pub url: Url,
+    /// PEM file with CA certificates trusted in addition to the public roots
+    pub ca_cert: Option<PathBuf>,
+}
+
+impl HaEndpoint {
+    /// Accepts `host:port`, an `http(s)://` base URL or a full `ws(s)://` URL.
+    ///
+    /// Without a path the standard `/api/websocket` endpoint is used.
+    pub fn parse(address: &str) -> Result<Self, String> {
+        let address = address.trim().trim_end_matches('/');
+        let address = if address.contains("://") {
+            address.to_string()
+        } else {
+            format!("ws://{}", address)
+        };
+        let mut url = Url::parse(&address)
+            .map_err(|e| format!("Invalid Home Assistant URL {}: {}", address, e))?;
+
+        let scheme = match url.scheme() {
+            "ws" | "http" => "ws",
+            "wss" | "https" => "wss",
+            other => return Err(format!("Unsupported Home Assistant URL scheme: {}", other)),
+        };
+        url.set_scheme(scheme)
+            .map_err(|_| format!("Cannot use {} for {}", scheme, address))?;
+        if url.host_str().is_none() {
+            return Err(format!("Home Assistant URL {} has no host", address));
+        }
+        if url.path().is_empty() || url.path() == "/" {
+            url.set_path("/api/websocket");
+        }
+
+        Ok(Self { url, ca_cert: None })
+    }
+
+    pub fn supervisor() -> Self {
+        Self::parse(SUPERVISOR_WEBSOCKET_URL).expect("Supervisor URL is valid")
+    }
+
+    /// Endpoint and token from the environment.
+    ///
+    /// `HA_URL` (or the older `HA_HOST`) wins; add-ons otherwise go through the Supervisor.
+    pub fn from_env() -> Result<(Self, String), Box<dyn Error>> {
+        let supervisor_token = std::env::var("SUPERVISOR_TOKEN").ok();
+        let address = std::env::var("HA_URL")
+            .or_else(|_| std::env::var("HA_HOST"))
+            .ok();
+
+        let (endpoint, token) = match (address, supervisor_token) {
+            (None, Some(token)) => (Self::supervisor(), Some(token)),
+            (address, supervisor_token) => (
+                Self::parse(address.as_deref().unwrap_or("localhost:8123"))?,
+                std::env::var("HA_TOKEN").ok().or(supervisor_token),
+            ),
+        };
+        let token = token.ok_or("Either SUPERVISOR_TOKEN or HA_TOKEN must be set")?;
+
+        let endpoint = match std::env::var("HA_CA_CERT") {
+            Ok(ca_cert) => endpoint.with_ca_cert(ca_cert),
+            Err(_) => endpoint,
+        };
+        Ok((endpoint, token))
+    }
+
+    pub fn with_ca_cert(mut self, ca_cert: impl Into<PathBuf>) -> Self {
+        self.ca_cert = Some(ca_cert.into());
+        self
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.url.scheme() == "wss"
+    }
+
+    /// rustls connector for `wss://` endpoints, `None` for plain websockets.
+    pub(crate) fn tls_connector(&self) -> Result<Option<Connector>, Box<dyn Error>> {
+        if !self.is_tls() {
+            return Ok(None);
+        }
+
+        let mut roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        if let Some(path) = &self.ca_cert {
+            for cert in CertificateDer::pem_file_iter(path)
+                .map_err(|e| format!("Failed to read CA certificate {}: {}", path.display(), e))?
+            {
+                roots.add(cert?)?;
+            }
+        }
+
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        Ok(Some(Connector::Rustls(Arc::new(config))))
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntityState {
     pub state: String,
     pub attributes: HashMap<String, Value>,
+    /// When `state` last changed
+    #[serde(default)]
+    pub last_changed: String,
+    /// When `state` or an attribute last changed
     pub last_updated: String,
+    /// When the state was last written, even if unchanged
+    #[serde(default)]
+    pub last_reported: Option<String>,
+    #[serde(default)]
+    pub context: Option<EventContext>,
 }
 
+/// A `state_changed` event: the entity's state before and after the change.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateChange {
+    pub entity_id: String,
+    /// `None` when the entity was just added
+    pub old_state: Option<EntityState>,
+    pub new_state: EntityState,
+}
+
+impl StateChange {
+    /// Who or what caused the change.
+    pub fn context(&self) -> Option<&EventContext> {
+        self.new_state.context.as_ref()
+    }
+
+    /// Changed by a user, e.g. from the UI, rather than by an automation or a device.
+    pub fn is_user_change(&self) -> bool {
+        self.context()
+            .is_some_and(|context| context.user_id.is_some())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ActionField {
     pub name: String,
     pub description: Option<String>,
     pub required: Option<bool>,
     pub selector: Option<Value>,
+    #[serde(default)]
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub example: Option<Value>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Action {
     #[serde(default)]
     pub domain: Option<String>,
@@ -35,11 +207,194 @@ pub struct Action {
     pub id: Option<String>,
 }
 
+/// Where an event was fired and by whom.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventContext {
+    pub id: String,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<String>,
+}
+
+/// An event from the Home Assistant event bus.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HaEvent {
+    pub event_type: String,
+    #[serde(default)]
+    pub data: Value,
+    /// `LOCAL` or `REMOTE`
+    #[serde(default)]
+    pub origin: Option<String>,
+    #[serde(default)]
+    pub time_fired: Option<String>,
+    #[serde(default)]
+    pub context: Option<EventContext>,
+}
+
+impl HaEvent {
+    /// Whether every key of `filter` is present in the event data with the same value.
+    pub fn data_matches(&self, filter: &Value) -> bool {
+        value_contains(&self.data, filter)
+    }
+}
+
+/// Objects match when every key of `expected` matches; other values must be equal.
+pub fn value_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .is_some_and(|actual| value_contains(actual, value))
+        }),
+        (actual, expected) => actual == expected,
+    }
+}
+
+/// Events of one type, optionally narrowed down by their data.
+pub struct EventSubscription {
+    event_type: String,
+    data: Option<Value>,
+    rx: broadcast::Receiver<HaEvent>,
+}
+
+impl EventSubscription {
+    pub(crate) fn new(event_type: &str, rx: broadcast::Receiver<HaEvent>) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            data: None,
+            rx,
+        }
+    }
+
+    /// Only deliver events whose data contains `data`, see [`HaEvent::data_matches`].
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub async fn recv(&mut self) -> Result<HaEvent, broadcast::error::RecvError> {
+        loop {
+            let event = self.rx.recv().await?;
+            if event.event_type == self.event_type
+                && self
+                    .data
+                    .as_ref()
+                    .is_none_or(|data| event.data_matches(data))
+            {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+type PendingRequests = HashMap<i32, oneshot::Sender<Result<Value, String>>>;
+
+/// A `render_template` subscription, sent again on every new connection.
+#[derive(Debug)]
+struct TemplateSubscriber {
+    command: Value,
+    /// Id of the `render_template` command on the current connection
+    message_id: i32,
+    tx: mpsc::UnboundedSender<TemplateUpdate>,
+}
+
+/// A re-rendered result of a `render_template` subscription.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateUpdate {
+    Rendered(Value),
+    /// Rendering failed, e.g. on an undefined variable; later renders may succeed again
+    Error(String),
+}
+
+impl TemplateUpdate {
+    /// Whether the result is true the way Home Assistant's template conditions see it.
+    pub fn is_true(&self) -> bool {
+        match self {
+            TemplateUpdate::Rendered(result) => template_result_is_true(result),
+            TemplateUpdate::Error(_) => false,
+        }
+    }
+}
+
+/// `true`, a non-zero number, or one of `true`, `on`, `yes`, `enable` and `1`.
+pub fn template_result_is_true(result: &Value) -> bool {
+    match result {
+        Value::Bool(result) => *result,
+        Value::Number(result) => result.as_f64().is_some_and(|result| result != 0.0),
+        Value::String(result) => matches!(
+            result.trim().to_lowercase().as_str(),
+            "true" | "on" | "yes" | "enable" | "1"
+        ),
+        _ => false,
+    }
+}
+
+/// Results of a template, rendered again whenever an entity it uses changes.
+///
+/// Dropping the subscription unsubscribes in Home Assistant.
+#[derive(Debug)]
+pub struct TemplateSubscription {
+    id: i32,
+    rx: mpsc::UnboundedReceiver<TemplateUpdate>,
+    client: HaClient,
+    /// Whether the last render was true
+    last: Option<bool>,
+}
+
+impl TemplateSubscription {
+    /// The next render, `None` once the connection is gone.
+    pub async fn recv(&mut self) -> Option<TemplateUpdate> {
+        let update = self.rx.recv().await?;
+        self.last = Some(update.is_true());
+        Some(update)
+    }
+
+    /// Wait for the template to change from false to true.
+    ///
+    /// Like a template trigger in Home Assistant, a template that is already true
+    /// when subscribing has to turn false first.
+    pub async fn becomes_true(&mut self) -> Option<TemplateUpdate> {
+        loop {
+            let was_true = self.last;
+            let update = self.recv().await?;
+            if was_true == Some(false) && update.is_true() {
+                return Some(update);
+            }
+        }
+    }
+}
+
+impl Drop for TemplateSubscription {
+    fn drop(&mut self) {
+        self.client.unsubscribe_template(self.id);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct HaClient {
     states: Arc<RwLock<HashMap<String, EntityState>>>,
     actions: Arc<RwLock<HashMap<String, Action>>>,
-    state_tx: broadcast::Sender<(String, EntityState)>,
+    /// Revision of `actions`, bumped whenever the catalog changes
+    services_revision: watch::Sender<u64>,
+    /// A coalesced reload of the services is scheduled
+    services_reload_pending: Arc<AtomicBool>,
+    /// A coalesced reload is scheduled, by [`Registry`]
+    registry_reload_pending: Arc<[AtomicBool; Registry::ALL.len()]>,
+    state_subscribers: StateSubscribers,
+    event_tx: broadcast::Sender<HaEvent>,
+    /// Event types subscribed to on the current and any later connection
+    event_types: Arc<RwLock<BTreeSet<String>>>,
+    /// Messages to send over the current connection
+    outgoing: Arc<RwLock<Option<mpsc::UnboundedSender<Message>>>>,
+    /// Commands waiting for their result, by message id
+    pending: Arc<Mutex<PendingRequests>>,
+    /// `render_template` subscriptions, by the id of their first command
+    templates: Arc<Mutex<HashMap<i32, TemplateSubscriber>>>,
+    registries: Arc<RwLock<Registries>>,
+    health: ConnectionHealth,
+    ping_interval: Duration,
+    ping_timeout: Duration,
     message_id: Arc<AtomicI32>,
 }
 
@@ -60,43 +415,291 @@ struct HaAuth {
 }
 
 #[derive(Debug, Deserialize)]
-struct HaEvent {
-    #[serde(rename = "type")]
-    msg_type: String,
-    event: Option<HaEventData>,
-    id: Option<i32>,
-    success: Option<bool>,
-}
-
-#[derive(Debug, Deserialize)]
-struct HaEventData {
-    data: Option<HaStateChanged>,
+struct HaEventMessage {
+    event: HaEvent,
 }
 
 #[derive(Debug, Deserialize)]
 struct HaStateChanged {
     entity_id: Option<String>,
+    old_state: Option<EntityState>,
     new_state: Option<EntityState>,
 }
 
 impl HaClient {
     pub fn new() -> Self {
-        let (state_tx, _) = broadcast::channel(100);
+        let (event_tx, _) = broadcast::channel(100);
         Self {
             states: Arc::new(RwLock::new(HashMap::new())),
             actions: Arc::new(RwLock::new(HashMap::new())),
-            state_tx,
+            services_revision: watch::Sender::new(0),
+            services_reload_pending: Arc::new(AtomicBool::new(false)),
+            registry_reload_pending: Arc::new(Default::default()),
+            state_subscribers: StateSubscribers::default(),
+            event_tx,
+            event_types: Arc::new(RwLock::new(BTreeSet::from(["state_changed".to_string()]))),
+            outgoing: Arc::new(RwLock::new(None)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            templates: Arc::new(Mutex::new(HashMap::new())),
+            registries: Arc::new(RwLock::new(Registries::default())),
+            health: ConnectionHealth::default(),
+            ping_interval: DEFAULT_PING_INTERVAL,
+            ping_timeout: DEFAULT_PING_TIMEOUT,
             message_id: Arc::new(AtomicI32::new(1)),
         }
     }
 
+    /// Ping every `interval` and drop the connection when a pong takes longer than `timeout`.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.ping_interval = interval;
+        self.ping_timeout = timeout;
+        self
+    }
+
     fn next_id(&self) -> i32 {
         self.message_id.fetch_add(1, Ordering::Relaxed)
     }
 
+    /// Queue a message on the current connection; dropped when not connected.
+    async fn send(&self, message: &impl Serialize) -> Result<(), Box<dyn Error>> {
+        let text = serde_json::to_string(message)?;
+        if let Some(outgoing) = self.outgoing.read().await.as_ref() {
+            outgoing
+                .send(Message::Text(text.into()))
+                .map_err(|_| "Home Assistant connection is closed")?;
+        }
+        Ok(())
+    }
+
+    /// Send a command and wait for its result.
+    ///
+    /// `command` is the message without its `id`, e.g. `{"type": "config/area_registry/list"}`.
+    pub async fn request(&self, command: Value) -> Result<Value, Box<dyn Error>> {
+        self.request_with_id(self.next_id(), command, REQUEST_TIMEOUT)
+            .await
+    }
+
+    async fn request_with_id(
+        &self,
+        id: i32,
+        mut command: Value,
+        timeout: Duration,
+    ) -> Result<Value, Box<dyn Error>> {
+        if self.outgoing.read().await.is_none() {
+            return Err("Not connected to Home Assistant".into());
+        }
+        command["id"] = id.into();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        if let Err(e) = self.send(&command).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        let result = tokio::time::timeout(timeout, rx).await;
+        self.pending.lock().unwrap().remove(&id);
+        match result {
+            Ok(Ok(result)) => Ok(result?),
+            Ok(Err(_)) => Err("Home Assistant connection closed".into()),
+            Err(_) => Err(format!("{} timed out", command["type"]).into()),
+        }
+    }
+
+    /// Call a service, e.g. `light.turn_on` with `{"entity_id": "light.kitchen"}`.
+    pub async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        data: Value,
+    ) -> Result<Value, Box<dyn Error>> {
+        self.request(serde_json::json!({
+            "type": "call_service",
+            "domain": domain,
+            "service": service,
+            "service_data": data,
+        }))
+        .await
+    }
+
+    /// Render a template now and again whenever the entities it uses change.
+    ///
+    /// Fails right away if the template does not parse.
+    pub async fn subscribe_template(
+        &self,
+        template: &str,
+        variables: Option<Value>,
+    ) -> Result<TemplateSubscription, Box<dyn Error>> {
+        let id = self.next_id();
+        let mut command = serde_json::json!({
+            "type": "render_template",
+            "template": template,
+            "report_errors": true,
+        });
+        if let Some(variables) = variables {
+            command["variables"] = variables;
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.templates.lock().unwrap().insert(
+            id,
+            TemplateSubscriber {
+                command: command.clone(),
+                message_id: id,
+                tx,
+            },
+        );
+
+        if let Err(e) = self.request_with_id(id, command, REQUEST_TIMEOUT).await {
+            self.templates.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok(TemplateSubscription {
+            id,
+            rx,
+            client: self.clone(),
+            last: None,
+        })
+    }
+
+    fn unsubscribe_template(&self, subscription: i32) {
+        let Some(template) = self.templates.lock().unwrap().remove(&subscription) else {
+            return;
+        };
+        // Dropped outside of async code, so do not wait for the lock
+        if let Ok(outgoing) = self.outgoing.try_read() {
+            if let Some(outgoing) = outgoing.as_ref() {
+                let message = serde_json::json!({
+                    "id": self.next_id(),
+                    "type": "unsubscribe_events",
+                    "subscription": template.message_id,
+                });
+                let _ = outgoing.send(Message::Text(message.to_string().into()));
+            }
+        }
+    }
+
+    /// Reload one registry from Home Assistant.
+    pub async fn load_registry(&self, registry: Registry) -> Result<(), Box<dyn Error>> {
+        let result = self
+            .request(serde_json::json!({ "type": registry.list_command() }))
+            .await?;
+        self.registries.write().await.load(registry, result)?;
+        tracing::debug!("Loaded {}", registry.list_command());
+        Ok(())
+    }
+
+    /// Refetch the service catalog, notifying [`HaClient::watch_services`] if it changed.
+    pub async fn load_services(&self) -> Result<(), Box<dyn Error>> {
+        let result = self
+            .request(serde_json::json!({ "type": "get_services" }))
+            .await?;
+        let services = parse_services(&result);
+        let mut actions = self.actions.write().await;
+        if *actions != services {
+            tracing::debug!("Loaded {} services", services.len());
+            *actions = services;
+            drop(actions);
+            self.services_revision
+                .send_modify(|revision| *revision += 1);
+        }
+        Ok(())
+    }
+
+    async fn send_subscribe(&self, event_type: &str) -> Result<(), Box<dyn Error>> {
+        self.send(&HaMessage {
+            id: self.next_id(),
+            msg_type: "subscribe_events".to_string(),
+            event_type: Some(event_type.to_string()),
+        })
+        .await
+    }
+
+    /// Receive events of the given type, subscribing to it in Home Assistant if needed.
+    ///
+    /// Subscriptions made before connecting are sent once the connection is up.
+    pub async fn subscribe_events(
+        &self,
+        event_type: &str,
+    ) -> Result<EventSubscription, Box<dyn Error>> {
+        let rx = self.event_tx.subscribe();
+        if self
+            .event_types
+            .write()
+            .await
+            .insert(event_type.to_string())
+        {
+            self.send_subscribe(event_type).await?;
+        }
+        Ok(EventSubscription::new(event_type, rx))
+    }
+
+    /// Every event of every subscribed type.
+    pub fn subscribe_to_events(&self) -> broadcast::Receiver<HaEvent> {
+        self.event_tx.subscribe()
+    }
+
+    /// Connect to `host:port` or a URL as accepted by [`HaEndpoint::parse`].
     pub async fn connect(&self, host: String, token: String) -> Result<(), Box<dyn Error>> {
-        let ws_url = format!("ws://{}/api/websocket", host);
-        let (ws_stream, _) = connect_async(ws_url).await?;
+        self.connect_to(&HaEndpoint::parse(&host)?, token).await
+    }
+
+    /// Connect once; see [`HaClient::keep_connected`] to reconnect when the connection is lost.
+    pub async fn connect_to(
+        &self,
+        endpoint: &HaEndpoint,
+        token: String,
+    ) -> Result<(), Box<dyn Error>> {
+        self.connect_once(endpoint, token).await.map(|_| ())
+    }
+
+    /// Stay connected in the background, reconnecting with a growing delay.
+    ///
+    /// Gives up only when Home Assistant rejects the token, since retrying a bad token
+    /// gets the address banned.
+    pub fn keep_connected(&self, endpoint: HaEndpoint, token: String) -> JoinHandle<()> {
+        let client = self.clone();
+        tokio::spawn(async move {
+            let mut delay = MIN_RECONNECT_DELAY;
+            loop {
+                let connection = client
+                    .connect_once(&endpoint, token.clone())
+                    .await
+                    .map_err(|e| e.to_string());
+                match connection {
+                    Ok(connection) => {
+                        let _ = connection.await;
+                        tracing::warn!("Lost connection to Home Assistant");
+                        delay = MIN_RECONNECT_DELAY;
+                        client
+                            .health
+                            .disconnected(ConnectionState::Reconnecting, None);
+                    }
+                    Err(e) if client.health.state() == ConnectionState::AuthFailed => {
+                        tracing::error!("Not reconnecting to Home Assistant: {}", e);
+                        return;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to connect to Home Assistant: {}", e);
+                        client
+                            .health
+                            .disconnected(ConnectionState::Reconnecting, Some(e));
+                    }
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        })
+    }
+
+    /// Connect and authenticate, returning the task reading from the connection.
+    async fn connect_once(
+        &self,
+        endpoint: &HaEndpoint,
+        token: String,
+    ) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        tracing::info!("Connecting to Home Assistant at {}", endpoint.url);
+        let connector = endpoint.tls_connector()?;
+        let (ws_stream, _) =
+            connect_async_tls_with_config(endpoint.url.as_str(), None, false, connector).await?;
         let (mut write, mut read) = ws_stream.split();
 
         // Wait for auth_required message
@@ -119,46 +722,76 @@ impl HaClient {
             let msg: serde_json::Value = serde_json::from_str(&text)?;
             if msg["type"] != "auth_ok" {
                 if msg["type"] == "auth_invalid" {
-                    return Err("Authentication failed".into());
+                    let error = "Authentication failed".to_string();
+                    self.health
+                        .disconnected(ConnectionState::AuthFailed, Some(error.clone()));
+                    return Err(error.into());
                 }
                 return Err("Expected auth_ok message".into());
             }
         }
 
-        // Subscribe to state changes
-        let sub_id = self.next_id();
-        let sub_msg = serde_json::to_string(&HaMessage {
-            id: sub_id,
-            msg_type: "subscribe_events".to_string(),
-            event_type: Some("state_changed".to_string()),
-        })?;
-        write.send(Message::Text(sub_msg.into())).await?;
+        // Everything after authentication goes through the outgoing queue
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            while let Some(message) = outgoing_rx.recv().await {
+                if let Err(e) = write.send(message).await {
+                    tracing::error!("Failed to send to Home Assistant: {}", e);
+                    break;
+                }
+            }
+        });
+        *self.outgoing.write().await = Some(outgoing_tx);
+        self.health.connected();
+
+        // Subscribe to state changes, registry and service updates and any other requested event types
+        self.event_types.write().await.extend(
+            Registry::ALL
+                .iter()
+                .map(|registry| registry.updated_event())
+                .chain(SERVICE_EVENTS)
+                .map(str::to_string),
+        );
+        let event_types: Vec<String> = self.event_types.read().await.iter().cloned().collect();
+        for event_type in &event_types {
+            self.send_subscribe(event_type).await?;
+        }
 
         // Get initial states
         let states_id = self.next_id();
-        let get_states_msg = serde_json::to_string(&HaMessage {
+        self.send(&HaMessage {
             id: states_id,
             msg_type: "get_states".to_string(),
             event_type: None,
-        })?;
-        write.send(Message::Text(get_states_msg.into())).await?;
-
-        // Get available actions
-        let actions_id = self.next_id();
-        let get_actions_msg = serde_json::to_string(&HaMessage {
-            id: actions_id,
-            msg_type: "get_services".to_string(),
-            event_type: None,
-        })?;
-        write.send(Message::Text(get_actions_msg.into())).await?;
+        })
+        .await?;
 
         let states = self.states.clone();
-        let actions = self.actions.clone();
-        let state_tx = self.state_tx.clone();
+        let state_subscribers = self.state_subscribers.clone();
+        let event_tx = self.event_tx.clone();
+        let outgoing = self.outgoing.clone();
+        let pending = self.pending.clone();
+        let templates = self.templates.clone();
+        let health = self.health.clone();
+        let client = self.clone();
+
+        // A ping without answer closes the connection, even if the socket looks open
+        let close = Arc::new(Notify::new());
+        let heartbeat = tokio::spawn(self.clone().heartbeat(close.clone()));
 
         // Handle incoming messages
-        tokio::spawn(async move {
-            while let Some(msg) = read.next().await {
+        let connection = tokio::spawn(async move {
+            loop {
+                let msg = tokio::select! {
+                    msg = read.next() => msg,
+                    _ = close.notified() => {
+                        tracing::warn!("Home Assistant stopped answering pings, closing the connection");
+                        break;
+                    }
+                };
+                let Some(msg) = msg else {
+                    break;
+                };
                 match msg {
                     Ok(Message::Text(text)) => {
                         let json: serde_json::Value = if let Ok(v) = serde_json::from_str(&text) {
@@ -171,6 +804,8 @@ impl HaClient {
                         if json["type"] == "result" && json["id"] == states_id {
                             if let Some(result) = json["result"].as_array() {
                                 let mut states_map = states.write().await;
+                                // Forget entities removed while disconnected
+                                let previous = std::mem::take(&mut *states_map);
                                 for state in result {
                                     if let (Some(entity_id), Some(state_obj)) = (
                                         state["entity_id"].as_str(),
@@ -179,71 +814,91 @@ impl HaClient {
                                         states_map.insert(entity_id.to_string(), state_obj);
                                     }
                                 }
+                                // Changes missed while disconnected reach subscribers as a gap
+                                if !previous.is_empty() {
+                                    state_subscribers
+                                        .dispatch_lagged(&missed_changes(&previous, &states_map));
+                                }
                             }
                             continue;
                         }
 
-                        // Handle get_services response
-                        if json["type"] == "result" && json["id"] == actions_id {
-                            tracing::debug!("Got services response: {}", json);
-                            if let Some(result) = json["result"].as_object() {
-                                let mut actions_map = actions.write().await;
-                                for (domain, domain_services) in result {
-                                    if let Some(services) = domain_services.as_object() {
-                                        for (service_name, service_data) in services {
-                                            let mut action = match serde_json::from_value::<Action>(
-                                                service_data.clone(),
-                                            ) {
-                                                Ok(action) => action,
-                                                Err(e) => {
-                                                    tracing::debug!(
-                                                        "Using fallback parsing for service {}.{} ({}). Raw data: {:?}",
-                                                        domain,
-                                                        service_name,
-                                                        e,
-                                                        service_data
-                                                    );
-                                                    Action {
-                                                        domain: None,
-                                                        name: None,
-                                                        description: None,
-                                                        target: None,
-                                                        fields: HashMap::new(),
-                                                        id: None,
-                                                    }
-                                                }
-                                            };
-
-                                            // Set domain and id after deserialization
-                                            let id = format!("{}.{}", domain, service_name);
-                                            action.domain = Some(domain.clone());
-                                            action.id = Some(id.clone());
-
-                                            tracing::debug!("Added action: {}", id);
-                                            actions_map.insert(id, action);
-                                        }
-                                    }
-                                }
+                        // Results of commands sent with `request`; a ping is answered by a pong
+                        if json["type"] == "result" || json["type"] == "pong" {
+                            let waiting = json["id"]
+                                .as_i64()
+                                .and_then(|id| pending.lock().unwrap().remove(&(id as i32)));
+                            if let Some(tx) = waiting {
+                                let result = if json["type"] == "pong" || json["success"] == true {
+                                    Ok(json["result"].clone())
+                                } else {
+                                    Err(json["error"]["message"]
+                                        .as_str()
+                                        .unwrap_or("Command failed")
+                                        .to_string())
+                                };
+                                let _ = tx.send(result);
                             }
                             continue;
                         }
 
-                        // Handle state changes
-                        if let Ok(event) = serde_json::from_value::<HaEvent>(json) {
-                            if let Some(event_data) = event.event {
-                                if let Some(state_changed) = event_data.data {
-                                    if let (Some(entity_id), Some(new_state)) =
-                                        (state_changed.entity_id, state_changed.new_state)
-                                    {
-                                        states
-                                            .write()
-                                            .await
-                                            .insert(entity_id.clone(), new_state.clone());
-                                        let _ = state_tx.send((entity_id, new_state));
-                                    }
-                                }
+                        if json["type"] != "event" {
+                            continue;
+                        }
+
+                        // Renders of subscribed templates
+                        let template = json["id"].as_i64().and_then(|id| {
+                            templates
+                                .lock()
+                                .unwrap()
+                                .values()
+                                .find(|template| template.message_id == id as i32)
+                                .map(|template| template.tx.clone())
+                        });
+                        if let Some(tx) = template {
+                            let update = match json["event"]["error"].as_str() {
+                                Some(error) => TemplateUpdate::Error(error.to_string()),
+                                None => TemplateUpdate::Rendered(json["event"]["result"].clone()),
+                            };
+                            let _ = tx.send(update);
+                            continue;
+                        }
+                        let event = match serde_json::from_value::<HaEventMessage>(json) {
+                            Ok(message) => message.event,
+                            Err(e) => {
+                                tracing::debug!("Ignoring malformed event: {}", e);
+                                continue;
+                            }
+                        };
+
+                        health.event_received();
+
+                        // Keep the state cache current
+                        if event.event_type == "state_changed" {
+                            if let Ok(HaStateChanged {
+                                entity_id: Some(entity_id),
+                                old_state,
+                                new_state: Some(new_state),
+                            }) = serde_json::from_value::<HaStateChanged>(event.data.clone())
+                            {
+                                states
+                                    .write()
+                                    .await
+                                    .insert(entity_id.clone(), new_state.clone());
+                                state_subscribers.dispatch(&StateChange {
+                                    entity_id,
+                                    old_state,
+                                    new_state,
+                                });
                             }
                         }
+                        if let Some(registry) = Registry::from_updated_event(&event.event_type) {
+                            client.schedule_registry_reload(registry);
+                        }
+                        if SERVICE_EVENTS.contains(&event.event_type.as_str()) {
+                            client.schedule_services_reload();
+                        }
+                        let _ = event_tx.send(event);
                     }
                     Err(e) => {
                         tracing::error!("WebSocket error: {}", e);
@@ -252,9 +907,121 @@ impl HaClient {
                     _ => {}
                 }
             }
+            // Stop queueing messages for a connection that is gone
+            heartbeat.abort();
+            *outgoing.write().await = None;
+            pending.lock().unwrap().clear();
+            health.disconnected(ConnectionState::Disconnected, None);
         });
 
-        Ok(())
+        for registry in Registry::ALL {
+            let client = self.clone();
+            tokio::spawn(async move { client.reload_registry(registry).await });
+        }
+        // Services may have changed while disconnected
+        let client = self.clone();
+        tokio::spawn(async move { client.reload_services().await });
+        self.resubscribe_templates();
+
+        Ok(connection)
+    }
+
+    /// Subscribe the templates of earlier connections on the current one.
+    fn resubscribe_templates(&self) {
+        let commands: Vec<(i32, Value)> = self
+            .templates
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter(|template| !template.tx.is_closed())
+            .map(|template| {
+                template.message_id = self.next_id();
+                (template.message_id, template.command.clone())
+            })
+            .collect();
+        for (id, command) in commands {
+            let client = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = client.request_with_id(id, command, REQUEST_TIMEOUT).await {
+                    tracing::warn!("Failed to subscribe to a template again: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Ping until a ping fails, then ask the connection to close.
+    async fn heartbeat(self, close: Arc<Notify>) {
+        loop {
+            tokio::time::sleep(self.ping_interval).await;
+            self.health.ping_sent();
+            let sent = Instant::now();
+            let ping = self
+                .request_with_id(
+                    self.next_id(),
+                    serde_json::json!({ "type": "ping" }),
+                    self.ping_timeout,
+                )
+                .await;
+            match ping {
+                Ok(_) => self.health.pong_received(sent.elapsed()),
+                Err(e) => {
+                    self.health.ping_failed(e.to_string());
+                    close.notify_one();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Health of the connection to Home Assistant.
+    pub fn status(&self) -> ConnectionStatus {
+        self.health.status()
+    }
+
+    async fn reload_registry(&self, registry: Registry) {
+        if let Err(e) = self.load_registry(registry).await {
+            tracing::warn!("Failed to load {}: {}", registry.list_command(), e);
+        }
+    }
+
+    async fn reload_services(&self) {
+        if let Err(e) = self.load_services().await {
+            tracing::warn!("Failed to load services: {}", e);
+        }
+    }
+
+    /// Reload a registry shortly, once for any number of calls in the meantime.
+    fn schedule_registry_reload(&self, registry: Registry) {
+        let pending = &self.registry_reload_pending[registry as usize];
+        if pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let client = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(REGISTRY_RELOAD_DELAY).await;
+            client.registry_reload_pending[registry as usize].store(false, Ordering::Release);
+            client.reload_registry(registry).await;
+        });
+    }
+
+    /// Reload the services shortly, once for any number of calls in the meantime.
+    fn schedule_services_reload(&self) {
+        if self.services_reload_pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let client = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(SERVICES_RELOAD_DELAY).await;
+            client
+                .services_reload_pending
+                .store(false, Ordering::Release);
+            client.reload_services().await;
+        });
+    }
+
+    /// Areas, devices and entities as last loaded from Home Assistant.
+    pub async fn get_registries(&self) -> Registries {
+        self.registries.read().await.clone()
     }
 
     pub async fn get_state(&self, entity_id: &str) -> Option<EntityState> {
@@ -269,7 +1036,138 @@ impl HaClient {
         self.actions.read().await.clone()
     }
 
-    pub fn subscribe_to_states(&self) -> broadcast::Receiver<(String, EntityState)> {
-        self.state_tx.subscribe()
+    /// Revision of the service catalog, changing whenever services are added, removed or updated.
+    pub fn watch_services(&self) -> watch::Receiver<u64> {
+        self.services_revision.subscribe()
+    }
+
+    /// State changes of the entities matching `filter`.
+    ///
+    /// Every subscription has its own queue; one that falls behind gets a
+    /// [`StateUpdate::Lagged`](crate::ha_subscription::StateUpdate::Lagged) with the net
+    /// change of each entity instead of losing changes.
+    pub fn subscribe_states(&self, filter: EntityFilter) -> StateSubscription {
+        self.state_subscribers.subscribe(filter, DEFAULT_CAPACITY)
+    }
+}
+
+/// Net change of every entity that is new or changed in `current`.
+fn missed_changes(
+    previous: &HashMap<String, EntityState>,
+    current: &HashMap<String, EntityState>,
+) -> Vec<StateChange> {
+    current
+        .iter()
+        .filter(|(entity_id, state)| {
+            previous.get(*entity_id).is_none_or(|old| {
+                old.state != state.state || old.last_updated != state.last_updated
+            })
+        })
+        .map(|(entity_id, state)| StateChange {
+            entity_id: entity_id.clone(),
+            old_state: previous.get(entity_id).cloned(),
+            new_state: state.clone(),
+        })
+        .collect()
+}
+
+#[async_trait]
+impl HaBackend for HaClient {
+    async fn get_state(&self, entity_id: &str) -> Option<EntityState> {
+        HaClient::get_state(self, entity_id).await
+    }
+
+    async fn get_all_states(&self) -> HashMap<String, EntityState> {
+        HaClient::get_all_states(self).await
+    }
+
+    async fn get_all_actions(&self) -> HashMap<String, Action> {
+        HaClient::get_all_actions(self).await
+    }
+
+    fn watch_services(&self) -> watch::Receiver<u64> {
+        HaClient::watch_services(self)
+    }
+
+    async fn get_registries(&self) -> Registries {
+        HaClient::get_registries(self).await
+    }
+
+    async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        data: Value,
+    ) -> Result<(), BackendError> {
+        HaClient::call_service(self, domain, service, data)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string().into())
+    }
+
+    fn subscribe_states(&self, filter: EntityFilter) -> StateSubscription {
+        HaClient::subscribe_states(self, filter)
+    }
+
+    async fn subscribe_events(&self, event_type: &str) -> Result<EventSubscription, BackendError> {
+        HaClient::subscribe_events(self, event_type)
+            .await
+            .map_err(|e| e.to_string().into())
+    }
+
+    async fn subscribe_template(
+        &self,
+        template: &str,
+        variables: Option<Value>,
+    ) -> Result<TemplateSubscription, BackendError> {
+        HaClient::subscribe_template(self, template, variables)
+            .await
+            .map_err(|e| e.to_string().into())
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        HaClient::status(self)
+    }
+}
+
+/// Actions keyed by `domain.service` from the result of `get_services`.
+fn parse_services(result: &Value) -> HashMap<String, Action> {
+    let mut actions = HashMap::new();
+    let Some(domains) = result.as_object() else {
+        return actions;
+    };
+    for (domain, domain_services) in domains {
+        let Some(services) = domain_services.as_object() else {
+            continue;
+        };
+        for (service_name, service_data) in services {
+            let mut action = match serde_json::from_value::<Action>(service_data.clone()) {
+                Ok(action) => action,
+                Err(e) => {
+                    tracing::debug!(
+                        "Using fallback parsing for service {}.{} ({}). Raw data: {:?}",
+                        domain,
+                        service_name,
+                        e,
+                        service_data
+                    );
+                    Action {
+                        domain: None,
+                        name: None,
+                        description: None,
+                        target: None,
+                        fields: HashMap::new(),
+                        id: None,
+                    }
+                }
+            };
+
+            // Set domain and id after deserialization
+            let id = format!("{}.{}", domain, service_name);
+            action.domain = Some(domain.clone());
+            action.id = Some(id.clone());
+            actions.insert(id, action);
+        }
     }
+    actions
 }
@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often to ping Home Assistant by default.
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for a pong before considering the connection dead.
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// No connection attempt made yet
+    Connecting,
+    Connected,
+    /// The connection ended and nobody is reconnecting
+    Disconnected,
+    /// Waiting to connect again after the connection ended or an attempt failed
+    Reconnecting,
+    /// Home Assistant rejected the token; not retried
+    AuthFailed,
+}
+
+/// Health of the websocket connection, as reported by `/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    pub connected_since: Option<DateTime<Utc>>,
+    /// When the last event from the event bus arrived
+    pub last_event_at: Option<DateTime<Utc>>,
+    /// Round-trip time of the last answered ping
+    pub ping_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub events_received: u64,
+    pub pings_sent: u64,
+    pub pings_failed: u64,
+    /// Connections made after the first one
+    pub reconnects: u64,
+}
+
+/// Shared, updatable [`ConnectionStatus`].
+#[derive(Debug, Clone)]
+pub struct ConnectionHealth {
+    status: Arc<Mutex<ConnectionStatus>>,
+}
+
+impl Default for ConnectionHealth {
+    fn default() -> Self {
+        Self {
+            status: Arc::new(Mutex::new(ConnectionStatus {
+                state: ConnectionState::Connecting,
+                connected_since: None,
+                last_event_at: None,
+                ping_ms: None,
+                last_error: None,
+                events_received: 0,
+                pings_sent: 0,
+                pings_failed: 0,
+                reconnects: 0,
+            })),
+        }
+    }
+}
+
+impl ConnectionHealth {
+    pub fn status(&self) -> ConnectionStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.status.lock().unwrap().state
+    }
+
+    pub fn connected(&self) {
+        let mut status = self.status.lock().unwrap();
+        if status.connected_since.is_some() {
+            status.reconnects += 1;
+        }
+        status.state = ConnectionState::Connected;
+        status.connected_since = Some(Utc::now());
+        status.ping_ms = None;
+        status.last_error = None;
+    }
+
+    /// The connection ended or could not be made.
+    pub fn disconnected(&self, state: ConnectionState, error: Option<String>) {
+        let mut status = self.status.lock().unwrap();
+        status.state = state;
+        if error.is_some() {
+            status.last_error = error;
+        }
+    }
+
+    pub fn event_received(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.events_received += 1;
+        status.last_event_at = Some(Utc::now());
+    }
+
+    pub fn ping_sent(&self) {
+        self.status.lock().unwrap().pings_sent += 1;
+    }
+
+    pub fn pong_received(&self, round_trip: Duration) {
+        self.status.lock().unwrap().ping_ms = Some(round_trip.as_millis() as u64);
+    }
+
+    pub fn ping_failed(&self, error: String) {
+        let mut status = self.status.lock().unwrap();
+        status.pings_failed += 1;
+        status.last_error = Some(error);
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// One of the Home Assistant registries mirrored by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Registry {
+    Area,
+    Device,
+    Entity,
+}
+
+impl Registry {
+    pub const ALL: [Registry; 3] = [Registry::Area, Registry::Device, Registry::Entity];
+
+    /// Websocket command listing the registry
+    pub fn list_command(self) -> &'static str {
+        match self {
+            Registry::Area => "config/area_registry/list",
+            Registry::Device => "config/device_registry/list",
+            Registry::Entity => "config/entity_registry/list",
+        }
+    }
+
+    /// Event fired by Home Assistant whenever the registry changes
+    pub fn updated_event(self) -> &'static str {
+        match self {
+            Registry::Area => "area_registry_updated",
+            Registry::Device => "device_registry_updated",
+            Registry::Entity => "entity_registry_updated",
+        }
+    }
+
+    pub fn from_updated_event(event_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|registry| registry.updated_event() == event_type)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Area {
+    pub area_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub floor_id: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Name given in the UI, preferred over `name`
+    #[serde(default)]
+    pub name_by_user: Option<String>,
+    #[serde(default)]
+    pub area_id: Option<String>,
+    #[serde(default)]
+    pub manufacturer: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub disabled_by: Option<String>,
+}
+
+impl Device {
+    pub fn display_name(&self) -> Option<&str> {
+        self.name_by_user.as_deref().or(self.name.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityEntry {
+    pub entity_id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub platform: Option<String>,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Set when the entity is moved away from its device's area
+    #[serde(default)]
+    pub area_id: Option<String>,
+    #[serde(default)]
+    pub disabled_by: Option<String>,
+    #[serde(default)]
+    pub hidden_by: Option<String>,
+    #[serde(default)]
+    pub entity_category: Option<String>,
+}
+
+impl EntityEntry {
+    pub fn domain(&self) -> &str {
+        self.entity_id
+            .split_once('.')
+            .map_or("", |(domain, _)| domain)
+    }
+}
+
+/// An area together with the entities placed in it, for grouped entity pickers.
+#[derive(Debug, Clone, Serialize)]
+pub struct AreaEntities {
+    #[serde(flatten)]
+    pub area: Area,
+    pub entities: Vec<String>,
+}
+
+/// Areas, devices and entities as last loaded from Home Assistant.
+#[derive(Debug, Clone, Default)]
+pub struct Registries {
+    pub areas: HashMap<String, Area>,
+    pub devices: HashMap<String, Device>,
+    pub entities: HashMap<String, EntityEntry>,
+}
+
+impl Registries {
+    /// Replace one registry with the result of its list command.
+    pub fn load(&mut self, registry: Registry, result: Value) -> Result<(), serde_json::Error> {
+        match registry {
+            Registry::Area => {
+                let areas: Vec<Area> = serde_json::from_value(result)?;
+                self.areas = areas
+                    .into_iter()
+                    .map(|area| (area.area_id.clone(), area))
+                    .collect();
+            }
+            Registry::Device => {
+                let devices: Vec<Device> = serde_json::from_value(result)?;
+                self.devices = devices
+                    .into_iter()
+                    .map(|device| (device.id.clone(), device))
+                    .collect();
+            }
+            Registry::Entity => {
+                let entities: Vec<EntityEntry> = serde_json::from_value(result)?;
+                self.entities = entities
+                    .into_iter()
+                    .map(|entity| (entity.entity_id.clone(), entity))
+                    .collect();
+            }
+        }
+        Ok(())
+    }
+
+    /// Look up an area by id, name or alias, ignoring case.
+    pub fn find_area(&self, area: &str) -> Option<&Area> {
+        self.areas.get(area).or_else(|| {
+            self.areas.values().find(|candidate| {
+                candidate.name.eq_ignore_ascii_case(area)
+                    || candidate
+                        .aliases
+                        .iter()
+                        .any(|alias| alias.eq_ignore_ascii_case(area))
+            })
+        })
+    }
+
+    /// The entity's own area, falling back to the area of its device.
+    pub fn entity_area_id(&self, entity_id: &str) -> Option<&str> {
+        let entity = self.entities.get(entity_id)?;
+        entity.area_id.as_deref().or_else(|| {
+            entity
+                .device_id
+                .as_ref()
+                .and_then(|device_id| self.devices.get(device_id))
+                .and_then(|device| device.area_id.as_deref())
+        })
+    }
+
+    pub fn entity_device(&self, entity_id: &str) -> Option<&Device> {
+        let device_id = self.entities.get(entity_id)?.device_id.as_ref()?;
+        self.devices.get(device_id)
+    }
+
+    /// Enabled entities in the area, optionally of one domain, sorted by entity id.
+    pub fn area_entities(&self, area_id: &str, domain: Option<&str>) -> Vec<String> {
+        let mut entities: Vec<String> = self
+            .entities
+            .values()
+            .filter(|entity| entity.disabled_by.is_none())
+            .filter(|entity| domain.is_none_or(|domain| entity.domain() == domain))
+            .filter(|entity| self.entity_area_id(&entity.entity_id) == Some(area_id))
+            .map(|entity| entity.entity_id.clone())
+            .collect();
+        entities.sort();
+        entities
+    }
+
+    /// Every area with its enabled entities, sorted by area name.
+    pub fn areas_with_entities(&self) -> Vec<AreaEntities> {
+        let mut by_area: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for entity in self.entities.values() {
+            if entity.disabled_by.is_some() {
+                continue;
+            }
+            if let Some(area_id) = self.entity_area_id(&entity.entity_id) {
+                by_area
+                    .entry(area_id)
+                    .or_default()
+                    .push(entity.entity_id.clone());
+            }
+        }
+
+        let mut areas: Vec<AreaEntities> = self
+            .areas
+            .values()
+            .map(|area| {
+                let mut entities = by_area.remove(area.area_id.as_str()).unwrap_or_default();
+                entities.sort();
+                AreaEntities {
+                    area: area.clone(),
+                    entities,
+                }
+            })
+            .collect();
+        areas.sort_by(|a, b| a.area.name.cmp(&b.area.name));
+        areas
+    }
+}
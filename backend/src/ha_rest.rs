@@ -0,0 +1,210 @@
+use crate::error::HaRestError;
+use crate::ha_client::{EntityState, HaEndpoint};
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::{Response, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One entry of the logbook, as returned by `/api/logbook`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogbookEntry {
+    pub when: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub entity_id: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// User who caused the entry, if any
+    #[serde(default)]
+    pub context_user_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryEntry {
+    entity_id: String,
+    #[serde(flatten)]
+    state: EntityState,
+}
+
+/// REST companion to the websocket client, for history, logbook and templates.
+#[derive(Debug, Clone)]
+pub struct HaRestClient {
+    http: reqwest::Client,
+    /// Everything before `/api`, e.g. `http://supervisor/core`
+    base_url: Url,
+    token: String,
+}
+
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl HaRestClient {
+    /// The REST API next to a websocket endpoint, trusting the same CA certificates.
+    pub fn from_endpoint(endpoint: &HaEndpoint, token: String) -> Result<Self, Box<dyn Error>> {
+        let mut base_url = endpoint.url.clone();
+        let scheme = if endpoint.is_tls() { "https" } else { "http" };
+        base_url
+            .set_scheme(scheme)
+            .map_err(|_| format!("Cannot use {} for {}", scheme, endpoint.url))?;
+        // `/api/websocket` directly, `/core/websocket` behind the Supervisor
+        let path = base_url.path().trim_end_matches('/');
+        let path = path.strip_suffix("/websocket").unwrap_or(path);
+        let path = path.strip_suffix("/api").unwrap_or(path).to_string();
+        base_url.set_path(&path);
+
+        let mut http = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(path) = &endpoint.ca_cert {
+            let pem = std::fs::read(path)
+                .map_err(|e| format!("Failed to read CA certificate {}: {}", path.display(), e))?;
+            for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+                http = http.add_root_certificate(cert);
+            }
+        }
+        Ok(Self {
+            http: http.build()?,
+            base_url,
+            token,
+        })
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// URL of `/api/<segments>`, each segment escaped.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("HTTP URLs have a path")
+            .pop_if_empty()
+            .push("api")
+            .extend(segments);
+        url
+    }
+
+    async fn check(response: Response) -> Result<Response, HaRestError> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        // Errors usually come as `{"message": "..."}`
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|body| body["message"].as_str().map(str::to_string))
+            .unwrap_or(body);
+        Err(HaRestError::Status {
+            status: status.as_u16(),
+            message,
+        })
+    }
+
+    /// States of the given entities between `start` and `end` (now if `None`), oldest first.
+    ///
+    /// The first state of each entity is the one it had at `start`.
+    pub async fn history(
+        &self,
+        entity_ids: &[String],
+        start: DateTime<Utc>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<BTreeMap<String, Vec<EntityState>>, HaRestError> {
+        if entity_ids.is_empty() {
+            return Err(HaRestError::InvalidQuery(
+                "At least one entity is required".to_string(),
+            ));
+        }
+        let mut query = vec![("filter_entity_id", entity_ids.join(","))];
+        if let Some(end) = end {
+            query.push(("end_time", timestamp(&end)));
+        }
+
+        let response = self
+            .http
+            .get(self.url(&["history", "period", &timestamp(&start)]))
+            .bearer_auth(&self.token)
+            .query(&query)
+            .send()
+            .await?;
+        let series: Vec<Vec<HistoryEntry>> = Self::check(response).await?.json().await?;
+
+        let mut history = BTreeMap::new();
+        for entry in series.into_iter().flatten() {
+            history
+                .entry(entry.entity_id)
+                .or_insert_with(Vec::new)
+                .push(entry.state);
+        }
+        Ok(history)
+    }
+
+    /// The state an entity had at `time`, `None` if it did not exist yet.
+    pub async fn state_at(
+        &self,
+        entity_id: &str,
+        time: DateTime<Utc>,
+    ) -> Result<Option<EntityState>, HaRestError> {
+        let mut history = self
+            .history(&[entity_id.to_string()], time, Some(time))
+            .await?;
+        Ok(history
+            .remove(entity_id)
+            .and_then(|states| states.into_iter().next()))
+    }
+
+    /// Logbook entries between `start` and `end`, optionally of one entity.
+    pub async fn logbook(
+        &self,
+        start: DateTime<Utc>,
+        end: Option<DateTime<Utc>>,
+        entity_id: Option<&str>,
+    ) -> Result<Vec<LogbookEntry>, HaRestError> {
+        let mut query = Vec::new();
+        if let Some(entity_id) = entity_id {
+            query.push(("entity", entity_id.to_string()));
+        }
+        if let Some(end) = end {
+            query.push(("end_time", timestamp(&end)));
+        }
+
+        let response = self
+            .http
+            .get(self.url(&["logbook", &timestamp(&start)]))
+            .bearer_auth(&self.token)
+            .query(&query)
+            .send()
+            .await?;
+        Ok(Self::check(response).await?.json().await?)
+    }
+
+    /// Render a Jinja template in Home Assistant.
+    pub async fn render_template(
+        &self,
+        template: &str,
+        variables: Option<Value>,
+    ) -> Result<String, HaRestError> {
+        let mut body = json!({ "template": template });
+        if let Some(variables) = variables {
+            body["variables"] = variables;
+        }
+
+        let response = self
+            .http
+            .post(self.url(&["template"]))
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()
+            .await?;
+        Ok(Self::check(response).await?.text().await?)
+    }
+}
@@ -0,0 +1,608 @@
+use crate::ha_backend::{BackendError, HaBackend};
+use crate::ha_client::{
+    Action, ActionField, EntityState, EventContext, EventSubscription, HaEvent, StateChange,
+    TemplateSubscription,
+};
+use crate::ha_health::{ConnectionHealth, ConnectionStatus};
+use crate::ha_registry::{Area, EntityEntry, Registries};
+use crate::ha_subscription::{EntityFilter, StateSubscribers, StateSubscription, DEFAULT_CAPACITY};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+
+/// How often the running clock moves forward.
+const CLOCK_TICK: Duration = Duration::from_secs(1);
+
+/// Services the simulator knows, by domain.
+const SERVICES: &[(&str, &[&str])] = &[
+    ("homeassistant", &["turn_on", "turn_off", "toggle"]),
+    ("light", &["turn_on", "turn_off", "toggle"]),
+    ("switch", &["turn_on", "turn_off", "toggle"]),
+    ("fan", &["turn_on", "turn_off", "toggle"]),
+    ("input_boolean", &["turn_on", "turn_off", "toggle"]),
+    ("cover", &["open_cover", "close_cover"]),
+    ("lock", &["lock", "unlock"]),
+    ("input_number", &["set_value"]),
+    ("input_text", &["set_value"]),
+    ("input_select", &["select_option"]),
+    ("climate", &["set_temperature", "set_hvac_mode"]),
+    ("button", &["press"]),
+    ("notify", &["notify"]),
+];
+
+/// Light attributes taken over from the data of `turn_on`
+const LIGHT_ATTRIBUTES: [&str; 4] = ["brightness", "color_temp_kelvin", "rgb_color", "effect"];
+
+/// A small house to try automations against when no configuration is given.
+const DEMO_CONFIG: &str = r#"
+entities:
+  - entity_id: light.living_room
+    state: "off"
+    area: Living Room
+    attributes: { friendly_name: Living Room Light }
+  - entity_id: climate.thermostat
+    state: heat
+    area: Living Room
+    attributes: { friendly_name: Thermostat, temperature: 21, current_temperature: 20.5 }
+  - entity_id: switch.coffee_maker
+    state: "off"
+    area: Kitchen
+    attributes: { friendly_name: Coffee Maker }
+  - entity_id: binary_sensor.front_door
+    state: "off"
+    area: Hallway
+    attributes: { friendly_name: Front Door, device_class: door }
+    repeat: true
+    script:
+      - { after: 300, state: "on" }
+      - { after: 20, state: "off" }
+  - entity_id: sensor.outdoor_temperature
+    state: "18.5"
+    attributes: { friendly_name: Outdoor Temperature, device_class: temperature, unit_of_measurement: "°C" }
+    repeat: true
+    script:
+      - { after: 600, state: "19.0" }
+      - { after: 600, state: "19.5" }
+      - { after: 600, state: "19.0" }
+      - { after: 600, state: "18.5" }
+  - entity_id: input_boolean.guest_mode
+    state: "off"
+    attributes: { friendly_name: Guest Mode }
+"#;
+
+/// One scripted change of an entity.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptStep {
+    /// Seconds after the previous step, or after the start for the first one
+    pub after: u64,
+    pub state: String,
+    /// Merged into the current attributes
+    #[serde(default)]
+    pub attributes: HashMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulatedEntity {
+    pub entity_id: String,
+    pub state: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, Value>,
+    /// Name of the entity's area; areas are created as needed
+    #[serde(default)]
+    pub area: Option<String>,
+    #[serde(default)]
+    pub script: Vec<ScriptStep>,
+    /// Start the script over after its last step
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+/// Entities of a simulated Home Assistant, usually read from YAML.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SimulatorConfig {
+    #[serde(default)]
+    pub entities: Vec<SimulatedEntity>,
+}
+
+impl SimulatorConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(serde_yaml::from_str(&yaml)?)
+    }
+
+    pub fn demo() -> Self {
+        serde_yaml::from_str(DEMO_CONFIG).expect("demo configuration is valid")
+    }
+}
+
+#[derive(Debug)]
+struct Script {
+    entity_id: String,
+    steps: Vec<ScriptStep>,
+    repeat: bool,
+    next: usize,
+    /// Simulated time at which the next step applies
+    due: Duration,
+}
+
+#[derive(Debug)]
+struct World {
+    /// Simulated time since the start
+    elapsed: Duration,
+    states: HashMap<String, EntityState>,
+    scripts: Vec<Script>,
+    contexts: u64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    start: DateTime<Utc>,
+    world: Mutex<World>,
+    actions: HashMap<String, Action>,
+    registries: Registries,
+    state_subscribers: StateSubscribers,
+    event_tx: broadcast::Sender<HaEvent>,
+    services_revision: watch::Sender<u64>,
+    health: ConnectionHealth,
+}
+
+/// Home Assistant simulated in process, for local development and tests without a real instance.
+///
+/// Entities follow their scripts as the clock moves, either by [`HaSimulator::advance`]
+/// or in real time with [`HaSimulator::run_clock`], and services change their states.
+#[derive(Debug, Clone)]
+pub struct HaSimulator {
+    inner: Arc<Inner>,
+}
+
+fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn domain_of(entity_id: &str) -> &str {
+    entity_id.split_once('.').map_or("", |(domain, _)| domain)
+}
+
+fn service_fields(service: &str) -> HashMap<String, ActionField> {
+    let field = |name: &str, selector: Value| ActionField {
+        name: name.to_string(),
+        description: None,
+        required: Some(true),
+        selector: Some(selector),
+        default: None,
+        example: None,
+    };
+    let fields = match service {
+        "set_value" => vec![("value", field("Value", json!({ "text": null })))],
+        "select_option" => vec![("option", field("Option", json!({ "text": null })))],
+        "set_temperature" => vec![(
+            "temperature",
+            field("Temperature", json!({ "number": { "step": 0.5 } })),
+        )],
+        "set_hvac_mode" => vec![("hvac_mode", field("HVAC mode", json!({ "text": null })))],
+        "notify" => vec![("message", field("Message", json!({ "text": null })))],
+        _ => Vec::new(),
+    };
+    fields
+        .into_iter()
+        .map(|(key, field)| (key.to_string(), field))
+        .collect()
+}
+
+fn service_catalog() -> HashMap<String, Action> {
+    let mut actions = HashMap::new();
+    for (domain, services) in SERVICES {
+        for service in *services {
+            let id = format!("{}.{}", domain, service);
+            let target = match *domain {
+                "notify" => None,
+                "homeassistant" => Some(json!({ "entity": {} })),
+                domain => Some(json!({ "entity": { "domain": domain } })),
+            };
+            actions.insert(
+                id.clone(),
+                Action {
+                    domain: Some(domain.to_string()),
+                    name: Some(service.replace('_', " ")),
+                    description: None,
+                    target,
+                    fields: service_fields(service),
+                    id: Some(id),
+                },
+            );
+        }
+    }
+    actions
+}
+
+/// The state and attributes an entity has after the service, `None` if it is left alone.
+fn service_effect(
+    service: &str,
+    current: &EntityState,
+    data: &Value,
+    now: &str,
+) -> Option<(String, HashMap<String, Value>)> {
+    let mut attributes = HashMap::new();
+    let state = match service {
+        "turn_on" => {
+            for attribute in LIGHT_ATTRIBUTES {
+                if let Some(value) = data.get(attribute) {
+                    attributes.insert(attribute.to_string(), value.clone());
+                }
+            }
+            "on".to_string()
+        }
+        "turn_off" => "off".to_string(),
+        "toggle" if current.state == "on" => "off".to_string(),
+        "toggle" => "on".to_string(),
+        "open_cover" => "open".to_string(),
+        "close_cover" => "closed".to_string(),
+        "lock" => "locked".to_string(),
+        "unlock" => "unlocked".to_string(),
+        "set_value" => value_string(data.get("value")?),
+        "select_option" => value_string(data.get("option")?),
+        "set_hvac_mode" => value_string(data.get("hvac_mode")?),
+        "set_temperature" => {
+            attributes.insert("temperature".to_string(), data.get("temperature")?.clone());
+            current.state.clone()
+        }
+        // Buttons remember when they were last pressed
+        "press" => now.to_string(),
+        _ => return None,
+    };
+    Some((state, attributes))
+}
+
+fn value_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// `entity_id` of service data, a single id or a list.
+fn target_entities(data: &Value) -> Vec<String> {
+    match data.get("entity_id") {
+        Some(Value::String(entity_id)) => vec![entity_id.clone()],
+        Some(Value::Array(entity_ids)) => entity_ids
+            .iter()
+            .filter_map(|entity_id| entity_id.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl HaSimulator {
+    pub fn new(config: SimulatorConfig) -> Result<Self, Box<dyn Error>> {
+        let start = Utc::now();
+        let now = start.to_rfc3339();
+        let mut states = HashMap::new();
+        let mut scripts = Vec::new();
+        let mut registries = Registries::default();
+
+        for entity in config.entities {
+            if domain_of(&entity.entity_id).is_empty() {
+                return Err(format!("Invalid entity id {}", entity.entity_id).into());
+            }
+            let state = EntityState {
+                state: entity.state,
+                attributes: entity.attributes,
+                last_changed: now.clone(),
+                last_updated: now.clone(),
+                last_reported: Some(now.clone()),
+                context: None,
+            };
+            if states.insert(entity.entity_id.clone(), state).is_some() {
+                return Err(format!("Entity {} is defined twice", entity.entity_id).into());
+            }
+
+            let area_id = entity.area.map(|name| {
+                let area_id = slug(&name);
+                registries
+                    .areas
+                    .entry(area_id.clone())
+                    .or_insert_with(|| Area {
+                        area_id: area_id.clone(),
+                        name,
+                        aliases: Vec::new(),
+                        floor_id: None,
+                        icon: None,
+                    });
+                area_id
+            });
+            registries.entities.insert(
+                entity.entity_id.clone(),
+                EntityEntry {
+                    entity_id: entity.entity_id.clone(),
+                    name: None,
+                    platform: Some("simulator".to_string()),
+                    device_id: None,
+                    area_id,
+                    disabled_by: None,
+                    hidden_by: None,
+                    entity_category: None,
+                },
+            );
+
+            if let Some(first) = entity.script.first() {
+                // A script taking no time at all would repeat forever
+                let repeat = entity.repeat && entity.script.iter().any(|step| step.after > 0);
+                scripts.push(Script {
+                    entity_id: entity.entity_id,
+                    due: Duration::from_secs(first.after),
+                    steps: entity.script,
+                    repeat,
+                    next: 0,
+                });
+            }
+        }
+
+        let (event_tx, _) = broadcast::channel(100);
+        let health = ConnectionHealth::default();
+        health.connected();
+        Ok(Self {
+            inner: Arc::new(Inner {
+                start,
+                world: Mutex::new(World {
+                    elapsed: Duration::ZERO,
+                    states,
+                    scripts,
+                    contexts: 0,
+                }),
+                actions: service_catalog(),
+                registries,
+                state_subscribers: StateSubscribers::default(),
+                event_tx,
+                services_revision: watch::Sender::new(0),
+                health,
+            }),
+        })
+    }
+
+    /// Simulated current time.
+    pub fn now(&self) -> DateTime<Utc> {
+        let elapsed = self.inner.world.lock().unwrap().elapsed;
+        self.time_at(elapsed)
+    }
+
+    fn time_at(&self, elapsed: Duration) -> DateTime<Utc> {
+        self.inner.start + chrono::Duration::from_std(elapsed).expect("simulated time is in range")
+    }
+
+    /// Move the clock forward, applying every script step that falls due on the way in order.
+    pub fn advance(&self, by: Duration) {
+        let mut world = self.inner.world.lock().unwrap();
+        let until = world.elapsed + by;
+        loop {
+            let next = world
+                .scripts
+                .iter()
+                .enumerate()
+                .filter(|(_, script)| script.next < script.steps.len() && script.due <= until)
+                .min_by_key(|(_, script)| script.due)
+                .map(|(index, _)| index);
+            let Some(index) = next else {
+                break;
+            };
+
+            let script = &mut world.scripts[index];
+            let step = script.steps[script.next].clone();
+            let entity_id = script.entity_id.clone();
+            let due = script.due;
+            script.next += 1;
+            if script.next == script.steps.len() && script.repeat {
+                script.next = 0;
+            }
+            if let Some(step) = script.steps.get(script.next) {
+                script.due += Duration::from_secs(step.after);
+            }
+
+            world.elapsed = due;
+            let context = self.new_context(&mut world, None);
+            self.set_state(&mut world, &entity_id, step.state, step.attributes, context);
+        }
+        world.elapsed = until;
+    }
+
+    /// Advance the clock in the background, `speed` simulated seconds per real second.
+    pub fn run_clock(&self, speed: f64) -> JoinHandle<()> {
+        let simulator = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CLOCK_TICK);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                simulator.advance(CLOCK_TICK.mul_f64(speed));
+            }
+        })
+    }
+
+    fn new_context(&self, world: &mut World, user_id: Option<String>) -> EventContext {
+        world.contexts += 1;
+        EventContext {
+            id: format!("simulator-{}", world.contexts),
+            parent_id: None,
+            user_id,
+        }
+    }
+
+    fn fire(&self, world: &World, event_type: &str, data: Value, context: EventContext) {
+        self.inner.health.event_received();
+        let _ = self.inner.event_tx.send(HaEvent {
+            event_type: event_type.to_string(),
+            data,
+            origin: Some("LOCAL".to_string()),
+            time_fired: Some(self.time_at(world.elapsed).to_rfc3339()),
+            context: Some(context),
+        });
+    }
+
+    /// Set a state like Home Assistant does, firing `state_changed` unless nothing changed.
+    fn set_state(
+        &self,
+        world: &mut World,
+        entity_id: &str,
+        state: String,
+        attributes: HashMap<String, Value>,
+        context: EventContext,
+    ) {
+        let now = self.time_at(world.elapsed).to_rfc3339();
+        let old_state = world.states.get(entity_id).cloned();
+        let mut merged = old_state
+            .as_ref()
+            .map(|old| old.attributes.clone())
+            .unwrap_or_default();
+        merged.extend(attributes);
+
+        let state_changed = old_state.as_ref().is_none_or(|old| old.state != state);
+        let changed = state_changed
+            || old_state
+                .as_ref()
+                .is_some_and(|old| old.attributes != merged);
+        if !changed {
+            if let Some(current) = world.states.get_mut(entity_id) {
+                current.last_reported = Some(now);
+            }
+            return;
+        }
+
+        let new_state = EntityState {
+            state,
+            attributes: merged,
+            last_changed: match &old_state {
+                Some(old) if !state_changed => old.last_changed.clone(),
+                _ => now.clone(),
+            },
+            last_updated: now.clone(),
+            last_reported: Some(now),
+            context: Some(context.clone()),
+        };
+        world
+            .states
+            .insert(entity_id.to_string(), new_state.clone());
+
+        let change = StateChange {
+            entity_id: entity_id.to_string(),
+            old_state,
+            new_state,
+        };
+        self.inner.state_subscribers.dispatch(&change);
+        self.fire(
+            world,
+            "state_changed",
+            json!({
+                "entity_id": change.entity_id,
+                "old_state": change.old_state,
+                "new_state": change.new_state,
+            }),
+            context,
+        );
+    }
+}
+
+#[async_trait]
+impl HaBackend for HaSimulator {
+    async fn get_state(&self, entity_id: &str) -> Option<EntityState> {
+        self.inner
+            .world
+            .lock()
+            .unwrap()
+            .states
+            .get(entity_id)
+            .cloned()
+    }
+
+    async fn get_all_states(&self) -> HashMap<String, EntityState> {
+        self.inner.world.lock().unwrap().states.clone()
+    }
+
+    async fn get_all_actions(&self) -> HashMap<String, Action> {
+        self.inner.actions.clone()
+    }
+
+    fn watch_services(&self) -> watch::Receiver<u64> {
+        self.inner.services_revision.subscribe()
+    }
+
+    async fn get_registries(&self) -> Registries {
+        self.inner.registries.clone()
+    }
+
+    async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        data: Value,
+    ) -> Result<(), BackendError> {
+        let id = format!("{}.{}", domain, service);
+        if !self.inner.actions.contains_key(&id) {
+            return Err(format!("Service {} not found", id).into());
+        }
+
+        let mut world = self.inner.world.lock().unwrap();
+        let entity_ids = target_entities(&data);
+        for entity_id in &entity_ids {
+            let entity_domain = domain_of(entity_id);
+            if !world.states.contains_key(entity_id) {
+                return Err(format!("Entity {} not found", entity_id).into());
+            }
+            if domain != "homeassistant" && entity_domain != domain {
+                return Err(format!("{} cannot act on {}", id, entity_id).into());
+            }
+        }
+
+        let context = self.new_context(&mut world, None);
+        self.fire(
+            &world,
+            "call_service",
+            json!({ "domain": domain, "service": service, "service_data": data }),
+            context.clone(),
+        );
+        let now = self.time_at(world.elapsed).to_rfc3339();
+        for entity_id in entity_ids {
+            let effect = service_effect(service, &world.states[&entity_id], &data, &now);
+            if let Some((state, attributes)) = effect {
+                self.set_state(&mut world, &entity_id, state, attributes, context.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe_states(&self, filter: EntityFilter) -> StateSubscription {
+        self.inner
+            .state_subscribers
+            .subscribe(filter, DEFAULT_CAPACITY)
+    }
+
+    async fn subscribe_events(&self, event_type: &str) -> Result<EventSubscription, BackendError> {
+        Ok(EventSubscription::new(
+            event_type,
+            self.inner.event_tx.subscribe(),
+        ))
+    }
+
+    /// Templates are rendered by Home Assistant, which the simulator does not embed.
+    async fn subscribe_template(
+        &self,
+        _template: &str,
+        _variables: Option<Value>,
+    ) -> Result<TemplateSubscription, BackendError> {
+        Err("Templates need a real Home Assistant".into())
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.inner.health.status()
+    }
+}
@@ -0,0 +1,229 @@
+use crate::ha_client::StateChange;
+use std::collections::{btree_map::Entry, BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::Notify;
+
+/// Changes queued per subscriber before they are merged into a gap.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// One entity pattern: `light.kitchen`, a whole domain such as `light`, or a glob like `sensor.*_temperature`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntityPattern {
+    Entity(String),
+    Domain(String),
+    Glob(String),
+}
+
+impl EntityPattern {
+    pub fn parse(pattern: &str) -> Self {
+        let pattern = pattern.trim();
+        if pattern.contains(['*', '?']) {
+            EntityPattern::Glob(pattern.to_string())
+        } else if pattern.contains('.') {
+            EntityPattern::Entity(pattern.to_string())
+        } else {
+            EntityPattern::Domain(pattern.to_string())
+        }
+    }
+
+    pub fn matches(&self, entity_id: &str) -> bool {
+        match self {
+            EntityPattern::Entity(id) => id == entity_id,
+            EntityPattern::Domain(domain) => entity_id
+                .split_once('.')
+                .is_some_and(|(entity_domain, _)| entity_domain == domain),
+            EntityPattern::Glob(glob) => glob_matches(glob.as_bytes(), entity_id.as_bytes()),
+        }
+    }
+}
+
+/// `*` matches any run of characters, `?` a single one.
+fn glob_matches(glob: &[u8], text: &[u8]) -> bool {
+    match glob.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob_matches(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_matches(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && glob_matches(rest, &text[1..]),
+    }
+}
+
+/// Entities a subscriber cares about; an empty filter matches every entity.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntityFilter {
+    patterns: Vec<EntityPattern>,
+}
+
+impl EntityFilter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn new<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            patterns: patterns
+                .into_iter()
+                .filter(|pattern| !pattern.as_ref().trim().is_empty())
+                .map(|pattern| EntityPattern::parse(pattern.as_ref()))
+                .collect(),
+        }
+    }
+
+    pub fn matches(&self, entity_id: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(entity_id))
+    }
+}
+
+/// What a [`StateSubscription`] delivers.
+#[derive(Debug, Clone)]
+pub enum StateUpdate {
+    Changed(Box<StateChange>),
+    /// The subscriber fell behind. Instead of dropping changes, those that did not fit are
+    /// merged into one net change per entity, from the state before the gap to the latest one.
+    Lagged {
+        changes: Vec<StateChange>,
+        /// Intermediate changes merged away
+        skipped: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    changes: VecDeque<StateChange>,
+    gap: BTreeMap<String, StateChange>,
+    skipped: usize,
+}
+
+impl Queue {
+    fn merge(&mut self, change: &StateChange) {
+        match self.gap.entry(change.entity_id.clone()) {
+            Entry::Occupied(mut merged) => {
+                merged.get_mut().new_state = change.new_state.clone();
+                self.skipped += 1;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(change.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    filter: EntityFilter,
+    capacity: usize,
+    queue: Mutex<Queue>,
+    notify: Notify,
+}
+
+impl Subscriber {
+    fn push(&self, change: &StateChange) {
+        let mut queue = self.queue.lock().unwrap();
+        // Once a gap is open, newer changes go there too to keep them in order
+        if queue.gap.is_empty() && queue.changes.len() < self.capacity {
+            queue.changes.push_back(change.clone());
+        } else {
+            queue.merge(change);
+        }
+        drop(queue);
+        self.notify.notify_one();
+    }
+
+    /// Merge a change into the gap, as if the subscriber had fallen behind.
+    fn push_gap(&self, change: &StateChange) {
+        self.queue.lock().unwrap().merge(change);
+        self.notify.notify_one();
+    }
+
+    fn pop(&self) -> Option<StateUpdate> {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(change) = queue.changes.pop_front() {
+            return Some(StateUpdate::Changed(Box::new(change)));
+        }
+        if queue.gap.is_empty() {
+            return None;
+        }
+        Some(StateUpdate::Lagged {
+            changes: std::mem::take(&mut queue.gap).into_values().collect(),
+            skipped: std::mem::take(&mut queue.skipped),
+        })
+    }
+}
+
+/// State changes of the entities matching a filter, see [`HaClient::subscribe_states`].
+///
+/// [`HaClient::subscribe_states`]: crate::ha_client::HaClient::subscribe_states
+#[derive(Debug)]
+pub struct StateSubscription {
+    subscriber: Arc<Subscriber>,
+}
+
+impl StateSubscription {
+    /// Wait for the next update.
+    pub async fn recv(&mut self) -> StateUpdate {
+        loop {
+            if let Some(update) = self.subscriber.pop() {
+                return update;
+            }
+            self.subscriber.notify.notified().await;
+        }
+    }
+
+    /// The next update if one is waiting.
+    pub fn try_recv(&mut self) -> Option<StateUpdate> {
+        self.subscriber.pop()
+    }
+}
+
+/// Fans state changes out to subscriptions; dropped subscriptions are forgotten.
+#[derive(Debug, Clone, Default)]
+pub struct StateSubscribers {
+    subscribers: Arc<Mutex<Vec<Weak<Subscriber>>>>,
+}
+
+impl StateSubscribers {
+    pub fn subscribe(&self, filter: EntityFilter, capacity: usize) -> StateSubscription {
+        let subscriber = Arc::new(Subscriber {
+            filter,
+            capacity: capacity.max(1),
+            queue: Mutex::new(Queue::default()),
+            notify: Notify::new(),
+        });
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&subscriber));
+        StateSubscription { subscriber }
+    }
+
+    pub fn dispatch(&self, change: &StateChange) {
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            let Some(subscriber) = subscriber.upgrade() else {
+                return false;
+            };
+            if subscriber.filter.matches(&change.entity_id) {
+                subscriber.push(change);
+            }
+            true
+        });
+    }
+
+    /// Hand over changes whose steps are unknown, e.g. those missed while disconnected;
+    /// subscribers get them as one [`StateUpdate::Lagged`].
+    pub fn dispatch_lagged(&self, changes: &[StateChange]) {
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            let Some(subscriber) = subscriber.upgrade() else {
+                return false;
+            };
+            for change in changes {
+                if subscriber.filter.matches(&change.entity_id) {
+                    subscriber.push_gap(change);
+                }
+            }
+            true
+        });
+    }
+}
@@ -0,0 +1,1090 @@
+use crate::automation::{Automation, AutomationCreate, AutomationStore};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+/// An entry of Home Assistant's `automations.yaml`.
+///
+/// Both the current plural keys (`triggers`, `actions`, ...) and the older singular ones are read.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HaAutomation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(
+        default,
+        rename = "triggers",
+        alias = "trigger",
+        deserialize_with = "one_or_many"
+    )]
+    pub triggers: Vec<Value>,
+    #[serde(
+        default,
+        rename = "conditions",
+        alias = "condition",
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub conditions: Vec<Value>,
+    #[serde(
+        default,
+        rename = "actions",
+        alias = "action",
+        deserialize_with = "one_or_many"
+    )]
+    pub actions: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// Keys without a Blockly equivalent, such as `variables`
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+/// HA accepts a single item wherever a list is expected.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Null => Vec::new(),
+        Value::Array(items) => items,
+        item => vec![item],
+    })
+}
+
+fn as_list(value: Option<&Value>) -> Vec<Value> {
+    match value {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(items)) => items.clone(),
+        Some(item) => vec![item.clone()],
+    }
+}
+
+/// Entity ids given as a string, a comma-separated string or a list.
+fn entity_ids(value: Option<&Value>) -> Result<Vec<String>, String> {
+    let mut ids = Vec::new();
+    for item in as_list(value) {
+        match item {
+            Value::String(s) => ids.extend(
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string),
+            ),
+            other => return Err(format!("Invalid entity_id: {}", other)),
+        }
+    }
+    if ids.is_empty() {
+        return Err("entity_id is required".to_string());
+    }
+    Ok(ids)
+}
+
+/// A `value_template`, kept as is for the template blocks.
+fn template(item: &Map<String, Value>) -> Result<String, String> {
+    match item.get("value_template") {
+        Some(Value::String(template)) => Ok(template.clone()),
+        Some(_) => Err("`value_template` must be a string".to_string()),
+        None => Err("`value_template` is required".to_string()),
+    }
+}
+
+/// A scalar as Blockly field text; templates have no Blockly equivalent outside the template blocks.
+fn scalar(value: &Value, key: &str) -> Result<String, String> {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => return Err(format!("`{}` must be a single value", key)),
+    };
+    if text.contains("{{") {
+        return Err(format!("Templates in `{}` are not supported", key));
+    }
+    Ok(text)
+}
+
+fn number_bound(item: &Map<String, Value>, key: &str) -> Result<String, String> {
+    match item.get(key) {
+        None | Some(Value::Null) => Ok(String::new()),
+        Some(Value::Number(n)) => Ok(n.to_string()),
+        Some(Value::String(s)) if s.parse::<f64>().is_ok() => Ok(s.clone()),
+        Some(_) => Err(format!(
+            "`{}` must be a number; entity references are not supported",
+            key
+        )),
+    }
+}
+
+/// Reject keys the target block cannot express.
+fn check_keys(item: &Map<String, Value>, kind: &str, supported: &[&str]) -> Result<(), String> {
+    // Keys that only affect presentation or tracing in HA
+    const IGNORED: [&str; 3] = ["alias", "id", "enabled"];
+    if item.get("enabled") == Some(&Value::Bool(false)) {
+        return Err("Disabled items are not supported".to_string());
+    }
+    match item
+        .keys()
+        .find(|key| !supported.contains(&key.as_str()) && !IGNORED.contains(&key.as_str()))
+    {
+        Some(key) => Err(format!("`{}` is not supported for {}", key, kind)),
+        None => Ok(()),
+    }
+}
+
+/// A block to be placed in the workspace, before ids are assigned.
+#[derive(Debug, Clone)]
+struct BlockSpec {
+    r#type: &'static str,
+    fields: Map<String, Value>,
+}
+
+impl BlockSpec {
+    fn new(r#type: &'static str, fields: &[(&str, String)]) -> Self {
+        Self {
+            r#type,
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.clone())))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ConditionSpec {
+    Block(BlockSpec),
+    And(Vec<ConditionSpec>),
+    Or(Vec<ConditionSpec>),
+}
+
+fn item_kind<'a>(item: &'a Map<String, Value>, keys: &[&str]) -> Option<&'a str> {
+    keys.iter()
+        .find_map(|key| item.get(*key).and_then(|k| k.as_str()))
+}
+
+fn as_object(value: &Value) -> Result<&Map<String, Value>, String> {
+    value
+        .as_object()
+        .ok_or_else(|| "Template shorthand is not supported".to_string())
+}
+
+fn convert_trigger(trigger: &Value) -> Result<Vec<BlockSpec>, String> {
+    let item = as_object(trigger)?;
+    let kind = item_kind(item, &["trigger", "platform"]).ok_or("Trigger type is missing")?;
+    let keys = |extra: &[&'static str]| [&["trigger", "platform"][..], extra].concat();
+
+    match kind {
+        "state" => {
+            check_keys(item, "state triggers", &keys(&["entity_id", "from", "to"]))?;
+            let to = match item.get("to") {
+                None | Some(Value::Null) => {
+                    return Err("State triggers without `to` are not supported".to_string())
+                }
+                Some(to) => scalar(to, "to")?,
+            };
+            let from = match item.get("from") {
+                None | Some(Value::Null) => None,
+                Some(from) => Some(scalar(from, "from")?),
+            };
+            Ok(entity_ids(item.get("entity_id"))?
+                .into_iter()
+                .map(|entity| {
+                    let mut fields = vec![("ENTITY_ID", entity), ("STATE", to.clone())];
+                    if let Some(from) = &from {
+                        fields.push(("FROM", from.clone()));
+                    }
+                    BlockSpec::new("ha_state_trigger", &fields)
+                })
+                .collect())
+        }
+        "numeric_state" => {
+            check_keys(
+                item,
+                "numeric_state triggers",
+                &keys(&["entity_id", "above", "below"]),
+            )?;
+            let above = number_bound(item, "above")?;
+            let below = number_bound(item, "below")?;
+            Ok(entity_ids(item.get("entity_id"))?
+                .into_iter()
+                .map(|entity| {
+                    BlockSpec::new(
+                        "ha_numeric_state_trigger",
+                        &[
+                            ("ENTITY_ID", entity),
+                            ("ABOVE", above.clone()),
+                            ("BELOW", below.clone()),
+                        ],
+                    )
+                })
+                .collect())
+        }
+        "time" => {
+            check_keys(item, "time triggers", &keys(&["at"]))?;
+            let times = as_list(item.get("at"));
+            if times.is_empty() {
+                return Err("`at` is required".to_string());
+            }
+            times
+                .iter()
+                .map(|at| {
+                    let at = scalar(at, "at")?;
+                    if at.contains('.') {
+                        return Err(format!("Times from entities are not supported: {}", at));
+                    }
+                    Ok(BlockSpec::new("ha_time_trigger", &[("TIME", at)]))
+                })
+                .collect()
+        }
+        "template" => {
+            check_keys(item, "template triggers", &keys(&["value_template"]))?;
+            Ok(vec![BlockSpec::new(
+                "ha_template_trigger",
+                &[("TEMPLATE", template(item)?)],
+            )])
+        }
+        "event" => {
+            check_keys(item, "event triggers", &keys(&["event_type", "event_data"]))?;
+            let data = match item.get("event_data") {
+                None | Some(Value::Null) => Value::Object(Map::new()),
+                Some(data @ Value::Object(_)) => data.clone(),
+                Some(_) => return Err("`event_data` must be a mapping".to_string()),
+            };
+            if data.to_string().contains("{{") {
+                return Err("Templates in `event_data` are not supported".to_string());
+            }
+            let event_types = as_list(item.get("event_type"));
+            if event_types.is_empty() {
+                return Err("`event_type` is required".to_string());
+            }
+            event_types
+                .iter()
+                .map(|event_type| {
+                    Ok(BlockSpec::new(
+                        "ha_event_trigger",
+                        &[
+                            ("EVENT_TYPE", scalar(event_type, "event_type")?),
+                            ("DATA", data.to_string()),
+                        ],
+                    ))
+                })
+                .collect()
+        }
+        other => Err(format!("`{}` triggers are not supported", other)),
+    }
+}
+
+fn convert_condition(condition: &Value) -> Result<ConditionSpec, String> {
+    let item = as_object(condition)?;
+    let kind = item_kind(item, &["condition"]).ok_or("Condition type is missing")?;
+
+    match kind {
+        "state" => {
+            check_keys(
+                item,
+                "state conditions",
+                &["condition", "entity_id", "state"],
+            )?;
+            let states = as_list(item.get("state"));
+            if states.is_empty() {
+                return Err("`state` is required".to_string());
+            }
+            let states = states
+                .iter()
+                .map(|s| scalar(s, "state"))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // All entities must match, each in any of the states
+            let entities = entity_ids(item.get("entity_id"))?
+                .into_iter()
+                .map(|entity| {
+                    ConditionSpec::Or(
+                        states
+                            .iter()
+                            .map(|state| {
+                                ConditionSpec::Block(BlockSpec::new(
+                                    "ha_state_condition",
+                                    &[("ENTITY_ID", entity.clone()), ("STATE", state.clone())],
+                                ))
+                            })
+                            .collect(),
+                    )
+                })
+                .collect();
+            Ok(ConditionSpec::And(entities))
+        }
+        "numeric_state" => {
+            check_keys(
+                item,
+                "numeric_state conditions",
+                &["condition", "entity_id", "above", "below"],
+            )?;
+            let above = number_bound(item, "above")?;
+            let below = number_bound(item, "below")?;
+            Ok(ConditionSpec::And(
+                entity_ids(item.get("entity_id"))?
+                    .into_iter()
+                    .map(|entity| {
+                        ConditionSpec::Block(BlockSpec::new(
+                            "ha_numeric_state_condition",
+                            &[
+                                ("ENTITY_ID", entity),
+                                ("ABOVE", above.clone()),
+                                ("BELOW", below.clone()),
+                            ],
+                        ))
+                    })
+                    .collect(),
+            ))
+        }
+        "time" => {
+            check_keys(item, "time conditions", &["condition", "after", "before"])?;
+            let bound = |key: &str, default: &str| match item.get(key) {
+                None => Ok(default.to_string()),
+                Some(value) => {
+                    let time = scalar(value, key)?;
+                    if time.contains('.') {
+                        return Err(format!("Times from entities are not supported: {}", time));
+                    }
+                    Ok(time)
+                }
+            };
+            if !item.contains_key("after") && !item.contains_key("before") {
+                return Err("`after` or `before` is required".to_string());
+            }
+            Ok(ConditionSpec::Block(BlockSpec::new(
+                "ha_time_condition",
+                &[
+                    ("START_TIME", bound("after", "00:00")?),
+                    ("END_TIME", bound("before", "23:59:59")?),
+                ],
+            )))
+        }
+        "template" => {
+            check_keys(
+                item,
+                "template conditions",
+                &["condition", "value_template"],
+            )?;
+            Ok(ConditionSpec::Block(BlockSpec::new(
+                "ha_template_condition",
+                &[("TEMPLATE", template(item)?)],
+            )))
+        }
+        "and" | "or" => {
+            check_keys(item, "logical conditions", &["condition", "conditions"])?;
+            let conditions = as_list(item.get("conditions"))
+                .iter()
+                .map(convert_condition)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(if kind == "and" {
+                ConditionSpec::And(conditions)
+            } else {
+                ConditionSpec::Or(conditions)
+            })
+        }
+        other => Err(format!("`{}` conditions are not supported", other)),
+    }
+}
+
+fn convert_action(action: &Value) -> Result<Vec<BlockSpec>, String> {
+    let item = as_object(action)?;
+    // `action` replaced `service` in HA 2024.8
+    let Some(service) = item_kind(item, &["action", "service"]) else {
+        let kind = item.keys().find(|k| k.as_str() != "alias");
+        return Err(match kind {
+            Some(kind) => format!("`{}` actions are not supported", kind),
+            None => "Empty action".to_string(),
+        });
+    };
+    check_keys(
+        item,
+        "actions",
+        &["action", "service", "data", "target", "entity_id"],
+    )?;
+    if !service.contains('.') || service.contains("{{") {
+        return Err(format!("Invalid action: {}", service));
+    }
+
+    let mut entities = Vec::new();
+    if let Some(entity_id) = item.get("entity_id") {
+        entities.extend(entity_ids(Some(entity_id))?);
+    }
+    if let Some(target) = item.get("target") {
+        let target = target.as_object().ok_or("`target` must be a mapping")?;
+        check_keys(target, "action targets", &["entity_id"])?;
+        entities.extend(entity_ids(target.get("entity_id"))?);
+    }
+
+    let mut data = match item.get("data") {
+        None | Some(Value::Null) => Map::new(),
+        Some(Value::Object(data)) => data.clone(),
+        Some(_) => return Err("`data` must be a mapping".to_string()),
+    };
+    if serde_json::to_string(&data)
+        .map_err(|e| e.to_string())?
+        .contains("{{")
+    {
+        return Err("Templates in `data` are not supported".to_string());
+    }
+
+    if data.is_empty() && entities.len() == 1 {
+        return Ok(vec![BlockSpec::new(
+            "ha_trigger_action",
+            &[
+                ("SERVICE", service.to_string()),
+                ("ENTITY_ID", entities.remove(0)),
+            ],
+        )]);
+    }
+
+    match entities.len() {
+        0 => {}
+        1 => {
+            data.insert("entity_id".to_string(), Value::String(entities.remove(0)));
+        }
+        _ => {
+            data.insert("entity_id".to_string(), json!(entities));
+        }
+    }
+    Ok(vec![BlockSpec::new(
+        "ha_call_service",
+        &[
+            ("SERVICE", service.to_string()),
+            ("DATA", Value::Object(data).to_string()),
+        ],
+    )])
+}
+
+/// A construct that could not be converted.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportIssue {
+    pub automation: String,
+    /// Location in the HA automation, e.g. `triggers[1]`
+    pub path: String,
+    pub message: String,
+}
+
+/// Result of converting one HA automation.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConvertedAutomation {
+    pub name: String,
+    pub description: Option<String>,
+    pub workspace: Value,
+    pub issues: Vec<ImportIssue>,
+}
+
+/// Assigns unique block ids while laying out the workspace.
+#[derive(Default)]
+struct WorkspaceBuilder {
+    next_id: usize,
+}
+
+impl WorkspaceBuilder {
+    fn block(&mut self, spec: &BlockSpec) -> Map<String, Value> {
+        self.next_id += 1;
+        let mut block = Map::new();
+        block.insert("type".to_string(), json!(spec.r#type));
+        block.insert(
+            "id".to_string(),
+            json!(format!("ha_import_{}", self.next_id)),
+        );
+        if !spec.fields.is_empty() {
+            block.insert("fields".to_string(), Value::Object(spec.fields.clone()));
+        }
+        block
+    }
+
+    /// Statement blocks linked through `next`.
+    fn chain(&mut self, specs: &[BlockSpec]) -> Option<Value> {
+        let mut next: Option<Value> = None;
+        for spec in specs.iter().rev() {
+            let mut block = self.block(spec);
+            if let Some(next) = next {
+                block.insert("next".to_string(), json!({ "block": next }));
+            }
+            next = Some(Value::Object(block));
+        }
+        next
+    }
+
+    fn condition(&mut self, condition: &ConditionSpec) -> Option<Value> {
+        let (op, conditions) = match condition {
+            ConditionSpec::Block(spec) => return Some(Value::Object(self.block(spec))),
+            ConditionSpec::And(conditions) => ("AND", conditions),
+            ConditionSpec::Or(conditions) => ("OR", conditions),
+        };
+
+        // Nest pairs into `logic_operation` blocks, left to right
+        let mut combined: Option<Value> = None;
+        for condition in conditions {
+            let Some(b) = self.condition(condition) else {
+                continue;
+            };
+            combined = Some(match combined {
+                None => b,
+                Some(a) => {
+                    let mut block = self.block(&BlockSpec::new("logic_operation", &[]));
+                    block.insert("fields".to_string(), json!({ "OP": op }));
+                    block.insert(
+                        "inputs".to_string(),
+                        json!({ "A": { "block": a }, "B": { "block": b } }),
+                    );
+                    Value::Object(block)
+                }
+            });
+        }
+        combined
+    }
+
+    /// Conditions guard the actions through a `controls_if` block.
+    fn body(&mut self, conditions: &[ConditionSpec], actions: &[BlockSpec]) -> Option<Value> {
+        let condition = self.condition(&ConditionSpec::And(conditions.to_vec()));
+        let actions = self.chain(actions);
+        match condition {
+            None => actions,
+            Some(condition) => {
+                let mut block = self.block(&BlockSpec::new("controls_if", &[]));
+                block.insert(
+                    "inputs".to_string(),
+                    json!({ "IF0": { "block": condition } }),
+                );
+                if let Some(actions) = actions {
+                    block.insert(
+                        "statements".to_string(),
+                        json!({ "DO0": { "block": actions } }),
+                    );
+                }
+                Some(Value::Object(block))
+            }
+        }
+    }
+}
+
+impl HaAutomation {
+    fn display_name(&self, index: usize) -> String {
+        self.alias
+            .clone()
+            .or_else(|| self.id.clone())
+            .unwrap_or_else(|| format!("Imported automation {}", index + 1))
+    }
+
+    /// Build a Blockly workspace from the existing trigger, condition and action blocks.
+    pub fn to_workspace(&self, index: usize) -> ConvertedAutomation {
+        let name = self.display_name(index);
+        let mut issues = Vec::new();
+        let mut issue = |path: String, message: String| {
+            issues.push(ImportIssue {
+                automation: name.clone(),
+                path,
+                message,
+            })
+        };
+
+        for key in self.other.keys() {
+            issue(key.clone(), format!("`{}` is not supported", key));
+        }
+
+        let mut triggers = Vec::new();
+        for (i, trigger) in self.triggers.iter().enumerate() {
+            match convert_trigger(trigger) {
+                Ok(specs) => triggers.extend(specs),
+                Err(e) => issue(format!("triggers[{}]", i), e),
+            }
+        }
+        let mut conditions = Vec::new();
+        for (i, condition) in self.conditions.iter().enumerate() {
+            match convert_condition(condition) {
+                Ok(spec) => conditions.push(spec),
+                Err(e) => issue(format!("conditions[{}]", i), e),
+            }
+        }
+        let mut actions = Vec::new();
+        for (i, action) in self.actions.iter().enumerate() {
+            match convert_action(action) {
+                Ok(specs) => actions.extend(specs),
+                Err(e) => issue(format!("actions[{}]", i), e),
+            }
+        }
+
+        // One stack per trigger, each running its own copy of the conditions and actions
+        let mut builder = WorkspaceBuilder::default();
+        let mut blocks = Vec::new();
+        if triggers.is_empty() {
+            blocks.extend(builder.body(&conditions, &actions));
+        }
+        for trigger in &triggers {
+            let mut block = builder.block(trigger);
+            if let Some(body) = builder.body(&conditions, &actions) {
+                block.insert("next".to_string(), json!({ "block": body }));
+            }
+            blocks.push(Value::Object(block));
+        }
+        for (i, block) in blocks.iter_mut().enumerate() {
+            block["x"] = json!(20);
+            block["y"] = json!(20 + i * 200);
+        }
+
+        ConvertedAutomation {
+            name,
+            description: self.description.clone(),
+            workspace: json!({ "blocks": { "languageVersion": 0, "blocks": blocks } }),
+            issues,
+        }
+    }
+}
+
+/// Parse `automations.yaml` content: a list of automations or a single one.
+pub fn parse_automations(yaml: &str) -> std::io::Result<Vec<HaAutomation>> {
+    let invalid = |e: serde_yaml::Error| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Failed to parse Home Assistant automations: {}", e),
+        )
+    };
+    match serde_yaml::from_str::<Value>(yaml).map_err(invalid)? {
+        Value::Array(items) => items
+            .into_iter()
+            .map(|item| {
+                serde_json::from_value(item).map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+            })
+            .collect(),
+        Value::Null => Ok(Vec::new()),
+        item => Ok(vec![
+            serde_json::from_value(item).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+        ]),
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HaImportOptions {
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Import automations even if some of their parts had to be left out
+    #[serde(default)]
+    pub allow_partial: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedAutomation {
+    pub name: String,
+    /// Id of the created automation; `None` on dry runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub workspace: Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HaImportReport {
+    pub dry_run: bool,
+    pub imported: Vec<ImportedAutomation>,
+    /// Automations left out because of unsupported constructs or errors
+    pub skipped: Vec<String>,
+    pub issues: Vec<ImportIssue>,
+    pub errors: Vec<String>,
+}
+
+/// Convert HA automations and create them in the store.
+pub async fn import_automations(
+    store: &AutomationStore,
+    yaml: &str,
+    options: HaImportOptions,
+) -> std::io::Result<HaImportReport> {
+    let mut report = HaImportReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+
+    for (index, automation) in parse_automations(yaml)?.iter().enumerate() {
+        let converted = automation.to_workspace(index);
+        let partial = !converted.issues.is_empty();
+        report.issues.extend(converted.issues);
+        if partial && !options.allow_partial {
+            report.skipped.push(converted.name);
+            continue;
+        }
+
+        let mut imported = ImportedAutomation {
+            name: converted.name.clone(),
+            id: None,
+            workspace: converted.workspace.clone(),
+        };
+        if !options.dry_run {
+            let created = store
+                .create(AutomationCreate {
+                    name: converted.name.clone(),
+                    description: converted.description,
+                    triggers: vec![],
+                    workspace: converted.workspace,
+                    conditions: vec![],
+                    tags: vec![],
+                    folder: None,
+                })
+                .await;
+            match created {
+                Ok(created) => imported.id = Some(created.id),
+                Err(e) => {
+                    report.errors.push(format!("{}: {}", converted.name, e));
+                    report.skipped.push(converted.name);
+                    continue;
+                }
+            }
+        }
+        report.imported.push(imported);
+    }
+
+    Ok(report)
+}
+
+/// A block that has no equivalent in HA automation YAML.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportIssue {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_type: Option<String>,
+    pub message: String,
+}
+
+impl ExportIssue {
+    fn new(block: &Value, message: impl Into<String>) -> Self {
+        let text = |key: &str| block.get(key).and_then(|v| v.as_str()).map(str::to_string);
+        Self {
+            block_id: text("id"),
+            block_type: text("type"),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.block_type, &self.block_id) {
+            (Some(t), Some(id)) => write!(f, "{} ({}): {}", t, id, self.message),
+            (Some(t), None) => write!(f, "{}: {}", t, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Top-level blocks in any of the workspace formats the code generator reads.
+fn workspace_blocks(workspace: &Value) -> &[Value] {
+    let blocks = workspace.get("blocks");
+    blocks
+        .and_then(|b| b.get("blocks"))
+        .and_then(|b| b.get("blocks"))
+        .and_then(|b| b.as_array())
+        .or_else(|| {
+            blocks
+                .and_then(|b| b.get("blocks"))
+                .and_then(|b| b.as_array())
+        })
+        .or_else(|| blocks.and_then(|b| b.as_array()))
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn field(block: &Value, name: &str) -> Result<String, ExportIssue> {
+    let value = block
+        .get("fields")
+        .and_then(|f| f.get(name))
+        .map(|v| v.get("value").unwrap_or(v));
+    match value {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(Value::Number(n)) => Ok(n.to_string()),
+        Some(Value::Bool(b)) => Ok(b.to_string()),
+        _ => Err(ExportIssue::new(
+            block,
+            format!("Field {} is missing", name),
+        )),
+    }
+}
+
+fn connected<'a>(block: &'a Value, kind: &str, name: &str) -> Option<&'a Value> {
+    block
+        .get(kind)
+        .and_then(|i| i.get(name))
+        .and_then(|i| i.get("block"))
+}
+
+fn next(block: &Value) -> Option<&Value> {
+    block.get("next").and_then(|n| n.get("block"))
+}
+
+/// Numeric bounds are written as numbers; an empty field leaves the bound out.
+fn insert_bound(
+    item: &mut Map<String, Value>,
+    block: &Value,
+    name: &str,
+    key: &str,
+) -> Result<(), ExportIssue> {
+    let text = field(block, name)?;
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(());
+    }
+    let number: serde_json::Number = text
+        .parse()
+        .map_err(|_| ExportIssue::new(block, format!("{} is not a number: {}", name, text)))?;
+    item.insert(key.to_string(), Value::Number(number));
+    Ok(())
+}
+
+fn export_trigger(block: &Value) -> Result<Value, ExportIssue> {
+    let mut item = Map::new();
+    match block
+        .get("type")
+        .and_then(|t| t.as_str())
+        .unwrap_or_default()
+    {
+        "ha_state_trigger" => {
+            item.insert("trigger".to_string(), json!("state"));
+            item.insert("entity_id".to_string(), json!(field(block, "ENTITY_ID")?));
+            // An empty or missing FROM accepts any previous state
+            if let Some(from) = field(block, "FROM").ok().filter(|from| !from.is_empty()) {
+                item.insert("from".to_string(), json!(from));
+            }
+            item.insert("to".to_string(), json!(field(block, "STATE")?));
+        }
+        "ha_numeric_state_trigger" => {
+            item.insert("trigger".to_string(), json!("numeric_state"));
+            item.insert("entity_id".to_string(), json!(field(block, "ENTITY_ID")?));
+            insert_bound(&mut item, block, "ABOVE", "above")?;
+            insert_bound(&mut item, block, "BELOW", "below")?;
+        }
+        "ha_time_trigger" => {
+            item.insert("trigger".to_string(), json!("time"));
+            item.insert("at".to_string(), json!(field(block, "TIME")?));
+        }
+        "ha_template_trigger" => {
+            item.insert("trigger".to_string(), json!("template"));
+            item.insert(
+                "value_template".to_string(),
+                json!(field(block, "TEMPLATE")?),
+            );
+        }
+        "ha_event_trigger" => {
+            item.insert("trigger".to_string(), json!("event"));
+            item.insert("event_type".to_string(), json!(field(block, "EVENT_TYPE")?));
+            match serde_json::from_str(&field(block, "DATA")?) {
+                Ok(Value::Object(data)) if data.is_empty() => {}
+                Ok(Value::Object(data)) => {
+                    item.insert("event_data".to_string(), Value::Object(data));
+                }
+                _ => return Err(ExportIssue::new(block, "DATA is not a JSON object")),
+            }
+        }
+        _ => return Err(ExportIssue::new(
+            block,
+            "Only state, numeric state, time, template and event triggers can start a block stack",
+        )),
+    }
+    Ok(Value::Object(item))
+}
+
+/// Appends the exported block to `conditions`, a list HA combines with AND.
+fn export_condition(block: &Value, conditions: &mut Vec<Value>) -> Result<(), ExportIssue> {
+    let mut item = Map::new();
+    match block.get("type").and_then(|t| t.as_str()).unwrap_or_default() {
+        "ha_state_condition" => {
+            item.insert("condition".to_string(), json!("state"));
+            item.insert("entity_id".to_string(), json!(field(block, "ENTITY_ID")?));
+            item.insert("state".to_string(), json!(field(block, "STATE")?));
+        }
+        "ha_numeric_state_condition" => {
+            item.insert("condition".to_string(), json!("numeric_state"));
+            item.insert("entity_id".to_string(), json!(field(block, "ENTITY_ID")?));
+            insert_bound(&mut item, block, "ABOVE", "above")?;
+            insert_bound(&mut item, block, "BELOW", "below")?;
+        }
+        "ha_time_condition" => {
+            item.insert("condition".to_string(), json!("time"));
+            item.insert("after".to_string(), json!(field(block, "START_TIME")?));
+            item.insert("before".to_string(), json!(field(block, "END_TIME")?));
+        }
+        "ha_template_condition" => {
+            item.insert("condition".to_string(), json!("template"));
+            item.insert("value_template".to_string(), json!(field(block, "TEMPLATE")?));
+        }
+        "logic_operation" => {
+            let operands = ["A", "B"].map(|name| {
+                connected(block, "inputs", name)
+                    .ok_or_else(|| ExportIssue::new(block, format!("Input {} is empty", name)))
+            });
+            if field(block, "OP")? == "AND" {
+                // `conditions` is combined with AND already, so the operands join it
+                for operand in operands {
+                    export_condition(operand?, conditions)?;
+                }
+                return Ok(());
+            }
+            let mut alternatives = Vec::new();
+            for operand in operands {
+                let mut all = Vec::new();
+                export_condition(operand?, &mut all)?;
+                alternatives.push(if all.len() == 1 {
+                    all.remove(0)
+                } else {
+                    json!({ "condition": "and", "conditions": all })
+                });
+            }
+            item.insert("condition".to_string(), json!("or"));
+            item.insert("conditions".to_string(), Value::Array(alternatives));
+        }
+        _ => {
+            return Err(ExportIssue::new(
+                block,
+                "Not an HA condition; only state, numeric state, time and template checks combined with and/or can be exported",
+            ))
+        }
+    }
+    conditions.push(Value::Object(item));
+    Ok(())
+}
+
+fn export_action(block: &Value) -> Result<Value, ExportIssue> {
+    let service = field(block, "SERVICE")?;
+    match block
+        .get("type")
+        .and_then(|t| t.as_str())
+        .unwrap_or_default()
+    {
+        "ha_trigger_action" => Ok(json!({
+            "action": service,
+            "target": { "entity_id": field(block, "ENTITY_ID")? },
+        })),
+        "ha_call_service" => {
+            let mut data = match serde_json::from_str(&field(block, "DATA")?) {
+                Ok(Value::Object(data)) => data,
+                _ => return Err(ExportIssue::new(block, "DATA is not a JSON object")),
+            };
+            let mut item = Map::new();
+            item.insert("action".to_string(), json!(service));
+            if let Some(entity_id) = data.remove("entity_id") {
+                item.insert("target".to_string(), json!({ "entity_id": entity_id }));
+            }
+            if !data.is_empty() {
+                item.insert("data".to_string(), Value::Object(data));
+            }
+            Ok(Value::Object(item))
+        }
+        _ => Err(ExportIssue::new(
+            block,
+            "Not an HA service call; only action blocks can be exported",
+        )),
+    }
+}
+
+/// Conditions and actions run by a trigger: either an action chain or one `if` around it.
+fn export_body(body: Option<&Value>) -> Result<(Vec<Value>, Vec<Value>), ExportIssue> {
+    let Some(first) = body else {
+        return Ok((Vec::new(), Vec::new()));
+    };
+
+    let mut conditions = Vec::new();
+    let mut action = Some(first);
+    if first.get("type").and_then(|t| t.as_str()) == Some("controls_if") {
+        let statements = first.get("statements").and_then(|s| s.as_object());
+        if statements.is_some_and(|s| s.keys().any(|k| k != "DO0"))
+            || first
+                .get("inputs")
+                .and_then(|i| i.as_object())
+                .is_some_and(|i| i.len() > 1)
+        {
+            return Err(ExportIssue::new(
+                first,
+                "`else if` and `else` branches cannot be expressed as HA conditions",
+            ));
+        }
+        if next(first).is_some() {
+            return Err(ExportIssue::new(
+                first,
+                "Blocks after an `if` cannot be expressed as HA conditions",
+            ));
+        }
+        let condition = connected(first, "inputs", "IF0")
+            .ok_or_else(|| ExportIssue::new(first, "The `if` condition is empty"))?;
+        export_condition(condition, &mut conditions)?;
+        action = connected(first, "statements", "DO0");
+    }
+
+    let mut actions = Vec::new();
+    while let Some(block) = action {
+        actions.push(export_action(block)?);
+        action = next(block);
+    }
+    Ok((conditions, actions))
+}
+
+impl HaAutomation {
+    /// Express an automation in HA's own format, or explain every block that prevents it.
+    pub fn from_automation(automation: &Automation) -> Result<Self, Vec<ExportIssue>> {
+        let mut issues = Vec::new();
+        let mut triggers = Vec::new();
+        let mut body: Option<(Vec<Value>, Vec<Value>)> = None;
+
+        let stacks = workspace_blocks(&automation.workspace);
+        if stacks.is_empty() {
+            issues.push(ExportIssue::new(
+                &Value::Null,
+                "The workspace has no blocks",
+            ));
+        }
+        for stack in stacks {
+            match export_trigger(stack) {
+                Ok(trigger) => triggers.push(trigger),
+                Err(issue) => {
+                    issues.push(issue);
+                    continue;
+                }
+            }
+            match export_body(next(stack)) {
+                Ok(exported) => match &body {
+                    None => body = Some(exported),
+                    // HA runs the same conditions and actions for every trigger
+                    Some(first) if *first == exported => {}
+                    Some(_) => issues.push(ExportIssue::new(
+                        stack,
+                        "Every trigger must run the same conditions and actions",
+                    )),
+                },
+                Err(issue) => issues.push(issue),
+            }
+        }
+
+        let (conditions, actions) = body.unwrap_or_default();
+        if actions.is_empty() && issues.is_empty() {
+            issues.push(ExportIssue::new(
+                &Value::Null,
+                "The automation has no actions",
+            ));
+        }
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+
+        let mut other = BTreeMap::new();
+        if !automation.enabled {
+            other.insert("initial_state".to_string(), Value::Bool(false));
+        }
+        Ok(Self {
+            id: Some(automation.id.clone()),
+            alias: Some(automation.name.clone()),
+            description: automation.description.clone(),
+            triggers,
+            conditions,
+            actions,
+            mode: Some("single".to_string()),
+            other,
+        })
+    }
+}
+
+/// Render automations as an `automations.yaml` list.
+pub fn to_yaml(automations: &[HaAutomation]) -> std::io::Result<String> {
+    serde_yaml::to_string(automations).map_err(Error::other)
+}
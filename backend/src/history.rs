@@ -0,0 +1,324 @@
+use crate::automation::Automation;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Summary of a stored automation version.
+#[derive(Debug, Clone, Serialize)]
+pub struct AutomationVersion {
+    pub version: i32,
+    pub name: String,
+    pub enabled: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<&Automation> for AutomationVersion {
+    fn from(automation: &Automation) -> Self {
+        Self {
+            version: automation.version,
+            name: automation.name.clone(),
+            enabled: automation.enabled,
+            updated_at: automation.updated_at,
+        }
+    }
+}
+
+/// A block as found in a workspace, keyed by its Blockly id.
+#[derive(Debug, Clone, PartialEq)]
+struct WorkspaceBlock {
+    r#type: String,
+    fields: BTreeMap<String, Value>,
+    parent: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockSummary {
+    pub id: String,
+    pub r#type: String,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FieldChange {
+    pub from: Option<Value>,
+    pub to: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockChange {
+    pub id: String,
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_type: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, FieldChange>,
+    /// The block is attached to a different parent block
+    pub moved: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AutomationDiff {
+    pub from: i32,
+    pub to: i32,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, FieldChange>,
+    pub added: Vec<BlockSummary>,
+    pub removed: Vec<BlockSummary>,
+    pub changed: Vec<BlockChange>,
+}
+
+/// Top-level blocks of a workspace in any of the layouts the editor has saved.
+fn top_level_blocks(workspace: &Value) -> &[Value] {
+    let blocks = workspace.get("blocks");
+    blocks
+        .and_then(|b| b.get("blocks"))
+        .and_then(|b| b.get("blocks"))
+        .and_then(|b| b.as_array())
+        .or_else(|| {
+            blocks
+                .and_then(|b| b.get("blocks"))
+                .and_then(|b| b.as_array())
+        })
+        .or_else(|| blocks.and_then(|b| b.as_array()))
+        .map(|b| b.as_slice())
+        .unwrap_or(&[])
+}
+
+fn collect_block(
+    block: &Value,
+    parent: Option<&str>,
+    index: &mut usize,
+    blocks: &mut BTreeMap<String, WorkspaceBlock>,
+) {
+    let Some(block_type) = block.get("type").and_then(|t| t.as_str()) else {
+        return;
+    };
+    // Blocks without an id cannot be matched across versions; give them a positional one
+    let id = match block.get("id").and_then(|i| i.as_str()) {
+        Some(id) => id.to_string(),
+        None => format!("#{}", index),
+    };
+    *index += 1;
+
+    let fields = block
+        .get("fields")
+        .and_then(|f| f.as_object())
+        .map(|fields| {
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), value.get("value").unwrap_or(value).clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    blocks.insert(
+        id.clone(),
+        WorkspaceBlock {
+            r#type: block_type.to_string(),
+            fields,
+            parent: parent.map(str::to_string),
+        },
+    );
+
+    for key in ["inputs", "statements"] {
+        if let Some(children) = block.get(key).and_then(|c| c.as_object()) {
+            for child in children.values() {
+                if let Some(child_block) = child.get("block") {
+                    collect_block(child_block, Some(&id), index, blocks);
+                }
+            }
+        }
+    }
+    if let Some(next) = block.get("next").and_then(|n| n.get("block")) {
+        collect_block(next, Some(&id), index, blocks);
+    }
+}
+
+fn workspace_blocks(workspace: &Value) -> BTreeMap<String, WorkspaceBlock> {
+    let mut blocks = BTreeMap::new();
+    let mut index = 0;
+    for block in top_level_blocks(workspace) {
+        collect_block(block, None, &mut index, &mut blocks);
+    }
+    blocks
+}
+
+fn property_change(
+    properties: &mut BTreeMap<String, FieldChange>,
+    name: &str,
+    from: Value,
+    to: Value,
+) {
+    if from != to {
+        properties.insert(
+            name.to_string(),
+            FieldChange {
+                from: Some(from),
+                to: Some(to),
+            },
+        );
+    }
+}
+
+/// Compare two versions of an automation block by block.
+pub fn diff(from: &Automation, to: &Automation) -> AutomationDiff {
+    let mut properties = BTreeMap::new();
+    property_change(
+        &mut properties,
+        "name",
+        Value::from(from.name.clone()),
+        Value::from(to.name.clone()),
+    );
+    property_change(
+        &mut properties,
+        "description",
+        Value::from(from.description.clone()),
+        Value::from(to.description.clone()),
+    );
+    property_change(
+        &mut properties,
+        "enabled",
+        Value::from(from.enabled),
+        Value::from(to.enabled),
+    );
+    property_change(
+        &mut properties,
+        "tags",
+        Value::from(from.tags.clone()),
+        Value::from(to.tags.clone()),
+    );
+    property_change(
+        &mut properties,
+        "folder",
+        Value::from(from.folder.clone()),
+        Value::from(to.folder.clone()),
+    );
+
+    let before = workspace_blocks(&from.workspace);
+    let after = workspace_blocks(&to.workspace);
+
+    let removed = before
+        .iter()
+        .filter(|(id, _)| !after.contains_key(*id))
+        .map(|(id, block)| BlockSummary {
+            id: id.clone(),
+            r#type: block.r#type.clone(),
+        })
+        .collect();
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for (id, new) in &after {
+        let Some(old) = before.get(id) else {
+            added.push(BlockSummary {
+                id: id.clone(),
+                r#type: new.r#type.clone(),
+            });
+            continue;
+        };
+        if old == new {
+            continue;
+        }
+
+        let mut fields = BTreeMap::new();
+        for key in old.fields.keys().chain(new.fields.keys()) {
+            let (from, to) = (old.fields.get(key), new.fields.get(key));
+            if from != to {
+                fields.insert(
+                    key.clone(),
+                    FieldChange {
+                        from: from.cloned(),
+                        to: to.cloned(),
+                    },
+                );
+            }
+        }
+
+        changed.push(BlockChange {
+            id: id.clone(),
+            r#type: new.r#type.clone(),
+            previous_type: (old.r#type != new.r#type).then(|| old.r#type.clone()),
+            fields,
+            moved: old.parent != new.parent,
+        });
+    }
+
+    AutomationDiff {
+        from: from.version,
+        to: to.version,
+        properties,
+        added,
+        removed,
+        changed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn automation(version: i32, workspace: Value) -> Automation {
+        Automation {
+            id: "a".to_string(),
+            name: format!("v{}", version),
+            description: None,
+            enabled: true,
+            version,
+            triggers: vec![],
+            workspace,
+            conditions: vec![],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            compilation_error: None,
+            tags: vec![],
+            folder: None,
+            blueprint: None,
+        }
+    }
+
+    #[test]
+    fn test_block_level_diff() {
+        let from = automation(
+            1,
+            json!({"blocks": {"languageVersion": 0, "blocks": [{
+                "type": "controls_if",
+                "id": "if",
+                "inputs": {"IF0": {"block": {"type": "logic_boolean", "id": "cond", "fields": {"BOOL": "TRUE"}}}},
+                "next": {"block": {"type": "text_print", "id": "print"}}
+            }]}}),
+        );
+        let to = automation(
+            2,
+            json!({"blocks": {"languageVersion": 0, "blocks": [
+                {
+                    "type": "controls_if",
+                    "id": "if",
+                    "inputs": {"IF0": {"block": {"type": "logic_boolean", "id": "cond", "fields": {"BOOL": "FALSE"}}}}
+                },
+                {"type": "math_number", "id": "num", "fields": {"NUM": 1}}
+            ]}}),
+        );
+
+        let diff = diff(&from, &to);
+        assert_eq!(diff.from, 1);
+        assert_eq!(diff.to, 2);
+        assert!(diff.properties.contains_key("name"));
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].id, "num");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].r#type, "text_print");
+
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].id, "cond");
+        assert_eq!(
+            diff.changed[0].fields["BOOL"],
+            FieldChange {
+                from: Some(json!("TRUE")),
+                to: Some(json!("FALSE"))
+            }
+        );
+        assert!(!diff.changed[0].moved);
+    }
+}
@@ -1,9 +1,25 @@
 mod automation;
 mod blockly;
 mod blocks;
+mod blueprint;
 mod codegen;
+mod diagnostics;
+mod error;
+mod extract;
+mod ha_backend;
 mod ha_client;
+mod ha_health;
+mod ha_registry;
+mod ha_rest;
+mod ha_simulator;
+mod ha_subscription;
+mod ha_yaml;
+mod history;
+mod packs;
+mod persist;
 mod rhai;
+mod selectors;
+mod storage;
 mod tests;
 mod web;
 
@@ -11,16 +27,19 @@ use automation::{Automation, AutomationCreate};
 use axum::{
     extract::{
         ws::{WebSocket, WebSocketUpgrade},
-        Path, State,
+        State,
     },
     http::StatusCode,
     response::{IntoResponse, Response},
     routing::{delete, get, post, put},
-    Json, Router,
+    Router,
 };
 use blocks::BlockDefinition;
 use dotenv::dotenv;
+use error::{ApiError, AutomationError, BlockError, BlueprintError};
+use extract::{Json, Path, Query};
 use futures::{SinkExt, StreamExt};
+use ha_backend::HaBackend;
 use ha_client::HaClient;
 use serde_json::json;
 use std::sync::Arc;
@@ -30,14 +49,32 @@ use tower_http::{
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+enum StorageBackend {
+    Yaml,
+    Sqlite(storage::SqliteDatabase),
+    Git,
+}
+
 #[derive(Clone)]
 struct AppState {
-    ha_client: Arc<HaClient>,
+    ha: Arc<dyn HaBackend>,
+    /// Not available with the simulator
+    ha_rest: Option<Arc<ha_rest::HaRestClient>>,
     automation_store: Arc<automation::AutomationStore>,
     block_store: Arc<blocks::BlockStore>,
+    toolbox_store: Arc<blockly::ToolboxStore>,
+    blueprint_store: Arc<blueprint::BlueprintStore>,
     automations: Arc<Vec<Automation>>,
 }
 
+impl AppState {
+    fn ha_rest(&self) -> Result<&ha_rest::HaRestClient, error::HaRestError> {
+        self.ha_rest
+            .as_deref()
+            .ok_or(error::HaRestError::Unavailable)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load .env file if it exists
@@ -51,31 +88,156 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Initialize Home Assistant client
-    let ha_client = Arc::new(HaClient::new());
-    let ha_host = std::env::var("HA_HOST").unwrap_or_else(|_| "localhost:8123".to_string());
+    // A real Home Assistant by default, or one simulated in process for local development
+    let (ha, ha_rest): (Arc<dyn HaBackend>, _) = match std::env::var("HA_BACKEND").as_deref() {
+        Ok("simulator") => {
+            let config = match std::env::var("HA_SIMULATOR_CONFIG") {
+                Ok(path) => ha_simulator::SimulatorConfig::load(std::path::Path::new(&path))?,
+                Err(_) => ha_simulator::SimulatorConfig::demo(),
+            };
+            // Simulated seconds per real second
+            let speed = match std::env::var("HA_SIMULATOR_SPEED") {
+                Ok(speed) => speed.parse::<f64>()?,
+                Err(_) => 1.0,
+            };
+            if !speed.is_finite() || speed < 0.0 {
+                return Err(format!("Invalid HA_SIMULATOR_SPEED: {}", speed).into());
+            }
+            tracing::info!("Using the simulated Home Assistant");
+            let simulator = ha_simulator::HaSimulator::new(config)?;
+            simulator.run_clock(speed);
+            (Arc::new(simulator), None)
+        }
+        Ok("home_assistant") | Err(_) => {
+            let client = HaClient::new();
+            let (endpoint, token) = ha_client::HaEndpoint::from_env()?;
 
-    let token = std::env::var("SUPERVISOR_TOKEN")
-        .or_else(|_| std::env::var("HA_TOKEN"))
-        .expect("Either SUPERVISOR_TOKEN or HA_TOKEN must be set");
+            let ha_rest = Arc::new(ha_rest::HaRestClient::from_endpoint(
+                &endpoint,
+                token.clone(),
+            )?);
 
-    // Connect to Home Assistant
-    ha_client.connect(ha_host, token).await?;
+            // Connect to Home Assistant, reconnecting whenever the connection is lost
+            client.keep_connected(endpoint, token);
+            (Arc::new(client), Some(ha_rest))
+        }
+        Ok(other) => return Err(format!("Unknown HA_BACKEND: {}", other).into()),
+    };
+
+    // YAML files by default, a single SQLite database, or YAML files committed to git
+    let backend = match std::env::var("STORAGE_BACKEND").as_deref() {
+        Ok("sqlite") => {
+            let path = match std::env::var("SQLITE_PATH") {
+                Ok(path) => std::path::PathBuf::from(path),
+                Err(_) => automation::AutomationStore::default_storage_path()?
+                    .with_file_name("storage.db"),
+            };
+            tracing::info!("Using SQLite storage at {:?}", path);
+            // `:memory:` keeps nothing across restarts, useful for trying things out
+            if path.as_os_str() == ":memory:" {
+                StorageBackend::Sqlite(storage::SqliteDatabase::open_in_memory()?)
+            } else {
+                StorageBackend::Sqlite(storage::SqliteDatabase::open(&path)?)
+            }
+        }
+        Ok("git") => StorageBackend::Git,
+        Ok("yaml") | Err(_) => StorageBackend::Yaml,
+        Ok(other) => return Err(format!("Unknown STORAGE_BACKEND: {}", other).into()),
+    };
 
     // Initialize block store first
-    let block_store = Arc::new(blocks::BlockStore::new().await?);
+    let mut blocks_git = None;
+    let block_store = Arc::new(match &backend {
+        StorageBackend::Sqlite(database) => {
+            blocks::BlockStore::with_repository(Arc::new(storage::SqliteBlockRepository::new(
+                database.clone(),
+                Some("blocks".into()),
+            )))
+            .await?
+        }
+        StorageBackend::Git => {
+            let repository = storage::GitBlockRepository::open("blocks".into()).await?;
+            blocks_git = Some(repository.work_tree());
+            blocks::BlockStore::with_repository(Arc::new(repository)).await?
+        }
+        StorageBackend::Yaml => blocks::BlockStore::new().await?,
+    });
+
+    // Load toolbox layouts from the blocks directory, committing changes to its repository
+    let mut toolbox_store = blockly::ToolboxStore::new().await?;
+    if let Some(git) = blocks_git {
+        toolbox_store = toolbox_store.with_git(git);
+    }
+    let toolbox_store = Arc::new(toolbox_store);
 
     // Create automation store with block store
-    let automation_store =
-        Arc::new(automation::AutomationStore::new(block_store.as_ref().clone()).await?);
+    // Versions kept per automation; 0 keeps every version
+    let history_limit = match std::env::var("AUTOMATION_HISTORY_LIMIT") {
+        Ok(limit) => match limit.parse::<usize>()? {
+            0 => None,
+            limit => Some(limit),
+        },
+        Err(_) => Some(automation::DEFAULT_HISTORY_LIMIT),
+    };
+    let (automation_store, history_limit) = match &backend {
+        StorageBackend::Sqlite(database) => (
+            automation::AutomationStore::with_repository(
+                block_store.as_ref().clone(),
+                Arc::new(storage::SqliteAutomationRepository::new(database.clone())),
+            )
+            .await?,
+            history_limit,
+        ),
+        // Commits are never pruned
+        StorageBackend::Git => (
+            automation::AutomationStore::with_repository(
+                block_store.as_ref().clone(),
+                Arc::new(
+                    storage::GitAutomationRepository::open(
+                        automation::AutomationStore::default_storage_path()?,
+                    )
+                    .await?,
+                ),
+            )
+            .await?,
+            None,
+        ),
+        StorageBackend::Yaml => (
+            automation::AutomationStore::new(block_store.as_ref().clone()).await?,
+            history_limit,
+        ),
+    };
+    let automation_store = Arc::new(automation_store.with_history_limit(history_limit));
+
+    let blueprint_store = Arc::new(match backend {
+        StorageBackend::Sqlite(database) => {
+            blueprint::BlueprintStore::with_repository(Arc::new(
+                storage::SqliteBlueprintRepository::new(database),
+            ))
+            .await?
+        }
+        StorageBackend::Git => {
+            blueprint::BlueprintStore::with_repository(Arc::new(
+                storage::GitBlueprintRepository::open(
+                    blueprint::BlueprintStore::default_blueprints_dir()?,
+                )
+                .await?,
+            ))
+            .await?
+        }
+        StorageBackend::Yaml => blueprint::BlueprintStore::new().await?,
+    });
 
     // Get initial automations
     let automations = Arc::new(automation_store.list().await);
 
     let state = Arc::new(AppState {
-        ha_client: ha_client.clone(),
+        ha,
+        ha_rest,
         automation_store,
         block_store,
+        toolbox_store,
+        blueprint_store,
         automations,
     });
 
@@ -91,17 +253,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/health", get(health_check))
         .route("/ws", get(ws_handler))
         .route("/api/states", get(get_states))
+        .route("/api/areas", get(get_areas))
+        .route("/api/areas/{area}/entities", get(get_area_entities))
+        .route("/api/devices", get(get_devices))
+        .route("/api/entities", get(get_entities))
+        .route("/api/history", get(get_history))
+        .route("/api/logbook", get(get_logbook))
+        .route("/api/template", post(render_template))
         .route("/api/actions", get(get_actions))
+        .route("/api/actions/blocks", get(get_action_blocks))
+        .route("/api/actions/{id}/fields", get(get_action_fields))
         .route("/api/automations", get(list_automations))
         .route("/api/automations", post(create_automation))
         .route("/api/automations/{id}", get(get_automation))
         .route("/api/automations/{id}", put(update_automation))
         .route("/api/automations/{id}", delete(delete_automation))
         .route("/api/automations/{id}/toggle", post(toggle_automation))
+        .route("/api/automations/import/ha", post(import_ha_automations))
+        .route("/api/automations/{id}/export/ha", get(export_ha_automation))
+        .route("/api/diagnostics", get(get_diagnostics))
+        .route(
+            "/api/automations/{id}/versions",
+            get(list_automation_versions),
+        )
+        .route(
+            "/api/automations/{id}/versions/{version}",
+            get(get_automation_version),
+        )
+        .route("/api/automations/{id}/diff", get(diff_automation_versions))
+        .route(
+            "/api/automations/{id}/rollback/{version}",
+            post(rollback_automation),
+        )
+        .route(
+            "/api/automations/{id}/blueprint",
+            put(update_blueprint_inputs),
+        )
+        .route("/api/blueprints", get(list_blueprints))
+        .route("/api/blueprints", post(create_blueprint))
+        .route("/api/blueprints/{id}", get(get_blueprint))
+        .route("/api/blueprints/{id}", put(update_blueprint))
+        .route("/api/blueprints/{id}", delete(delete_blueprint))
+        .route(
+            "/api/blueprints/{id}/instances",
+            get(list_blueprint_instances),
+        )
+        .route(
+            "/api/blueprints/{id}/instances",
+            post(instantiate_blueprint),
+        )
         .route("/api/blockly/toolbox", get(get_blockly_toolbox))
+        .route("/api/toolbox/layouts", get(list_toolbox_layouts))
+        .route("/api/toolbox/layouts/{name}", get(get_toolbox_layout))
+        .route("/api/toolbox/layouts/{name}", put(update_toolbox_layout))
+        .route("/api/toolbox/layouts/{name}", delete(delete_toolbox_layout))
         .route("/api/blocks", get(list_blocks))
         .route("/api/blocks", post(create_or_update_block))
         .route("/api/blocks/{block_type}", delete(delete_block))
+        .route("/api/blocks/tests", post(run_block_tests))
+        .route("/api/blocks/packs/export", get(export_block_pack))
+        .route("/api/blocks/packs/import", post(import_block_pack))
+        .route(
+            "/api/blocks/{block_type}/tests",
+            post(run_single_block_tests),
+        )
         .route("/api/blocks/user", get(list_user_blocks))
         .route("/api/blocks/user", post(create_user_block))
         .route("/api/blocks/user/{id}", put(update_user_block))
@@ -129,21 +344,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-async fn health_check() -> Json<serde_json::Value> {
+async fn health_check(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let home_assistant = state.ha.status();
+    // Still answers while Home Assistant is away, so watchdogs do not restart the builder
+    let status = if home_assistant.state == ha_health::ConnectionState::Connected {
+        "ok"
+    } else {
+        "degraded"
+    };
     Json(json!({
-        "status": "ok",
-        "version": env!("CARGO_PKG_VERSION")
+        "status": status,
+        "version": env!("CARGO_PKG_VERSION"),
+        "home_assistant": home_assistant
     }))
 }
 
 async fn get_states(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
-    let states = state.ha_client.get_all_states().await;
+    let states = state.ha.get_all_states().await;
     Json(json!(states))
 }
 
 async fn get_actions(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
     tracing::debug!("Getting actions...");
-    let actions = state.ha_client.get_all_actions().await;
+    let actions = state.ha.get_all_actions().await;
     tracing::debug!(
         "Got actions: {}",
         serde_json::to_string_pretty(&actions).unwrap_or_default()
@@ -151,13 +374,171 @@ async fn get_actions(State(state): State<Arc<AppState>>) -> Json<serde_json::Val
     Json(json!(actions))
 }
 
+async fn get_action_blocks(State(state): State<Arc<AppState>>) -> Json<Vec<BlockDefinition>> {
+    let actions = state.ha.get_all_actions().await;
+    let mut blocks: Vec<_> = actions
+        .values()
+        .filter_map(selectors::service_block)
+        .collect();
+    blocks.sort_by(|a, b| a.r#type.cmp(&b.r#type));
+    Json(blocks)
+}
+
+/// Areas with the entities placed in them, directly or through their device.
+async fn get_areas(State(state): State<Arc<AppState>>) -> Json<Vec<ha_registry::AreaEntities>> {
+    let registries = state.ha.get_registries().await;
+    Json(registries.areas_with_entities())
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AreaEntitiesQuery {
+    domain: Option<String>,
+}
+
+/// Entity ids in an area given by id or name, e.g. `/api/areas/Kitchen/entities?domain=light`.
+async fn get_area_entities(
+    State(state): State<Arc<AppState>>,
+    Path(area): Path<String>,
+    Query(query): Query<AreaEntitiesQuery>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let registries = state.ha.get_registries().await;
+    let area = registries
+        .find_area(&area)
+        .ok_or_else(|| ApiError::not_found(format!("Area {} not found", area)))?;
+    Ok(Json(
+        registries.area_entities(&area.area_id, query.domain.as_deref()),
+    ))
+}
+
+async fn get_devices(State(state): State<Arc<AppState>>) -> Json<Vec<ha_registry::Device>> {
+    let registries = state.ha.get_registries().await;
+    let mut devices: Vec<_> = registries.devices.into_values().collect();
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(devices)
+}
+
+/// Entity registry entries, with `area_id` inherited from the device where not set.
+async fn get_entities(State(state): State<Arc<AppState>>) -> Json<Vec<ha_registry::EntityEntry>> {
+    let registries = state.ha.get_registries().await;
+    let mut entities: Vec<_> = registries
+        .entities
+        .values()
+        .map(|entity| ha_registry::EntityEntry {
+            area_id: registries
+                .entity_area_id(&entity.entity_id)
+                .map(str::to_string),
+            ..entity.clone()
+        })
+        .collect();
+    entities.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+    Json(entities)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct HistoryQuery {
+    /// Comma separated entity ids
+    entity_id: String,
+    start: Option<chrono::DateTime<chrono::Utc>>,
+    end: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// State history from Home Assistant, the last day unless `start` is given.
+async fn get_history(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<std::collections::BTreeMap<String, Vec<ha_client::EntityState>>>, ApiError> {
+    let entity_ids: Vec<String> = query
+        .entity_id
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect();
+    let start = query
+        .start
+        .unwrap_or_else(|| chrono::Utc::now() - chrono::Duration::days(1));
+    Ok(Json(
+        state
+            .ha_rest()?
+            .history(&entity_ids, start, query.end)
+            .await?,
+    ))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LogbookQuery {
+    entity_id: Option<String>,
+    start: Option<chrono::DateTime<chrono::Utc>>,
+    end: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Logbook entries from Home Assistant, the last day unless `start` is given.
+async fn get_logbook(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<LogbookQuery>,
+) -> Result<Json<Vec<ha_rest::LogbookEntry>>, ApiError> {
+    let start = query
+        .start
+        .unwrap_or_else(|| chrono::Utc::now() - chrono::Duration::days(1));
+    Ok(Json(
+        state
+            .ha_rest()?
+            .logbook(start, query.end, query.entity_id.as_deref())
+            .await?,
+    ))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TemplateRequest {
+    template: String,
+    #[serde(default)]
+    variables: Option<serde_json::Value>,
+}
+
+/// Render a Jinja template in Home Assistant.
+async fn render_template(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<TemplateRequest>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let result = state
+        .ha_rest()?
+        .render_template(&request.template, request.variables)
+        .await?;
+    Ok(Json(json!({ "result": result })))
+}
+
+async fn get_action_fields(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<blocks::BlockArgument>>, ApiError> {
+    let actions = state.ha.get_all_actions().await;
+    let action = actions
+        .get(&id)
+        .ok_or_else(|| ApiError::not_found("Action not found"))?;
+
+    let mut fields: Vec<_> = action
+        .fields
+        .iter()
+        .map(|(key, field)| selectors::field_argument(key, field))
+        .collect();
+    fields.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(fields))
+}
+
 async fn ws_handler(State(state): State<Arc<AppState>>, ws: WebSocketUpgrade) -> Response {
     ws.on_upgrade(|socket| handle_socket(socket, state))
 }
 
 // Automation handlers
-async fn list_automations(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
-    let automations = state.automation_store.list().await;
+async fn list_automations(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<automation::AutomationFilter>,
+) -> Json<serde_json::Value> {
+    let registries = state.ha.get_registries().await;
+    let automations = state
+        .automation_store
+        .search(&filter, Some(&registries))
+        .await;
     Json(json!({
         "automations": automations
     }))
@@ -167,11 +548,10 @@ async fn list_automations(State(state): State<Arc<AppState>>) -> Json<serde_json
 async fn get_automation(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> impl IntoResponse {
-    if let Some(automation) = state.automation_store.get(&id).await {
-        (StatusCode::OK, Json(automation)).into_response()
-    } else {
-        (StatusCode::NOT_FOUND, "Automation not found".to_string()).into_response()
+) -> Result<Json<Automation>, ApiError> {
+    match state.automation_store.get(&id).await {
+        Some(automation) => Ok(Json(automation)),
+        None => Err(AutomationError::NotFound(id).into()),
     }
 }
 
@@ -179,11 +559,9 @@ async fn get_automation(
 async fn create_automation(
     State(state): State<Arc<AppState>>,
     Json(data): Json<AutomationCreate>,
-) -> impl IntoResponse {
-    match state.automation_store.create(data).await {
-        Ok(automation) => (StatusCode::CREATED, Json(automation)).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-    }
+) -> Result<(StatusCode, Json<Automation>), ApiError> {
+    let automation = state.automation_store.create(data).await?;
+    Ok((StatusCode::CREATED, Json(automation)))
 }
 
 #[axum::debug_handler]
@@ -191,20 +569,22 @@ async fn update_automation(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(data): Json<automation::AutomationUpdate>,
-) -> Response {
-    match state.automation_store.update(&id, data).await {
-        Ok(Some(automation)) => (StatusCode::OK, Json(automation)).into_response(),
-        Ok(None) => (StatusCode::NOT_FOUND, "Automation not found".to_string()).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+) -> Result<Json<Automation>, ApiError> {
+    match state.automation_store.update(&id, data).await? {
+        Some(automation) => Ok(Json(automation)),
+        None => Err(AutomationError::NotFound(id).into()),
     }
 }
 
 #[axum::debug_handler]
-async fn delete_automation(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
-    match state.automation_store.delete(&id).await {
-        Ok(true) => StatusCode::NO_CONTENT.into_response(),
-        Ok(false) => (StatusCode::NOT_FOUND, "Automation not found".to_string()).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+async fn delete_automation(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if state.automation_store.delete(&id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AutomationError::NotFound(id).into())
     }
 }
 
@@ -213,38 +593,270 @@ async fn toggle_automation(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(data): Json<serde_json::Value>,
-) -> Result<Json<Automation>, (StatusCode, String)> {
-    let enabled = match data.get("enabled").and_then(|v| v.as_bool()) {
-        Some(enabled) => enabled,
-        None => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                "Missing 'enabled' field".to_string(),
-            ))
-        }
+) -> Result<Json<Automation>, ApiError> {
+    let enabled = data
+        .get("enabled")
+        .and_then(|v| v.as_bool())
+        .ok_or_else(|| ApiError::bad_request("Missing 'enabled' field"))?;
+
+    match state.automation_store.toggle(&id, enabled).await? {
+        Some(automation) => Ok(Json(automation)),
+        None => Err(AutomationError::NotFound(id).into()),
+    }
+}
+
+async fn get_diagnostics(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<diagnostics::LoadDiagnostic>> {
+    let mut diagnostics = state.automation_store.diagnostics();
+    diagnostics.extend(state.block_store.diagnostics());
+    diagnostics.extend(state.blueprint_store.diagnostics());
+    Json(diagnostics)
+}
+
+async fn list_automation_versions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<history::AutomationVersion>>, ApiError> {
+    match state.automation_store.versions(&id).await? {
+        Some(versions) => Ok(Json(versions)),
+        None => Err(AutomationError::NotFound(id).into()),
+    }
+}
+
+async fn get_automation_version(
+    State(state): State<Arc<AppState>>,
+    Path((id, version)): Path<(String, i32)>,
+) -> Result<Json<Automation>, ApiError> {
+    state
+        .automation_store
+        .get_version(&id, version)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("Version not found"))
+}
+
+#[derive(serde::Deserialize)]
+struct DiffQuery {
+    from: i32,
+    to: Option<i32>,
+}
+
+async fn diff_automation_versions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<history::AutomationDiff>, ApiError> {
+    // Compare against the current version unless `to` is given
+    let to = match query.to {
+        Some(to) => to,
+        None => match state.automation_store.get(&id).await {
+            Some(automation) => automation.version,
+            None => return Err(AutomationError::NotFound(id).into()),
+        },
     };
 
-    match state.automation_store.toggle(&id, enabled).await {
-        Ok(Some(automation)) => Ok(Json(automation)),
-        Ok(None) => Err((StatusCode::NOT_FOUND, "Automation not found".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    state
+        .automation_store
+        .diff(&id, query.from, to)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("Version not found"))
+}
+
+#[axum::debug_handler]
+async fn rollback_automation(
+    State(state): State<Arc<AppState>>,
+    Path((id, version)): Path<(String, i32)>,
+) -> Result<Json<Automation>, ApiError> {
+    state
+        .automation_store
+        .rollback(&id, version)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("Version not found"))
+}
+
+async fn import_ha_automations(
+    State(state): State<Arc<AppState>>,
+    Query(options): Query<ha_yaml::HaImportOptions>,
+    body: String,
+) -> Result<Json<ha_yaml::HaImportReport>, ApiError> {
+    Ok(Json(
+        ha_yaml::import_automations(&state.automation_store, &body, options).await?,
+    ))
+}
+
+async fn export_ha_automation(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let automation = state
+        .automation_store
+        .get(&id)
+        .await
+        .ok_or(AutomationError::NotFound(id))?;
+
+    match ha_yaml::HaAutomation::from_automation(&automation) {
+        Ok(exported) => Ok((
+            StatusCode::OK,
+            [(axum::http::header::CONTENT_TYPE, "application/yaml")],
+            ha_yaml::to_yaml(&[exported])?,
+        )
+            .into_response()),
+        Err(issues) => Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({
+                "error": "not_exportable",
+                "message": "Automation cannot be expressed as Home Assistant YAML",
+                "issues": issues,
+            })),
+        )
+            .into_response()),
+    }
+}
+
+async fn list_blueprints(State(state): State<Arc<AppState>>) -> Json<Vec<blueprint::Blueprint>> {
+    Json(state.blueprint_store.list().await)
+}
+
+async fn get_blueprint(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<blueprint::Blueprint>, ApiError> {
+    match state.blueprint_store.get(&id).await {
+        Some(blueprint) => Ok(Json(blueprint)),
+        None => Err(BlueprintError::NotFound(id).into()),
+    }
+}
+
+async fn create_blueprint(
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<blueprint::BlueprintCreate>,
+) -> Result<(StatusCode, Json<blueprint::Blueprint>), ApiError> {
+    let blueprint = state.blueprint_store.create(data).await?;
+    Ok((StatusCode::CREATED, Json(blueprint)))
+}
+
+async fn update_blueprint(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(data): Json<blueprint::BlueprintUpdate>,
+) -> Result<Json<blueprint::BlueprintChange>, ApiError> {
+    let change =
+        blueprint::update(&state.blueprint_store, &state.automation_store, &id, data).await?;
+    Ok(Json(change))
+}
+
+async fn delete_blueprint(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    blueprint::delete(&state.blueprint_store, &state.automation_store, &id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_blueprint_instances(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Automation>>, ApiError> {
+    if state.blueprint_store.get(&id).await.is_none() {
+        return Err(BlueprintError::NotFound(id).into());
     }
+    Ok(Json(state.automation_store.instances_of(&id).await))
+}
+
+async fn instantiate_blueprint(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<blueprint::InstantiateRequest>,
+) -> Result<(StatusCode, Json<Automation>), ApiError> {
+    let automation = blueprint::instantiate(
+        &state.blueprint_store,
+        &state.automation_store,
+        &id,
+        request,
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(automation)))
+}
+
+async fn update_blueprint_inputs(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(inputs): Json<std::collections::BTreeMap<String, serde_json::Value>>,
+) -> Result<Json<Automation>, ApiError> {
+    let automation =
+        blueprint::set_inputs(&state.blueprint_store, &state.automation_store, &id, inputs).await?;
+    Ok(Json(automation))
+}
+
+#[derive(serde::Deserialize)]
+struct ToolboxQuery {
+    layout: Option<String>,
 }
 
 #[axum::debug_handler]
-async fn get_blockly_toolbox(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+async fn get_blockly_toolbox(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ToolboxQuery>,
+) -> Result<Response, ApiError> {
     let blocks = state.block_store.list().await;
-    let toolbox = blockly::create_default_toolbox(&blocks);
+    let layout = query
+        .layout
+        .unwrap_or_else(|| blockly::DEFAULT_LAYOUT.to_string());
+    let toolbox = state
+        .toolbox_store
+        .render(&layout, &blocks)
+        .await
+        .ok_or_else(|| ApiError::not_found("Toolbox layout not found"))?;
 
     let response = blockly::ToolboxResponse { toolbox, blocks };
 
     let json = serde_json::to_string_pretty(&response).unwrap();
 
-    (
+    Ok((
         StatusCode::OK,
         [(axum::http::header::CONTENT_TYPE, "application/json")],
         json,
     )
+        .into_response())
+}
+
+async fn list_toolbox_layouts(State(state): State<Arc<AppState>>) -> Json<Vec<String>> {
+    Json(state.toolbox_store.list().await)
+}
+
+async fn get_toolbox_layout(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<blockly::ToolboxLayout>, ApiError> {
+    state
+        .toolbox_store
+        .get(&name)
+        .await
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("Toolbox layout not found"))
+}
+
+async fn update_toolbox_layout(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(layout): Json<blockly::ToolboxLayout>,
+) -> Result<Json<blockly::ToolboxLayout>, ApiError> {
+    state.toolbox_store.save(&name, layout.clone()).await?;
+    Ok(Json(layout))
+}
+
+async fn delete_toolbox_layout(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<(), ApiError> {
+    if state.toolbox_store.delete(&name).await? {
+        Ok(())
+    } else {
+        Err(ApiError::not_found("Toolbox layout not found"))
+    }
 }
 
 async fn list_blocks(State(state): State<Arc<AppState>>) -> Json<Vec<BlockDefinition>> {
@@ -255,23 +867,77 @@ async fn list_blocks(State(state): State<Arc<AppState>>) -> Json<Vec<BlockDefini
 async fn create_or_update_block(
     State(state): State<Arc<AppState>>,
     Json(block): Json<BlockDefinition>,
-) -> Result<(), (StatusCode, String)> {
-    state
-        .block_store
-        .create_or_update(block)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+) -> Result<(), ApiError> {
+    Ok(state.block_store.create_or_update(block).await?)
 }
 
 async fn delete_block(
     State(state): State<Arc<AppState>>,
     Path(block_type): Path<String>,
-) -> Result<(), (StatusCode, String)> {
-    match state.block_store.delete(&block_type).await {
-        Ok(true) => Ok(()),
-        Ok(false) => Err((StatusCode::NOT_FOUND, "Block not found".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+) -> Result<(), ApiError> {
+    if state.block_store.delete(&block_type).await? {
+        Ok(())
+    } else {
+        Err(BlockError::NotFound(block_type).into())
+    }
+}
+
+async fn run_block_tests(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<codegen::testing::BlockTestOutcome>> {
+    let generator = codegen::CodeGenerator::new(state.block_store.as_ref().clone());
+    Json(generator.run_block_tests(None).await)
+}
+
+async fn run_single_block_tests(
+    State(state): State<Arc<AppState>>,
+    Path(block_type): Path<String>,
+) -> Result<Json<Vec<codegen::testing::BlockTestOutcome>>, ApiError> {
+    if state.block_store.get(&block_type).await.is_none() {
+        return Err(BlockError::NotFound(block_type).into());
     }
+
+    let generator = codegen::CodeGenerator::new(state.block_store.as_ref().clone());
+    Ok(Json(generator.run_block_tests(Some(&block_type)).await))
+}
+
+async fn export_block_pack(
+    State(state): State<Arc<AppState>>,
+    Query(options): Query<packs::ExportQuery>,
+) -> Result<Response, ApiError> {
+    let pack = packs::BlockPack::export(
+        &state.block_store,
+        Some(&state.toolbox_store),
+        options.into(),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, "application/yaml")],
+        pack.to_yaml()?,
+    )
+        .into_response())
+}
+
+async fn import_block_pack(
+    State(state): State<Arc<AppState>>,
+    Query(options): Query<packs::ImportOptions>,
+    body: String,
+) -> Result<(StatusCode, Json<packs::ImportReport>), ApiError> {
+    let pack = packs::BlockPack::from_yaml(&body)?;
+    let report = pack
+        .import(&state.block_store, Some(&state.toolbox_store), options)
+        .await?;
+
+    let status = if report.applied || report.dry_run {
+        StatusCode::OK
+    } else if !report.errors.is_empty() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::CONFLICT
+    };
+    Ok((status, Json(report)))
 }
 
 async fn list_user_blocks(State(state): State<Arc<AppState>>) -> Json<Vec<BlockDefinition>> {
@@ -286,82 +952,78 @@ async fn list_user_blocks(State(state): State<Arc<AppState>>) -> Json<Vec<BlockD
 async fn create_user_block(
     State(state): State<Arc<AppState>>,
     Json(block): Json<BlockDefinition>,
-) -> Result<Json<BlockDefinition>, (StatusCode, String)> {
+) -> Result<Json<BlockDefinition>, ApiError> {
     // Ensure this is marked as a user block
     if block.id.is_some() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Block ID should not be provided for new blocks".to_string(),
+        return Err(ApiError::bad_request(
+            "Block ID should not be provided for new blocks",
         ));
     }
 
     let block_type = block.r#type.clone();
-    match state.block_store.create_or_update(block).await {
-        Ok(()) => {
-            let created_block = state.block_store.get(&block_type).await.ok_or((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to retrieve created block".to_string(),
-            ))?;
-            Ok(Json(created_block))
-        }
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-    }
+    state.block_store.create_or_update(block).await?;
+    let created_block = state
+        .block_store
+        .get(&block_type)
+        .await
+        .ok_or_else(|| ApiError::internal("Failed to retrieve created block"))?;
+    Ok(Json(created_block))
 }
 
 async fn update_user_block(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(mut block): Json<BlockDefinition>,
-) -> Result<Json<BlockDefinition>, (StatusCode, String)> {
+) -> Result<Json<BlockDefinition>, ApiError> {
     // Verify block exists and is a user block
     let block_type = block.r#type.clone();
     let existing = state
         .block_store
         .get(&block_type)
         .await
-        .ok_or((StatusCode::NOT_FOUND, "Block not found".to_string()))?;
+        .ok_or_else(|| BlockError::NotFound(block_type.clone()))?;
 
     if existing.id.as_ref() != Some(&id) {
-        return Err((StatusCode::BAD_REQUEST, "Block ID mismatch".to_string()));
+        return Err(ApiError::bad_request("Block ID mismatch"));
     }
 
     // Preserve the original ID and created timestamp
     block.id = existing.id;
     block.created = existing.created;
 
-    match state.block_store.create_or_update(block).await {
-        Ok(()) => {
-            let updated_block = state.block_store.get(&block_type).await.ok_or((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to retrieve updated block".to_string(),
-            ))?;
-            Ok(Json(updated_block))
-        }
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-    }
+    state.block_store.create_or_update(block).await?;
+    let updated_block = state
+        .block_store
+        .get(&block_type)
+        .await
+        .ok_or_else(|| ApiError::internal("Failed to retrieve updated block"))?;
+    Ok(Json(updated_block))
 }
 
 async fn delete_user_block(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<(), (StatusCode, String)> {
+) -> Result<(), ApiError> {
     // First get the block to verify it's a user block
     let blocks = state.block_store.list().await;
     let block = blocks
         .iter()
         .find(|b| b.id.as_ref() == Some(&id))
-        .ok_or((StatusCode::NOT_FOUND, "Block not found".to_string()))?;
+        .ok_or_else(|| BlockError::NotFound(id.clone()))?;
 
-    match state.block_store.delete(&block.r#type).await {
-        Ok(true) => Ok(()),
-        Ok(false) => Err((StatusCode::NOT_FOUND, "Block not found".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    if state.block_store.delete(&block.r#type).await? {
+        Ok(())
+    } else {
+        Err(BlockError::NotFound(block.r#type.clone()).into())
     }
 }
 
 async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     let (mut sender, mut receiver) = socket.split();
-    let mut state_rx = state.ha_client.subscribe_to_states();
+    let mut states = state
+        .ha
+        .subscribe_states(ha_subscription::EntityFilter::all());
+    let mut services = state.ha.watch_services();
 
     // Handle incoming messages
     let mut recv_task = tokio::spawn(async move {
@@ -390,26 +1052,58 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
 
     // Send state updates to client
     let mut send_task = tokio::spawn(async move {
-        while let Ok((entity_id, state)) = state_rx.recv().await {
-            let msg = json!({
-                "type": "state_changed",
-                "entity_id": entity_id,
-                "state": state
-            });
-
-            match serde_json::to_string(&msg) {
-                Ok(msg_str) => {
-                    if let Err(e) = sender
-                        .send(axum::extract::ws::Message::Text(msg_str.into()))
-                        .await
-                    {
-                        tracing::error!("WebSocket send error: {}", e);
-                        break;
-                    }
+        'updates: loop {
+            let mut messages = Vec::new();
+            let update = tokio::select! {
+                update = states.recv() => Some(update),
+                Ok(()) = services.changed() => {
+                    // Let the editor refresh its service pickers
+                    let revision = *services.borrow_and_update();
+                    messages.push(json!({
+                        "type": "services_changed",
+                        "revision": revision
+                    }));
+                    None
                 }
-                Err(e) => {
-                    tracing::error!("Failed to serialize WebSocket message: {}", e);
-                    continue;
+            };
+            if let Some(update) = update {
+                let changes = match update {
+                    ha_subscription::StateUpdate::Changed(change) => vec![*change],
+                    ha_subscription::StateUpdate::Lagged { changes, skipped } => {
+                        // Tell the client that intermediate states were merged
+                        tracing::debug!("WebSocket client lagged, {} changes merged", skipped);
+                        messages.push(json!({
+                            "type": "states_lagged",
+                            "skipped": skipped
+                        }));
+                        changes
+                    }
+                };
+                messages.extend(changes.into_iter().map(|change| {
+                    json!({
+                        "type": "state_changed",
+                        "entity_id": change.entity_id,
+                        "state": change.new_state,
+                        "old_state": change.old_state
+                    })
+                }));
+            }
+
+            for msg in messages {
+                match serde_json::to_string(&msg) {
+                    Ok(msg_str) => {
+                        if let Err(e) = sender
+                            .send(axum::extract::ws::Message::Text(msg_str.into()))
+                            .await
+                        {
+                            tracing::error!("WebSocket send error: {}", e);
+                            break 'updates;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to serialize WebSocket message: {}", e);
+                        continue;
+                    }
                 }
             }
         }
@@ -0,0 +1,362 @@
+use crate::blockly::{LayoutCategory, ToolboxItem, ToolboxStore};
+use crate::blocks::{is_valid_block_name, BlockDefinition, BlockStore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, ErrorKind};
+
+/// A shareable bundle of block definitions, built-in templates and a toolbox category.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BlockPack {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Prefix added to block types on import, e.g. `acme` turns `notify` into `acme_notify`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub blocks: Vec<BlockDefinition>,
+    /// Built-in Rhai templates by block type
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toolbox_category: Option<LayoutCategory>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub name: String,
+    pub description: Option<String>,
+    pub namespace: Option<String>,
+    /// Block types to export; all user blocks when empty
+    pub block_types: Vec<String>,
+    /// Name of a toolbox category in `layout` to bundle with the blocks
+    pub category: Option<String>,
+    pub layout: Option<String>,
+}
+
+/// Query string form of `ExportOptions`, with `types` as a comma-separated list.
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub name: String,
+    pub description: Option<String>,
+    pub namespace: Option<String>,
+    pub types: Option<String>,
+    pub category: Option<String>,
+    pub layout: Option<String>,
+}
+
+impl From<ExportQuery> for ExportOptions {
+    fn from(query: ExportQuery) -> Self {
+        Self {
+            name: query.name,
+            description: query.description,
+            namespace: query.namespace,
+            block_types: query
+                .types
+                .map(|types| {
+                    types
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|t| !t.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            category: query.category,
+            layout: query.layout,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Overrides the namespace declared by the pack
+    pub namespace: Option<String>,
+    /// Toolbox layout receiving the pack's category
+    pub layout: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackConflict {
+    pub block_type: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Whether anything was written; false on dry runs and blocked imports
+    pub applied: bool,
+    pub blocks: Vec<String>,
+    pub templates: Vec<String>,
+    pub conflicts: Vec<PackConflict>,
+    pub errors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toolbox_category: Option<String>,
+}
+
+fn validate_namespace(namespace: &str) -> std::io::Result<()> {
+    let valid = !namespace.is_empty()
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid pack namespace: {}", namespace),
+        ))
+    }
+}
+
+fn namespaced(namespace: Option<&str>, block_type: &str) -> String {
+    match namespace {
+        Some(ns) if !block_type.starts_with(&format!("{}_", ns)) => {
+            format!("{}_{}", ns, block_type)
+        }
+        _ => block_type.to_string(),
+    }
+}
+
+impl BlockPack {
+    pub fn from_yaml(content: &str) -> std::io::Result<Self> {
+        serde_yaml::from_str(content).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Failed to parse block pack: {}", e),
+            )
+        })
+    }
+
+    pub fn to_yaml(&self) -> std::io::Result<String> {
+        serde_yaml::to_string(self)
+            .map_err(|e| Error::other(format!("Failed to serialize block pack: {}", e)))
+    }
+
+    pub async fn export(
+        block_store: &BlockStore,
+        toolbox_store: Option<&ToolboxStore>,
+        options: ExportOptions,
+    ) -> std::io::Result<Self> {
+        if let Some(namespace) = &options.namespace {
+            validate_namespace(namespace)?;
+        }
+
+        let mut blocks = Vec::new();
+        let mut templates = BTreeMap::new();
+        if options.block_types.is_empty() {
+            blocks = block_store
+                .list()
+                .await
+                .into_iter()
+                .filter(|b| b.id.is_some())
+                .collect();
+        } else {
+            let available = block_store.list().await;
+            for block_type in &options.block_types {
+                if let Some(template) = block_store.builtin_template(block_type).await {
+                    templates.insert(block_type.clone(), template);
+                }
+                match available.iter().find(|b| &b.r#type == block_type) {
+                    Some(block) => blocks.push(block.clone()),
+                    None if templates.contains_key(block_type) => {}
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::NotFound,
+                            format!("Block not found: {}", block_type),
+                        ))
+                    }
+                }
+            }
+        }
+        blocks.sort_by(|a, b| a.r#type.cmp(&b.r#type));
+
+        // Installation-specific metadata is assigned again on import
+        for block in &mut blocks {
+            block.id = None;
+            block.created = None;
+            block.modified = None;
+        }
+
+        let toolbox_category = match (&options.category, toolbox_store) {
+            (Some(category), Some(toolbox_store)) => {
+                let layout_name = options
+                    .layout
+                    .as_deref()
+                    .unwrap_or(crate::blockly::DEFAULT_LAYOUT);
+                let layout = toolbox_store
+                    .get_or_default(layout_name, &block_store.list().await)
+                    .await
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::NotFound,
+                            format!("Toolbox layout not found: {}", layout_name),
+                        )
+                    })?;
+                let category = layout
+                    .categories
+                    .into_iter()
+                    .find(|c| &c.name == category)
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::NotFound,
+                            format!("Toolbox category not found: {}", category),
+                        )
+                    })?;
+                Some(category)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            name: options.name,
+            description: options.description,
+            namespace: options.namespace,
+            blocks,
+            templates,
+            toolbox_category,
+        })
+    }
+
+    /// Apply the namespace to every block type the pack defines.
+    fn namespaced(mut self, namespace: Option<&str>) -> Self {
+        let renames: HashMap<String, String> = self
+            .blocks
+            .iter()
+            .map(|b| b.r#type.clone())
+            .chain(self.templates.keys().cloned())
+            .map(|t| (t.clone(), namespaced(namespace, &t)))
+            .collect();
+
+        for block in &mut self.blocks {
+            block.r#type = renames[&block.r#type].clone();
+        }
+        self.templates = self
+            .templates
+            .into_iter()
+            .map(|(t, template)| (renames[&t].clone(), template))
+            .collect();
+        if let Some(category) = &mut self.toolbox_category {
+            for item in &mut category.contents {
+                if let ToolboxItem::Block { r#type, .. } = item {
+                    if let Some(renamed) = renames.get(r#type) {
+                        *r#type = renamed.clone();
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    pub async fn import(
+        self,
+        block_store: &BlockStore,
+        toolbox_store: Option<&ToolboxStore>,
+        options: ImportOptions,
+    ) -> std::io::Result<ImportReport> {
+        let namespace = options.namespace.clone().or_else(|| self.namespace.clone());
+        if let Some(namespace) = &namespace {
+            validate_namespace(namespace)?;
+        }
+        let pack = self.namespaced(namespace.as_deref());
+
+        let mut report = ImportReport {
+            dry_run: options.dry_run,
+            ..Default::default()
+        };
+
+        for block in &pack.blocks {
+            if let Err(e) = block.validate() {
+                report.errors.push(e);
+            }
+            if block_store.contains(&block.r#type).await {
+                report.conflicts.push(PackConflict {
+                    block_type: block.r#type.clone(),
+                    reason: "A block with this type already exists".to_string(),
+                });
+            }
+            report.blocks.push(block.r#type.clone());
+        }
+        for block_type in pack.templates.keys() {
+            if !is_valid_block_name(block_type) {
+                report.errors.push(format!(
+                    "Invalid template name {:?}: only letters, digits and underscores are allowed",
+                    block_type
+                ));
+            }
+            if block_store.builtin_template(block_type).await.is_some() {
+                let reason = if block_store.is_imported_template(block_type) {
+                    "A built-in template with this type was already imported"
+                } else {
+                    "A shipped built-in template with this type exists and cannot be replaced"
+                };
+                report.conflicts.push(PackConflict {
+                    block_type: block_type.clone(),
+                    reason: reason.to_string(),
+                });
+            }
+            report.templates.push(block_type.clone());
+        }
+        report.toolbox_category = pack.toolbox_category.as_ref().map(|c| c.name.clone());
+
+        let layout_name = options
+            .layout
+            .clone()
+            .unwrap_or_else(|| crate::blockly::DEFAULT_LAYOUT.to_string());
+        let layout = match (&pack.toolbox_category, toolbox_store) {
+            (Some(_), Some(toolbox_store)) => match toolbox_store
+                .get_or_default(&layout_name, &block_store.list().await)
+                .await
+            {
+                Some(layout) => Some(layout),
+                None => {
+                    report.errors.push(format!(
+                        "Toolbox layout not found: {}; save a layout before importing its category",
+                        layout_name
+                    ));
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        let blocking_conflict = report.conflicts.iter().any(|c| {
+            !options.overwrite
+                || (pack.templates.contains_key(&c.block_type)
+                    && !block_store.is_imported_template(&c.block_type))
+        });
+        if options.dry_run || blocking_conflict || !report.errors.is_empty() {
+            return Ok(report);
+        }
+
+        for (block_type, template) in &pack.templates {
+            block_store
+                .save_builtin_template(block_type, template)
+                .await?;
+        }
+        for block in pack.blocks {
+            block_store.create_or_update(block).await?;
+        }
+        if let (Some(category), Some(mut layout), Some(toolbox_store)) =
+            (pack.toolbox_category, layout, toolbox_store)
+        {
+            match layout
+                .categories
+                .iter_mut()
+                .find(|c| c.name == category.name)
+            {
+                Some(existing) => *existing = category,
+                None => layout.categories.push(category),
+            }
+            toolbox_store.save(&layout_name, layout).await?;
+        }
+
+        report.applied = true;
+        Ok(report)
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// Suffix of the temporary files written before they are renamed into place.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}{}", file_name, TEMP_SUFFIX))
+}
+
+/// Write `contents` to a temporary file next to `path`, sync it and rename it over `path`.
+///
+/// Readers see either the old or the new file, never a truncated one.
+pub async fn write_atomic(
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let temp = temp_path(path);
+
+    let mut file = fs::File::create(&temp).await?;
+    if let Err(e) = async {
+        file.write_all(contents.as_ref()).await?;
+        file.sync_all().await
+    }
+    .await
+    {
+        drop(file);
+        let _ = fs::remove_file(&temp).await;
+        return Err(e);
+    }
+    drop(file);
+
+    fs::rename(&temp, path).await?;
+
+    // Persist the rename itself; not supported on every platform
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = fs::File::open(parent).await {
+            let _ = dir.sync_all().await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove temporary files left behind by writes interrupted by a crash.
+pub async fn remove_stale_temp_files(dir: impl AsRef<Path>) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') && name.ends_with(TEMP_SUFFIX) {
+            tracing::warn!("Removing incomplete write: {:?}", entry.path());
+            fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Stable 64-bit FNV-1a hash of file contents, used to match scripts to their source.
+pub fn content_hash(contents: &[u8]) -> String {
+    let hash = contents.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_atomic_replaces_file() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.yaml");
+
+        write_atomic(&path, "first").await?;
+        write_atomic(&path, "second").await?;
+        assert_eq!(fs::read_to_string(&path).await?, "second");
+
+        // Leftover from an interrupted write
+        fs::write(dir.path().join(".b.yaml.tmp"), "partial").await?;
+        remove_stale_temp_files(dir.path()).await?;
+        assert!(!dir.path().join(".b.yaml.tmp").exists());
+        assert!(path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash_is_stable() {
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_ne!(content_hash(b"a"), content_hash(b"b"));
+    }
+}
@@ -1,8 +1,56 @@
-use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map, Module, NativeCallContext};
+use crate::ha_backend::HaBackend;
+use crate::ha_client::{EntityState, StateChange, REQUEST_TIMEOUT};
+use crate::ha_rest::HaRestClient;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Map, Module, NativeCallContext};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::runtime::{Handle, RuntimeFlavor};
 
-pub struct HaApi;
+/// Home Assistant as seen by scripts.
+///
+/// Functions that need a connection fail when the engine was built without one.
+#[derive(Debug, Clone, Default)]
+pub struct HaApi {
+    backend: Option<Arc<dyn HaBackend>>,
+    rest: Option<Arc<HaRestClient>>,
+}
 
 impl HaApi {
+    pub fn with_backend(mut self, backend: Arc<dyn HaBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn with_rest(mut self, rest: Arc<HaRestClient>) -> Self {
+        self.rest = Some(rest);
+        self
+    }
+
+    fn backend(&self) -> Result<&dyn HaBackend, Box<EvalAltResult>> {
+        self.backend
+            .as_deref()
+            .ok_or_else(|| "Home Assistant is not connected".into())
+    }
+
+    fn rest(&self) -> Result<&HaRestClient, Box<EvalAltResult>> {
+        self.rest
+            .as_deref()
+            .ok_or_else(|| "Home Assistant REST API is not available".into())
+    }
+
+    /// Wait for a Home Assistant request from a script function.
+    ///
+    /// Script functions are synchronous, so this needs a multi-threaded runtime
+    /// whose other workers keep the connection going meanwhile.
+    fn block_on<F: Future>(future: F) -> Result<F::Output, Box<EvalAltResult>> {
+        let handle =
+            Handle::try_current().map_err(|_| "Home Assistant requests need a Tokio runtime")?;
+        if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+            return Err("Home Assistant requests need a multi-threaded Tokio runtime".into());
+        }
+        Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+    }
+
     pub fn get_state(entity_id: &str) -> Result<Dynamic, Box<EvalAltResult>> {
         // TODO: Implement actual HA API call
         // For now, return a mock state
@@ -23,6 +71,15 @@ impl HaApi {
         Ok(Dynamic::UNIT)
     }
 
+    pub fn call_service_with_data(
+        domain: &str,
+        service: &str,
+        data: Map,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        // TODO: Implement actual HA API call
+        Ok(Dynamic::UNIT)
+    }
+
     pub fn get_attributes(entity_id: &str) -> Result<Dynamic, Box<EvalAltResult>> {
         // TODO: Implement actual HA API call
         // For now, return a mock attributes map
@@ -31,6 +88,171 @@ impl HaApi {
         Ok(Dynamic::from(map))
     }
 
+    /// Entities in an area given by id, name or alias, optionally of one domain ("" for all).
+    ///
+    /// Empty for an unknown area, like Home Assistant's `area_entities`.
+    pub fn area_entities(&self, area: &str, domain: &str) -> Result<Array, Box<EvalAltResult>> {
+        let registries = Self::block_on(self.backend()?.get_registries())?;
+        let Some(area) = registries.find_area(area) else {
+            return Ok(Array::new());
+        };
+        let domain = (!domain.is_empty()).then_some(domain);
+        Ok(registries
+            .area_entities(&area.area_id, domain)
+            .into_iter()
+            .map(Dynamic::from)
+            .collect())
+    }
+
+    /// Area id of an entity, directly or through its device; `()` when unassigned.
+    pub fn area_of(&self, entity_id: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let registries = Self::block_on(self.backend()?.get_registries())?;
+        Ok(registries
+            .entity_area_id(entity_id)
+            .map_or(Dynamic::UNIT, |area_id| Dynamic::from(area_id.to_string())))
+    }
+
+    /// Device id of an entity; `()` when it has none.
+    pub fn device_of(&self, entity_id: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let registries = Self::block_on(self.backend()?.get_registries())?;
+        Ok(registries
+            .entity_device(entity_id)
+            .map_or(Dynamic::UNIT, |device| Dynamic::from(device.id.clone())))
+    }
+
+    /// Start of a window reaching `seconds` into the past.
+    fn seconds_ago(seconds: i64) -> Result<chrono::DateTime<chrono::Utc>, Box<EvalAltResult>> {
+        if seconds < 0 {
+            return Err(format!("Cannot look {} seconds into the future", -seconds).into());
+        }
+        Ok(chrono::Utc::now() - chrono::Duration::seconds(seconds))
+    }
+
+    /// State an entity had `seconds` ago, e.g. `state_ago("sensor.temperature", 3600)`;
+    /// `()` if it did not exist yet.
+    pub fn state_ago(&self, entity_id: &str, seconds: i64) -> Result<Dynamic, Box<EvalAltResult>> {
+        let time = Self::seconds_ago(seconds)?;
+        let state =
+            Self::block_on(self.rest()?.state_at(entity_id, time))?.map_err(|e| e.to_string())?;
+        Ok(state.map_or(Dynamic::UNIT, |state| Dynamic::from(state.state)))
+    }
+
+    /// States of an entity during the last `seconds`, oldest first.
+    pub fn history(&self, entity_id: &str, seconds: i64) -> Result<Array, Box<EvalAltResult>> {
+        let start = Self::seconds_ago(seconds)?;
+        let mut history =
+            Self::block_on(self.rest()?.history(&[entity_id.to_string()], start, None))?
+                .map_err(|e| e.to_string())?;
+        history
+            .remove(entity_id)
+            .unwrap_or_default()
+            .iter()
+            .map(rhai::serde::to_dynamic)
+            .collect()
+    }
+
+    /// Render a Home Assistant template, e.g. `render_template("{{ states('sun.sun') }}")`.
+    pub fn render_template(&self, template: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let rendered = Self::block_on(self.rest()?.render_template(template, None))?
+            .map_err(|e| e.to_string())?;
+        Ok(Dynamic::from(rendered))
+    }
+
+    /// Whether a Home Assistant template renders as true right now.
+    pub fn template_is_true(&self, template: &str) -> Result<bool, Box<EvalAltResult>> {
+        let backend = self.backend()?;
+        Self::block_on(async {
+            let mut subscription = backend
+                .subscribe_template(template, None)
+                .await
+                .map_err(|e| e.to_string())?;
+            match tokio::time::timeout(REQUEST_TIMEOUT, subscription.recv()).await {
+                Ok(Some(update)) => Ok(update.is_true()),
+                Ok(None) => Err("Connection to Home Assistant closed".into()),
+                Err(_) => Err(format!("Template was not rendered: {}", template).into()),
+            }
+        })?
+    }
+
+    /// Split a `domain.service` action id.
+    fn split_action(action: &str) -> Result<(&str, &str), Box<EvalAltResult>> {
+        action
+            .split_once('.')
+            .ok_or_else(|| format!("Invalid action, expected domain.service: {}", action).into())
+    }
+
+    pub fn parse_json(text: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let value: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {}", e))?;
+        rhai::serde::to_dynamic(value)
+    }
+
+    /// Whether the current local time is within `start`..`end` (`HH:MM[:SS]`), wrapping past midnight.
+    pub fn time_between(start: &str, end: &str) -> Result<bool, Box<EvalAltResult>> {
+        let parse = |time: &str| {
+            chrono::NaiveTime::parse_from_str(time, "%H:%M:%S")
+                .or_else(|_| chrono::NaiveTime::parse_from_str(time, "%H:%M"))
+                .map_err(|e| format!("Invalid time {}: {}", time, e))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        let now = chrono::Local::now().time();
+
+        Ok(if start <= end {
+            start <= now && now <= end
+        } else {
+            now >= start || now <= end
+        })
+    }
+
+    /// Whether a numeric state is above `above` and below `below`; an empty bound is ignored.
+    pub fn numeric_state(
+        entity_id: &str,
+        above: &str,
+        below: &str,
+    ) -> Result<bool, Box<EvalAltResult>> {
+        let state = Self::get_state(entity_id)?.to_string();
+        let Ok(value) = state.parse::<f64>() else {
+            return Ok(false);
+        };
+        let bound = |bound: &str| -> Result<Option<f64>, Box<EvalAltResult>> {
+            match bound.trim() {
+                "" => Ok(None),
+                bound => bound
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("Invalid numeric bound: {}", bound).into()),
+            }
+        };
+
+        Ok(bound(above)?.is_none_or(|above| value > above)
+            && bound(below)?.is_none_or(|below| value < below))
+    }
+
+    fn on_time(
+        ctx: &mut NativeCallContext<'_>,
+        callback: FnPtr,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        callback.call_within_context::<Dynamic>(ctx, ())?;
+        Ok(Dynamic::UNIT)
+    }
+
+    /// Run `callback` every time the template changes from false to true,
+    /// until the connection to Home Assistant is gone.
+    fn on_template_true(
+        &self,
+        ctx: &mut NativeCallContext<'_>,
+        template: &str,
+        callback: FnPtr,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        let backend = self.backend()?;
+        let mut subscription = Self::block_on(backend.subscribe_template(template, None))?
+            .map_err(|e| e.to_string())?;
+        while Self::block_on(subscription.becomes_true())?.is_some() {
+            callback.call_within_context::<Dynamic>(ctx, ())?;
+        }
+        Ok(Dynamic::UNIT)
+    }
+
     fn on_state_change(
         ctx: &mut NativeCallContext<'_>,
         callback: FnPtr,
@@ -41,9 +263,63 @@ impl HaApi {
         callback.call_within_context(ctx, (entity_id, state))?;
         Ok(Dynamic::UNIT)
     }
+
+    /// Trigger variables for a state change, named like `trigger` in Home Assistant templates.
+    ///
+    /// `from_state` is `()` for a newly added entity; states carry `state`, `attributes`,
+    /// `last_changed`, `last_updated`, `last_reported` and `context`.
+    pub fn state_trigger(change: &StateChange) -> Result<Dynamic, Box<EvalAltResult>> {
+        rhai::serde::to_dynamic(serde_json::json!({
+            "platform": "state",
+            "entity_id": change.entity_id,
+            "from_state": change.old_state,
+            "to_state": change.new_state,
+            "context": change.context(),
+            "by_user": change.is_user_change(),
+        }))
+    }
+
+    fn on_state_trigger(
+        ctx: &mut NativeCallContext<'_>,
+        entity_id: &str,
+        callback: FnPtr,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        // TODO: Subscribe to the entity's state changes
+        let mock_state = |state: &str| EntityState {
+            state: state.to_string(),
+            attributes: Default::default(),
+            last_changed: "2024-01-26T10:45:00Z".to_string(),
+            last_updated: "2024-01-26T10:45:00Z".to_string(),
+            last_reported: Some("2024-01-26T10:45:00Z".to_string()),
+            context: None,
+        };
+        let change = StateChange {
+            entity_id: entity_id.to_string(),
+            old_state: Some(mock_state("old_state")),
+            new_state: mock_state("new_state"),
+        };
+
+        callback.call_within_context::<Dynamic>(ctx, (Self::state_trigger(&change)?,))?;
+        Ok(Dynamic::UNIT)
+    }
+
+    fn on_event(
+        ctx: &mut NativeCallContext<'_>,
+        event_type: &str,
+        data: Map,
+        callback: FnPtr,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        let mut event = Map::new();
+        event.insert("event_type".into(), event_type.into());
+        event.insert("data".into(), data.into());
+        event.insert("origin".into(), "LOCAL".into());
+
+        callback.call_within_context::<Dynamic>(ctx, (Dynamic::from(event),))?;
+        Ok(Dynamic::UNIT)
+    }
 }
 
-pub fn register_ha_api(engine: &mut Engine) {
+pub fn register_ha_api(engine: &mut Engine, api: HaApi) {
     let mut module = Module::new();
 
     module.set_native_fn("get_state", |entity_id: &str| HaApi::get_state(entity_id));
@@ -56,9 +332,54 @@ pub fn register_ha_api(engine: &mut Engine) {
             HaApi::call_service(domain, service, entity_id)
         },
     );
+    module.set_native_fn("call_service", |domain: &str, service: &str, data: Map| {
+        HaApi::call_service_with_data(domain, service, data)
+    });
     module.set_native_fn("get_attributes", |entity_id: &str| {
         HaApi::get_attributes(entity_id)
     });
+    module.set_native_fn("call_service", |action: &str, entity_id: &str| {
+        let (domain, service) = HaApi::split_action(action)?;
+        HaApi::call_service(domain, service, entity_id)
+    });
+    module.set_native_fn("call_service", |action: &str, data: Map| {
+        let (domain, service) = HaApi::split_action(action)?;
+        HaApi::call_service_with_data(domain, service, data)
+    });
+    module.set_native_fn("parse_json", HaApi::parse_json);
+    let ha = api.clone();
+    module.set_native_fn("area_entities", move |area: &str| {
+        ha.area_entities(area, "")
+    });
+    let ha = api.clone();
+    module.set_native_fn("area_entities", move |area: &str, domain: &str| {
+        ha.area_entities(area, domain)
+    });
+    let ha = api.clone();
+    module.set_native_fn("area_of", move |entity_id: &str| ha.area_of(entity_id));
+    let ha = api.clone();
+    module.set_native_fn("state_ago", move |entity_id: &str, seconds: i64| {
+        ha.state_ago(entity_id, seconds)
+    });
+    let ha = api.clone();
+    module.set_native_fn("history", move |entity_id: &str, seconds: i64| {
+        ha.history(entity_id, seconds)
+    });
+    let ha = api.clone();
+    module.set_native_fn("render_template", move |template: &str| {
+        ha.render_template(template)
+    });
+    let ha = api.clone();
+    module.set_native_fn("template_is_true", move |template: &str| {
+        ha.template_is_true(template)
+    });
+    let ha = api.clone();
+    module.set_native_fn("device_of", move |entity_id: &str| ha.device_of(entity_id));
+    module.set_native_fn("time_between", HaApi::time_between);
+    module.set_native_fn(
+        "numeric_state",
+        |entity_id: &str, above: &str, below: &str| HaApi::numeric_state(entity_id, above, below),
+    );
 
     module.set_native_fn(
         "on_state_change",
@@ -67,6 +388,41 @@ pub fn register_ha_api(engine: &mut Engine) {
         },
     );
 
+    module.set_native_fn(
+        "on_state_trigger",
+        |mut ctx: NativeCallContext, entity_id: &str, callback: FnPtr| {
+            HaApi::on_state_trigger(&mut ctx, entity_id, callback)
+        },
+    );
+    module.set_native_fn(
+        "on_event",
+        |mut ctx: NativeCallContext, event_type: &str, data: Map, callback: FnPtr| {
+            HaApi::on_event(&mut ctx, event_type, data, callback)
+        },
+    );
+
+    module.set_native_fn(
+        "on_numeric_state",
+        |mut ctx: NativeCallContext,
+         _entity_id: &str,
+         _above: &str,
+         _below: &str,
+         callback: FnPtr| { HaApi::on_time(&mut ctx, callback) },
+    );
+    let ha = api.clone();
+    module.set_native_fn(
+        "on_template_true",
+        move |mut ctx: NativeCallContext, template: &str, callback: FnPtr| {
+            ha.on_template_true(&mut ctx, template, callback)
+        },
+    );
+    module.set_native_fn(
+        "on_time",
+        |mut ctx: NativeCallContext, _time: &str, callback: FnPtr| {
+            HaApi::on_time(&mut ctx, callback)
+        },
+    );
+
     engine.register_global_module(module.into());
 }
 
@@ -77,7 +433,7 @@ mod tests {
     #[test]
     fn test_ha_api_registration() {
         let mut engine = Engine::new();
-        register_ha_api(&mut engine);
+        register_ha_api(&mut engine, HaApi::default());
 
         // Test get_state
         let result = engine
@@ -93,10 +449,136 @@ mod tests {
         let result = engine.eval::<()>(r#"call_service("light", "turn_on", "light.living_room")"#);
         assert!(result.is_ok());
 
+        // Test call_service with service data
+        let result = engine.eval::<()>(
+            r#"call_service("light", "turn_on", #{ "entity_id": "light.living_room", "brightness": 128 })"#,
+        );
+        assert!(result.is_ok());
+
+        // Test the domain.service forms used by imported automations
+        let result = engine.eval::<()>(r#"call_service("light.turn_on", "light.living_room")"#);
+        assert!(result.is_ok());
+        let result = engine.eval::<()>(
+            r#"call_service("light.turn_on", parse_json(`{"entity_id": "light.living_room"}`))"#,
+        );
+        assert!(result.is_ok());
+        assert!(engine
+            .eval::<()>(r#"call_service("turn_on", "light.living_room")"#)
+            .is_err());
+
+        // Test time and numeric conditions
+        assert!(engine
+            .eval::<bool>(r#"time_between("00:00", "23:59:59")"#)
+            .unwrap());
+        assert!(!engine
+            .eval::<bool>(r#"numeric_state("sensor.temperature", "", "20")"#)
+            .unwrap());
+
+        // Test event triggers receive the event as a map
+        let result = engine
+            .eval::<String>(
+                r#"let seen = ""; on_event("zha_event", parse_json(`{"command": "on"}`), |event| { seen = event.data.command; }); seen"#,
+            );
+        assert_eq!(result.unwrap(), "on");
+
+        // Test state triggers receive the previous and new state
+        let result = engine
+            .eval::<String>(
+                r#"let seen = ""; on_state_trigger("light.kitchen", |trigger| { seen = `${trigger.entity_id}: ${trigger.from_state?.state} -> ${trigger.to_state.state}`; }); seen"#,
+            )
+            .unwrap();
+        assert_eq!(result, "light.kitchen: old_state -> new_state");
+
+        // Registry lookups need a connection
+        let error = engine
+            .eval::<Array>(r#"area_entities("Living Room", "light")"#)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Home Assistant is not connected"));
+
+        // History helpers need the REST API
+        assert!(engine
+            .eval::<Dynamic>(r#"state_ago("sensor.temperature", 3600)"#)
+            .unwrap_err()
+            .to_string()
+            .contains("REST API is not available"));
+
+        // Template conditions and triggers need a connection
+        assert!(engine
+            .eval::<bool>(r#"template_is_true(`{{ is_state('sun.sun', 'below_horizon') }}`)"#)
+            .is_err());
+        assert!(engine
+            .eval::<()>(r#"let runs = 0; on_template_true(`{{ true }}`, || { runs += 1; });"#)
+            .is_err());
+
         // Test get_attributes
         let result = engine
             .eval::<Map>(r#"get_attributes("light.living_room")"#)
             .unwrap();
         assert_eq!(result.get("mock_attr").unwrap().to_string(), "mock_value");
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_registry_lookups() {
+        let simulator = crate::ha_simulator::HaSimulator::new(
+            serde_yaml::from_str(
+                r#"
+entities:
+  - { entity_id: light.ceiling, state: "off", area: Living Room }
+  - { entity_id: light.lamp, state: "off", area: Living Room }
+  - { entity_id: media_player.tv, state: "off", area: Living Room }
+  - { entity_id: light.kitchen, state: "off", area: Kitchen }
+  - { entity_id: sensor.outdoor, state: "5" }
+"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut engine = Engine::new();
+        register_ha_api(
+            &mut engine,
+            HaApi::default().with_backend(Arc::new(simulator)),
+        );
+
+        let names = |array: Array| {
+            array
+                .into_iter()
+                .map(|entity| entity.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(
+                engine
+                    .eval::<Array>(r#"area_entities("living room", "light")"#)
+                    .unwrap()
+            ),
+            vec!["light.ceiling", "light.lamp"]
+        );
+        assert_eq!(
+            engine
+                .eval::<Array>(r#"area_entities("living_room")"#)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(engine
+            .eval::<Array>(r#"area_entities("Attic")"#)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            engine
+                .eval::<String>(r#"area_of("light.kitchen")"#)
+                .unwrap(),
+            "kitchen"
+        );
+        assert!(engine
+            .eval::<Dynamic>(r#"area_of("sensor.outdoor")"#)
+            .unwrap()
+            .is_unit());
+        assert!(engine
+            .eval::<Dynamic>(r#"device_of("light.kitchen")"#)
+            .unwrap()
+            .is_unit());
+    }
 }
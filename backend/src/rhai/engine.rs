@@ -1,6 +1,7 @@
-use super::bindings::register_ha_api;
+use super::bindings::{register_ha_api, HaApi};
+use crate::error::{CompileDiagnostic, CompileStage};
 use crate::rhai::{SCRIPT_MEM_LIMIT_BYTES, SCRIPT_TIMEOUT_MS};
-use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+use rhai::{Dynamic, Engine, EvalAltResult, ParseError, Scope, AST};
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
@@ -9,7 +10,13 @@ pub struct ScriptEngine {
 }
 
 impl ScriptEngine {
+    /// An engine whose Home Assistant functions fail for want of a connection;
+    /// enough for compiling and for pure scripts.
     pub fn new() -> Self {
+        Self::with_api(HaApi::default())
+    }
+
+    pub fn with_api(api: HaApi) -> Self {
         let mut engine = Engine::new();
 
         // Set sandbox limits
@@ -25,7 +32,7 @@ impl ScriptEngine {
         engine.disable_symbol("system");
 
         // Register Home Assistant API
-        register_ha_api(&mut engine);
+        register_ha_api(&mut engine, api);
 
         Self {
             engine: Arc::new(engine),
@@ -41,6 +48,22 @@ impl ScriptEngine {
         })
     }
 
+    /// Position and message of a `compile` failure.
+    pub fn compile_diagnostic(error: &EvalAltResult) -> CompileDiagnostic {
+        if let EvalAltResult::ErrorSystem(_, inner) = error {
+            if let Some(parse_error) = inner.downcast_ref::<ParseError>() {
+                return CompileDiagnostic::from_parse_error(parse_error);
+            }
+        }
+        let position = error.position();
+        CompileDiagnostic {
+            stage: CompileStage::Compile,
+            message: error.to_string(),
+            line: position.line(),
+            column: position.position(),
+        }
+    }
+
     pub fn run(&self, ast: &AST) -> Result<Dynamic, Box<EvalAltResult>> {
         let mut scope = Scope::new();
         self.engine
@@ -59,6 +82,21 @@ impl ScriptEngine {
         let ast = self.compile(script)?;
         self.run(&ast)
     }
+
+    /// Evaluate a script and return the value of its last expression.
+    pub fn eval_script(&self, script: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let ast = self.compile(script)?;
+        let mut scope = Scope::new();
+        self.engine
+            .as_ref()
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &ast)
+            .map_err(|e| {
+                Box::new(EvalAltResult::ErrorSystem(
+                    format!("Runtime error: {}", e),
+                    Box::new(e),
+                ))
+            })
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,552 @@
+use crate::blocks::{BlockArgument, BlockDefinition};
+use crate::ha_client::{Action, ActionField};
+use serde_json::Value;
+
+/// Typed view of a Home Assistant selector definition, e.g. `{"number": {"min": 0}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Entity {
+        domains: Vec<String>,
+    },
+    Number {
+        min: Option<f64>,
+        max: Option<f64>,
+        step: Option<f64>,
+    },
+    Select {
+        options: Vec<(String, String)>,
+    },
+    Boolean,
+    Time,
+    /// Stored in seconds
+    Duration,
+    ColorRgb,
+    Text,
+    Unknown(String),
+}
+
+impl Selector {
+    pub fn from_value(selector: &Value) -> Option<Self> {
+        let (kind, config) = selector.as_object()?.iter().next()?;
+
+        let selector = match kind.as_str() {
+            "entity" => Selector::Entity {
+                domains: entity_domains(config),
+            },
+            "number" => Selector::Number {
+                min: config.get("min").and_then(|v| v.as_f64()),
+                max: config.get("max").and_then(|v| v.as_f64()),
+                // HA allows `step: any`, which has no Blockly equivalent
+                step: config.get("step").and_then(|v| v.as_f64()),
+            },
+            "select" => Selector::Select {
+                options: select_options(config),
+            },
+            "boolean" => Selector::Boolean,
+            "time" => Selector::Time,
+            "duration" => Selector::Duration,
+            "color_rgb" => Selector::ColorRgb,
+            "text" => Selector::Text,
+            other => Selector::Unknown(other.to_string()),
+        };
+
+        Some(selector)
+    }
+
+    /// Blockly field type used to edit a value of this selector.
+    pub fn field_type(&self) -> &'static str {
+        match self {
+            Selector::Entity { .. } => "field_entity",
+            Selector::Number { .. } | Selector::Duration => "field_number",
+            Selector::Select { .. } => "field_dropdown",
+            Selector::Boolean => "field_checkbox",
+            Selector::Time => "field_time",
+            Selector::ColorRgb => "field_colour",
+            Selector::Text | Selector::Unknown(_) => "field_input",
+        }
+    }
+
+    pub fn to_block_argument(&self, name: &str, default: Option<&Value>) -> BlockArgument {
+        let mut argument = BlockArgument {
+            r#type: self.field_type().to_string(),
+            name: name.to_string(),
+            default: default.and_then(|d| self.default_string(d)),
+            ..Default::default()
+        };
+
+        match self {
+            Selector::Entity { domains } if !domains.is_empty() => {
+                argument.domain = Some(domains.clone());
+            }
+            Selector::Number { min, max, step } => {
+                argument.min = *min;
+                argument.max = *max;
+                argument.precision = *step;
+            }
+            Selector::Select { options } => {
+                argument.options = Some(
+                    options
+                        .iter()
+                        .map(|(label, value)| vec![label.clone(), value.clone()])
+                        .collect(),
+                );
+            }
+            _ => {}
+        }
+
+        argument
+    }
+
+    /// Rhai expression reading the rendered field `name` with the right type.
+    pub fn rhai_value(&self, name: &str) -> String {
+        match self {
+            Selector::Number { .. } | Selector::Duration => format!("{{{{{}}}}}", name),
+            Selector::Boolean => format!("{{{{rhai_string {}}}}} == \"TRUE\"", name),
+            // Free text, so quoted by the helper rather than pasted between quotes
+            _ => format!("{{{{rhai_string {}}}}}", name),
+        }
+    }
+
+    /// Check a value given for this selector and convert it to what the Blockly field stores.
+    pub fn field_value(&self, value: &Value) -> Result<Value, String> {
+        match (self, value) {
+            (Selector::Entity { domains }, Value::String(entity)) => {
+                let domain = entity.split_once('.').map(|(domain, _)| domain);
+                match domain {
+                    None => Err(format!("'{}' is not an entity id", entity)),
+                    Some(domain) if !domains.is_empty() && !domains.iter().any(|d| d == domain) => {
+                        Err(format!(
+                            "'{}' is not in domain {}",
+                            entity,
+                            domains.join(" or ")
+                        ))
+                    }
+                    Some(_) => Ok(value.clone()),
+                }
+            }
+            (Selector::Number { min, max, .. }, Value::Number(number)) => {
+                let n = number.as_f64().unwrap_or_default();
+                if min.is_some_and(|min| n < min) || max.is_some_and(|max| n > max) {
+                    Err(format!(
+                        "{} is outside {}..{}",
+                        n,
+                        min.map(|m| m.to_string()).unwrap_or_default(),
+                        max.map(|m| m.to_string()).unwrap_or_default()
+                    ))
+                } else {
+                    Ok(value.clone())
+                }
+            }
+            (Selector::Duration, _) => duration_seconds(value)
+                .map(Value::from)
+                .ok_or_else(|| format!("{} is not a duration", value)),
+            (Selector::Select { options }, Value::String(s)) => {
+                if options.iter().any(|(_, option)| option == s) {
+                    Ok(value.clone())
+                } else {
+                    Err(format!("'{}' is not one of the options", s))
+                }
+            }
+            (Selector::Boolean, Value::Bool(b)) => {
+                Ok(Value::from(if *b { "TRUE" } else { "FALSE" }))
+            }
+            (Selector::Text | Selector::Time | Selector::Unknown(_), Value::String(_)) => {
+                Ok(value.clone())
+            }
+            (Selector::ColorRgb, _) => self
+                .default_string(value)
+                .map(Value::from)
+                .ok_or_else(|| format!("{} is not an RGB colour", value)),
+            (Selector::Unknown(_), _) => Ok(value.clone()),
+            (_, other) => Err(format!("{} has the wrong type", other)),
+        }
+    }
+
+    fn default_string(&self, value: &Value) -> Option<String> {
+        match (self, value) {
+            (_, Value::Null) => None,
+            (Selector::Boolean, Value::Bool(b)) => {
+                Some(if *b { "TRUE" } else { "FALSE" }.to_string())
+            }
+            (Selector::ColorRgb, Value::Array(rgb)) => {
+                let channels: Vec<u64> = rgb.iter().filter_map(|c| c.as_u64()).collect();
+                match channels.as_slice() {
+                    [r, g, b] => Some(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+                    _ => None,
+                }
+            }
+            (_, Value::String(s)) => Some(s.clone()),
+            (_, other) => Some(other.to_string()),
+        }
+    }
+}
+
+/// Seconds of a duration given as seconds, `HH:MM:SS` or `{hours, minutes, seconds}`.
+fn duration_seconds(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64().filter(|n| *n >= 0.0),
+        Value::String(s) => {
+            let mut seconds = 0.0;
+            for part in s.split(':') {
+                seconds = seconds * 60.0 + part.trim().parse::<f64>().ok()?;
+            }
+            Some(seconds)
+        }
+        Value::Object(parts) => {
+            let mut seconds = 0.0;
+            for (unit, factor) in [
+                ("days", 86400.0),
+                ("hours", 3600.0),
+                ("minutes", 60.0),
+                ("seconds", 1.0),
+                ("milliseconds", 0.001),
+            ] {
+                if let Some(amount) = parts.get(unit) {
+                    seconds += amount.as_f64()? * factor;
+                }
+            }
+            Some(seconds)
+        }
+        _ => None,
+    }
+}
+
+fn string_or_list(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(s)) => vec![s.clone()],
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|v| v.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn entity_domains(config: &Value) -> Vec<String> {
+    // Older selectors put `domain` at the top level, newer ones nest it in `filter`
+    let mut domains = string_or_list(config.get("domain"));
+    match config.get("filter") {
+        Some(Value::Array(filters)) => {
+            for filter in filters {
+                domains.extend(string_or_list(filter.get("domain")));
+            }
+        }
+        Some(filter) => domains.extend(string_or_list(filter.get("domain"))),
+        None => {}
+    }
+    domains.dedup();
+    domains
+}
+
+fn select_options(config: &Value) -> Vec<(String, String)> {
+    config
+        .get("options")
+        .and_then(|o| o.as_array())
+        .map(|options| {
+            options
+                .iter()
+                .filter_map(|option| match option {
+                    Value::String(s) => Some((s.clone(), s.clone())),
+                    Value::Object(o) => {
+                        let value = o.get("value")?.as_str()?.to_string();
+                        let label = o
+                            .get("label")
+                            .and_then(|l| l.as_str())
+                            .map(str::to_string)
+                            .unwrap_or_else(|| value.clone());
+                        Some((label, value))
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn field_selector(field: &ActionField) -> Selector {
+    field
+        .selector
+        .as_ref()
+        .and_then(Selector::from_value)
+        .unwrap_or(Selector::Text)
+}
+
+/// Build the Blockly argument used to edit a single service field.
+pub fn field_argument(key: &str, field: &ActionField) -> BlockArgument {
+    field_selector(field).to_block_argument(key, field.default.as_ref())
+}
+
+/// Domains of the entities a service can target, `None` if it takes no entity target.
+fn target_domains(action: &Action) -> Option<Vec<String>> {
+    let entity = action.target.as_ref()?.get("entity")?;
+    // Newer Home Assistant versions list several entity filters
+    let mut domains = match entity {
+        Value::Array(filters) => filters.iter().flat_map(entity_domains).collect(),
+        filter => entity_domains(filter),
+    };
+    domains.dedup();
+    Some(domains)
+}
+
+/// Generate a statement block calling the given service with typed fields.
+///
+/// Targeted entities come first. Optional fields get a checkbox and are only sent when ticked,
+/// since Home Assistant rejects some combinations and treats e.g. `brightness: 0` as "off".
+pub fn service_block(action: &Action) -> Option<BlockDefinition> {
+    let id = action.id.as_ref()?;
+    let (domain, service) = id.split_once('.')?;
+
+    // Required fields first, then alphabetically for a stable layout
+    let mut fields: Vec<_> = action.fields.iter().collect();
+    fields.sort_by(|(a_key, a), (b_key, b)| {
+        b.required
+            .unwrap_or(false)
+            .cmp(&a.required.unwrap_or(false))
+            .then_with(|| a_key.cmp(b_key))
+    });
+
+    let mut message = action.name.clone().unwrap_or_else(|| id.clone());
+    let mut args = Vec::new();
+    let mut data = String::new();
+    if let Some(domains) = target_domains(action) {
+        // Older services list `entity_id` as a field instead
+        if !action.fields.contains_key("entity_id") {
+            let selector = Selector::Entity { domains };
+            message.push_str(&format!(" Entity %{}", args.len() + 1));
+            args.push(selector.to_block_argument("entity_id", None));
+            data.push_str(&format!(
+                "\"entity_id\": {}, ",
+                selector.rhai_value("entity_id")
+            ));
+        }
+    }
+    for (key, field) in fields {
+        let selector = field_selector(field);
+        let entry = format!("\"{}\": {}, ", key, selector.rhai_value(key));
+        if field.required.unwrap_or(false) {
+            message.push_str(&format!(" {} %{}", field.name, args.len() + 1));
+            data.push_str(&entry);
+        } else {
+            let include = format!("include_{}", key);
+            message.push_str(&format!(
+                " %{} {} %{}",
+                args.len() + 1,
+                field.name,
+                args.len() + 2
+            ));
+            args.push(Selector::Boolean.to_block_argument(&include, None));
+            data.push_str(&format!(
+                "{{{{#if (eq {} \"TRUE\")}}}}{}{{{{/if}}}}",
+                include, entry
+            ));
+        }
+        args.push(selector.to_block_argument(key, field.default.as_ref()));
+    }
+
+    Some(BlockDefinition {
+        r#type: format!("ha_service_{}_{}", domain, service),
+        message0: message,
+        args0: Some(args),
+        previous_statement: Some(true),
+        next_statement: Some(true),
+        colour: 60,
+        tooltip: action.description.clone().unwrap_or_default(),
+        category: Some("Actions".to_string()),
+        rhai_template: Some(format!(
+            "call_service(\"{}\", \"{}\", #{{ {}}});",
+            domain, service, data
+        )),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BlockStore;
+    use crate::codegen::generator::CodeGenerator;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_selector_parsing() {
+        assert_eq!(
+            Selector::from_value(&json!({"entity": {"domain": "light"}})),
+            Some(Selector::Entity {
+                domains: vec!["light".to_string()]
+            })
+        );
+        assert_eq!(
+            Selector::from_value(&json!({"entity": {"filter": [{"domain": ["light", "switch"]}]}})),
+            Some(Selector::Entity {
+                domains: vec!["light".to_string(), "switch".to_string()]
+            })
+        );
+        assert_eq!(
+            Selector::from_value(&json!({"number": {"min": 0, "max": 255, "step": "any"}})),
+            Some(Selector::Number {
+                min: Some(0.0),
+                max: Some(255.0),
+                step: None
+            })
+        );
+        assert_eq!(
+            Selector::from_value(&json!({"text": null})),
+            Some(Selector::Text)
+        );
+        assert_eq!(Selector::from_value(&json!(null)), None);
+    }
+
+    #[test]
+    fn test_selector_to_block_argument() {
+        let select = Selector::from_value(&json!({
+            "select": {"options": ["low", {"label": "High", "value": "high"}]}
+        }))
+        .unwrap();
+        let argument = select.to_block_argument("MODE", Some(&json!("low")));
+        assert_eq!(argument.r#type, "field_dropdown");
+        assert_eq!(
+            argument.options,
+            Some(vec![
+                vec!["low".to_string(), "low".to_string()],
+                vec!["High".to_string(), "high".to_string()],
+            ])
+        );
+        assert_eq!(argument.default, Some("low".to_string()));
+
+        let number = Selector::from_value(&json!({"number": {"min": 1, "max": 10, "step": 0.5}}))
+            .unwrap()
+            .to_block_argument("LEVEL", None);
+        assert_eq!(number.r#type, "field_number");
+        assert_eq!(number.min, Some(1.0));
+        assert_eq!(number.max, Some(10.0));
+        assert_eq!(number.precision, Some(0.5));
+
+        let boolean = Selector::Boolean.to_block_argument("FLAG", Some(&json!(true)));
+        assert_eq!(boolean.r#type, "field_checkbox");
+        assert_eq!(boolean.default, Some("TRUE".to_string()));
+
+        let colour = Selector::ColorRgb.to_block_argument("RGB", Some(&json!([255, 0, 16])));
+        assert_eq!(colour.r#type, "field_colour");
+        assert_eq!(colour.default, Some("#ff0010".to_string()));
+    }
+
+    fn field(name: &str, required: bool, selector: Value) -> ActionField {
+        ActionField {
+            name: name.to_string(),
+            description: None,
+            required: Some(required),
+            selector: Some(selector),
+            default: None,
+            example: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_service_block() {
+        let mut fields = HashMap::new();
+        fields.insert(
+            "brightness".to_string(),
+            field(
+                "Brightness",
+                false,
+                json!({"number": {"min": 0, "max": 255}}),
+            ),
+        );
+        fields.insert(
+            "flash".to_string(),
+            field(
+                "Flash",
+                false,
+                json!({"select": {"options": ["long", "short"]}}),
+            ),
+        );
+        fields.insert(
+            "transition".to_string(),
+            field(
+                "Transition",
+                false,
+                json!({"number": {"min": 0, "max": 300}}),
+            ),
+        );
+        let action = Action {
+            domain: Some("light".to_string()),
+            name: Some("Turn on".to_string()),
+            description: Some("Turn on a light".to_string()),
+            target: Some(json!({"entity": [{"domain": ["light"]}]})),
+            fields,
+            id: Some("light.turn_on".to_string()),
+        };
+
+        let block = service_block(&action).unwrap();
+        assert_eq!(block.r#type, "ha_service_light_turn_on");
+        assert_eq!(
+            block.message0,
+            "Turn on Entity %1 %2 Brightness %3 %4 Flash %5 %6 Transition %7"
+        );
+        let args = block.args0.clone().unwrap();
+        assert_eq!(args[0].name, "entity_id");
+        assert_eq!(args[0].domain, Some(vec!["light".to_string()]));
+        assert_eq!(args[1].name, "include_brightness");
+        assert_eq!(args[1].r#type, "field_checkbox");
+        assert_eq!(args[2].r#type, "field_number");
+
+        // Only the entity and the ticked fields are sent, whatever the other fields hold,
+        // and text stays inside its string literal
+        let temp_dir = tempfile::tempdir().unwrap();
+        let block_store = BlockStore::with_blocks_dir(temp_dir.path().to_path_buf())
+            .await
+            .unwrap();
+        block_store.create_or_update(block).await.unwrap();
+        let workspace = json!({"blocks": {"blocks": [{
+            "type": "ha_service_light_turn_on",
+            "fields": {
+                "entity_id": "light.kitchen",
+                "include_brightness": "TRUE",
+                "brightness": 128,
+                "include_flash": "TRUE",
+                "flash": "short\"); restart(\"",
+                "transition": 0
+            }
+        }]}});
+        let code = CodeGenerator::new(block_store)
+            .generate_code(&workspace, &HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            code,
+            r#"call_service("light", "turn_on", #{ "entity_id": "light.kitchen", "brightness": 128, "flash": "short\"); restart(\"", });"#
+        );
+        rhai::Engine::new().compile(&code).unwrap();
+    }
+
+    #[test]
+    fn test_service_block_entity_field() {
+        // Older services list the entity as a required field rather than a target
+        let mut fields = HashMap::new();
+        fields.insert(
+            "entity_id".to_string(),
+            field(
+                "Entity",
+                true,
+                json!({"entity": {"domain": "media_player"}}),
+            ),
+        );
+        let action = Action {
+            domain: Some("tts".to_string()),
+            name: Some("Say".to_string()),
+            description: None,
+            target: Some(json!({"entity": {"domain": "media_player"}})),
+            fields,
+            id: Some("tts.say".to_string()),
+        };
+
+        let block = service_block(&action).unwrap();
+        assert_eq!(block.message0, "Say Entity %1");
+        assert_eq!(block.args0.unwrap().len(), 1);
+        assert_eq!(
+            block.rhai_template.unwrap(),
+            r#"call_service("tts", "say", #{ "entity_id": {{rhai_string entity_id}}, });"#
+        );
+    }
+}
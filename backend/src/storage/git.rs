@@ -0,0 +1,459 @@
+use super::{
+    AutomationRepository, BlockRepository, BlueprintRepository, StoredBlocks,
+    YamlAutomationRepository, YamlBlockRepository, YamlBlueprintRepository,
+};
+use crate::automation::Automation;
+use crate::blocks::BlockDefinition;
+use crate::blueprint::Blueprint;
+use crate::diagnostics::LoadDiagnostic;
+use async_trait::async_trait;
+use git2::{ErrorCode, IndexAddOption, Repository, Signature, Sort};
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Generated or local-only files that stay out of the automations repository.
+const AUTOMATION_IGNORES: &[&str] = &[
+    "*.rhai",
+    "manifest.yaml",
+    "history/",
+    "quarantine/",
+    ".*.tmp",
+];
+const BLOCK_IGNORES: &[&str] = &["quarantine/", ".*.tmp"];
+const BLUEPRINT_IGNORES: &[&str] = &["quarantine/", ".*.tmp"];
+
+/// Used when git has no `user.name` and `user.email` configured.
+const DEFAULT_AUTHOR: (&str, &str) = ("Advanced Automation", "advanced-automation@localhost");
+
+/// A directory that is the working tree of its own git repository.
+#[derive(Clone)]
+pub struct GitWorkTree {
+    repo: Arc<Mutex<Repository>>,
+    workdir: PathBuf,
+}
+
+impl std::fmt::Debug for GitWorkTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GitWorkTree")
+            .field("workdir", &self.workdir)
+            .finish()
+    }
+}
+
+impl GitWorkTree {
+    /// Open the repository at `dir`, creating it if needed, and commit anything changed by hand.
+    pub async fn open(dir: &Path, ignores: &[&str]) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(dir).await?;
+        let workdir = tokio::fs::canonicalize(dir).await?;
+
+        // Only a repository rooted here counts; `dir` may sit inside an unrelated checkout
+        let (repo, message) = match Repository::open(&workdir) {
+            Ok(repo) => (repo, "Record changes made outside the add-on".to_string()),
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                tracing::info!("Initializing git repository in {:?}", workdir);
+                let name = workdir.file_name().unwrap_or_default().to_string_lossy();
+                (
+                    Repository::init(&workdir).map_err(Error::other)?,
+                    format!("Start tracking {}", name),
+                )
+            }
+            Err(e) => return Err(Error::other(e)),
+        };
+
+        let gitignore = workdir.join(".gitignore");
+        if !gitignore.exists() {
+            tokio::fs::write(&gitignore, ignores.join("\n") + "\n").await?;
+        }
+
+        let tree = Self {
+            repo: Arc::new(Mutex::new(repo)),
+            workdir,
+        };
+        tree.commit_all(&message).await?;
+        Ok(tree)
+    }
+
+    /// Run `f` with the repository on the blocking thread pool.
+    async fn call<T, F>(&self, f: F) -> std::io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Repository) -> Result<T, git2::Error> + Send + 'static,
+    {
+        let repo = self.repo.clone();
+        tokio::task::spawn_blocking(move || {
+            let repo = repo.lock().unwrap();
+            f(&repo).map_err(Error::other)
+        })
+        .await
+        .map_err(Error::other)?
+    }
+
+    fn relative(&self, path: &Path) -> std::io::Result<PathBuf> {
+        let path = match path.parent().and_then(|p| p.canonicalize().ok()) {
+            Some(parent) => parent.join(path.file_name().unwrap_or_default()),
+            None => path.to_path_buf(),
+        };
+        path.strip_prefix(&self.workdir)
+            .map(Path::to_path_buf)
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{:?} is outside {:?}", path, self.workdir),
+                )
+            })
+    }
+
+    /// Stage `paths` as they are on disk, deleted files included, and commit them.
+    pub async fn commit(&self, paths: &[PathBuf], message: String) -> std::io::Result<()> {
+        let paths = paths
+            .iter()
+            .map(|path| self.relative(path))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let workdir = self.workdir.clone();
+        self.call(move |repo| {
+            let mut index = repo.index()?;
+            for path in &paths {
+                if workdir.join(path).exists() {
+                    index.add_path(path)?;
+                } else {
+                    index.remove_path(path)?;
+                }
+            }
+            index.write()?;
+            commit_index(repo, &message)
+        })
+        .await
+    }
+
+    /// Stage every change in the working tree, respecting `.gitignore`, and commit it.
+    pub async fn commit_all(&self, message: &str) -> std::io::Result<()> {
+        let message = message.to_string();
+        self.call(move |repo| {
+            let mut index = repo.index()?;
+            index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+            index.update_all(["*"], None)?;
+            index.write()?;
+            commit_index(repo, &message)
+        })
+        .await
+    }
+
+    /// Distinct contents `path` had over the history, newest first.
+    pub async fn file_history(&self, path: &Path) -> std::io::Result<Vec<Vec<u8>>> {
+        let path = self.relative(path)?;
+        self.call(move |repo| {
+            let mut contents = Vec::new();
+            let mut walk = repo.revwalk()?;
+            match walk.push_head() {
+                Ok(()) => {}
+                Err(e) if e.code() == ErrorCode::UnbornBranch => return Ok(contents),
+                Err(e) => return Err(e),
+            }
+            walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+
+            let mut last = None;
+            for oid in walk {
+                let commit = repo.find_commit(oid?)?;
+                let blob_id = match commit.tree()?.get_path(&path) {
+                    Ok(entry) => Some(entry.id()),
+                    Err(e) if e.code() == ErrorCode::NotFound => None,
+                    Err(e) => return Err(e),
+                };
+                if blob_id != last {
+                    if let Some(blob_id) = blob_id {
+                        contents.push(repo.find_blob(blob_id)?.content().to_vec());
+                    }
+                    last = blob_id;
+                }
+            }
+            Ok(contents)
+        })
+        .await
+    }
+}
+
+/// Commit the index on top of `HEAD`, unless nothing changed.
+fn commit_index(repo: &Repository, message: &str) -> Result<(), git2::Error> {
+    let tree_id = repo.index()?.write_tree()?;
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if parent.as_ref().is_some_and(|p| p.tree_id() == tree_id) {
+        return Ok(());
+    }
+
+    let tree = repo.find_tree(tree_id)?;
+    let signature = repo
+        .signature()
+        .or_else(|_| Signature::now(DEFAULT_AUTHOR.0, DEFAULT_AUTHOR.1))?;
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+    tracing::debug!("Committed: {}", message);
+    Ok(())
+}
+
+/// YAML automation files in a git repository; versions come from the commit history.
+#[derive(Debug)]
+pub struct GitAutomationRepository {
+    files: YamlAutomationRepository,
+    git: GitWorkTree,
+}
+
+impl GitAutomationRepository {
+    pub async fn open(storage_path: PathBuf) -> std::io::Result<Self> {
+        let files = YamlAutomationRepository::open(storage_path)
+            .await?
+            .without_history();
+        let git = GitWorkTree::open(files.storage_path(), AUTOMATION_IGNORES).await?;
+        Ok(Self { files, git })
+    }
+
+    async fn current(&self, id: &str) -> Option<Automation> {
+        let content = tokio::fs::read_to_string(self.files.automation_path(id))
+            .await
+            .ok()?;
+        serde_yaml::from_str(&content).ok()
+    }
+
+    /// Every distinct committed state of an automation, newest first.
+    async fn revisions(&self, id: &str) -> std::io::Result<Vec<Automation>> {
+        let contents = self
+            .git
+            .file_history(&self.files.automation_path(id))
+            .await?;
+        Ok(contents
+            .iter()
+            .filter_map(|content| serde_yaml::from_slice(content).ok())
+            .collect())
+    }
+}
+
+fn save_message(previous: Option<&Automation>, automation: &Automation) -> String {
+    match previous {
+        None => format!("Create automation '{}'", automation.name),
+        Some(previous)
+            if previous.enabled != automation.enabled && previous.version == automation.version =>
+        {
+            let action = if automation.enabled {
+                "Enable"
+            } else {
+                "Disable"
+            };
+            format!("{} automation '{}'", action, automation.name)
+        }
+        Some(previous) if previous.name != automation.name => format!(
+            "Rename automation '{}' to '{}' (version {})",
+            previous.name, automation.name, automation.version
+        ),
+        Some(_) => format!(
+            "Update automation '{}' to version {}",
+            automation.name, automation.version
+        ),
+    }
+}
+
+#[async_trait]
+impl AutomationRepository for GitAutomationRepository {
+    async fn load(&self) -> std::io::Result<Vec<Automation>> {
+        self.files.load().await
+    }
+
+    async fn save(&self, automation: &Automation) -> std::io::Result<()> {
+        let previous = self.current(&automation.id).await;
+        self.files.save(automation).await?;
+        self.git
+            .commit(
+                &[self.files.automation_path(&automation.id)],
+                save_message(previous.as_ref(), automation),
+            )
+            .await
+    }
+
+    async fn delete(&self, id: &str) -> std::io::Result<()> {
+        let name = self
+            .current(id)
+            .await
+            .map(|a| a.name)
+            .unwrap_or_else(|| id.to_string());
+        self.files.delete(id).await?;
+        self.git
+            .commit(
+                &[self.files.automation_path(id)],
+                format!("Delete automation '{}'", name),
+            )
+            .await
+    }
+
+    async fn save_script(&self, automation: &Automation, script: &str) -> std::io::Result<()> {
+        self.files.save_script(automation, script).await
+    }
+
+    async fn script_is_current(&self, automation: &Automation) -> std::io::Result<bool> {
+        self.files.script_is_current(automation).await
+    }
+
+    async fn retain_scripts(&self, ids: &HashSet<String>) -> std::io::Result<()> {
+        self.files.retain_scripts(ids).await
+    }
+
+    async fn versions(&self, id: &str) -> std::io::Result<Vec<i32>> {
+        Ok(self.history(id).await?.iter().map(|a| a.version).collect())
+    }
+
+    async fn get_version(&self, id: &str, version: i32) -> std::io::Result<Option<Automation>> {
+        Ok(self
+            .revisions(id)
+            .await?
+            .into_iter()
+            .find(|a| a.version == version))
+    }
+
+    /// Walks the commit history once, keeping the newest revision of each version.
+    async fn history(&self, id: &str) -> std::io::Result<Vec<Automation>> {
+        let mut history = Vec::new();
+        let mut seen = HashSet::new();
+        for automation in self.revisions(id).await? {
+            if seen.insert(automation.version) {
+                history.push(automation);
+            }
+        }
+        history.sort_by_key(|a| a.version);
+        Ok(history)
+    }
+
+    /// Git history is never rewritten, so there is nothing to prune.
+    async fn delete_version(&self, _id: &str, _version: i32) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.files.diagnostics()
+    }
+}
+
+/// YAML block files in a git repository.
+#[derive(Debug)]
+pub struct GitBlockRepository {
+    files: YamlBlockRepository,
+    git: GitWorkTree,
+}
+
+impl GitBlockRepository {
+    pub async fn open(blocks_dir: PathBuf) -> std::io::Result<Self> {
+        let git = GitWorkTree::open(&blocks_dir, BLOCK_IGNORES).await?;
+        Ok(Self {
+            files: YamlBlockRepository::new(blocks_dir),
+            git,
+        })
+    }
+
+    /// The repository of the blocks directory, for other files kept in it.
+    pub fn work_tree(&self) -> GitWorkTree {
+        self.git.clone()
+    }
+}
+
+#[async_trait]
+impl BlockRepository for GitBlockRepository {
+    async fn load(&self) -> std::io::Result<StoredBlocks> {
+        self.files.load().await
+    }
+
+    async fn save_block(&self, block: &BlockDefinition) -> std::io::Result<()> {
+        let path = self.files.block_path(block);
+        let action = if path.exists() { "Update" } else { "Create" };
+        self.files.save_block(block).await?;
+        self.git
+            .commit(&[path], format!("{} block '{}'", action, block.r#type))
+            .await
+    }
+
+    async fn delete_block(&self, block: &BlockDefinition) -> std::io::Result<()> {
+        self.files.delete_block(block).await?;
+        self.git
+            .commit(
+                &[self.files.block_path(block)],
+                format!("Delete block '{}'", block.r#type),
+            )
+            .await
+    }
+
+    async fn save_template(&self, block_type: &str, template: &str) -> std::io::Result<()> {
+        self.files.save_template(block_type, template).await?;
+        self.git
+            .commit(
+                &[self
+                    .files
+                    .imported_dir()
+                    .join(format!("{}.rhai", block_type))],
+                format!("Import template for '{}'", block_type),
+            )
+            .await
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.files.diagnostics()
+    }
+}
+
+/// YAML blueprint files in a git repository.
+#[derive(Debug)]
+pub struct GitBlueprintRepository {
+    files: YamlBlueprintRepository,
+    git: GitWorkTree,
+}
+
+impl GitBlueprintRepository {
+    pub async fn open(blueprints_dir: PathBuf) -> std::io::Result<Self> {
+        let files = YamlBlueprintRepository::open(blueprints_dir).await?;
+        let git = GitWorkTree::open(files.blueprints_dir(), BLUEPRINT_IGNORES).await?;
+        Ok(Self { files, git })
+    }
+}
+
+#[async_trait]
+impl BlueprintRepository for GitBlueprintRepository {
+    async fn load(&self) -> std::io::Result<Vec<Blueprint>> {
+        self.files.load().await
+    }
+
+    async fn save(&self, blueprint: &Blueprint) -> std::io::Result<()> {
+        let message = if blueprint.version == 1 {
+            format!("Create blueprint '{}'", blueprint.name)
+        } else {
+            format!(
+                "Update blueprint '{}' to version {}",
+                blueprint.name, blueprint.version
+            )
+        };
+        self.files.save(blueprint).await?;
+        self.git
+            .commit(&[self.files.blueprint_path(&blueprint.id)], message)
+            .await
+    }
+
+    async fn delete(&self, blueprint: &Blueprint) -> std::io::Result<()> {
+        self.files.delete(blueprint).await?;
+        self.git
+            .commit(
+                &[self.files.blueprint_path(&blueprint.id)],
+                format!("Delete blueprint '{}'", blueprint.name),
+            )
+            .await
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.files.diagnostics()
+    }
+}
@@ -0,0 +1,96 @@
+pub mod git;
+pub mod sqlite;
+pub mod yaml;
+
+use crate::automation::Automation;
+use crate::blocks::BlockDefinition;
+use crate::blueprint::Blueprint;
+use crate::diagnostics::LoadDiagnostic;
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+
+pub use git::{GitAutomationRepository, GitBlockRepository, GitBlueprintRepository, GitWorkTree};
+pub use sqlite::{
+    SqliteAutomationRepository, SqliteBlockRepository, SqliteBlueprintRepository, SqliteDatabase,
+};
+pub use yaml::{YamlAutomationRepository, YamlBlockRepository, YamlBlueprintRepository};
+
+/// Persistence for automations, their compiled scripts and their version history.
+///
+/// `AutomationStore` keeps the working set in memory and writes through to a repository.
+#[async_trait]
+pub trait AutomationRepository: std::fmt::Debug + Send + Sync {
+    /// Every stored automation; entries that cannot be read are reported as diagnostics.
+    async fn load(&self) -> std::io::Result<Vec<Automation>>;
+
+    /// Store the automation and a snapshot of this version.
+    async fn save(&self, automation: &Automation) -> std::io::Result<()>;
+
+    /// Remove the automation together with its script and history.
+    async fn delete(&self, id: &str) -> std::io::Result<()>;
+
+    async fn save_script(&self, automation: &Automation, script: &str) -> std::io::Result<()>;
+
+    /// Whether the stored script was generated from this version of the automation.
+    async fn script_is_current(&self, automation: &Automation) -> std::io::Result<bool>;
+
+    /// Forget scripts of automations that are not in `ids`.
+    async fn retain_scripts(&self, ids: &HashSet<String>) -> std::io::Result<()>;
+
+    /// Version numbers with a stored snapshot, oldest first.
+    async fn versions(&self, id: &str) -> std::io::Result<Vec<i32>>;
+
+    async fn get_version(&self, id: &str, version: i32) -> std::io::Result<Option<Automation>>;
+
+    async fn delete_version(&self, id: &str, version: i32) -> std::io::Result<()>;
+
+    /// Every stored snapshot, oldest first.
+    async fn history(&self, id: &str) -> std::io::Result<Vec<Automation>> {
+        let mut history = Vec::new();
+        for version in self.versions(id).await? {
+            if let Some(automation) = self.get_version(id, version).await? {
+                history.push(automation);
+            }
+        }
+        Ok(history)
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic>;
+}
+
+/// Everything a block repository holds, as loaded at startup.
+#[derive(Debug, Clone, Default)]
+pub struct StoredBlocks {
+    pub blocks: HashMap<String, BlockDefinition>,
+    /// Built-in Rhai templates by block type
+    pub templates: HashMap<String, String>,
+    /// Built-in templates added through `save_template` rather than shipped
+    pub imported_templates: HashSet<String>,
+}
+
+/// Persistence for block definitions and built-in templates.
+#[async_trait]
+pub trait BlockRepository: std::fmt::Debug + Send + Sync {
+    async fn load(&self) -> std::io::Result<StoredBlocks>;
+
+    async fn save_block(&self, block: &BlockDefinition) -> std::io::Result<()>;
+
+    async fn delete_block(&self, block: &BlockDefinition) -> std::io::Result<()>;
+
+    async fn save_template(&self, block_type: &str, template: &str) -> std::io::Result<()>;
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic>;
+}
+
+/// Persistence for blueprints.
+#[async_trait]
+pub trait BlueprintRepository: std::fmt::Debug + Send + Sync {
+    /// Every stored blueprint; entries that cannot be read are reported as diagnostics.
+    async fn load(&self) -> std::io::Result<Vec<Blueprint>>;
+
+    async fn save(&self, blueprint: &Blueprint) -> std::io::Result<()>;
+
+    async fn delete(&self, blueprint: &Blueprint) -> std::io::Result<()>;
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic>;
+}
@@ -0,0 +1,492 @@
+use super::{
+    AutomationRepository, BlockRepository, BlueprintRepository, StoredBlocks, YamlBlockRepository,
+};
+use crate::automation::Automation;
+use crate::blocks::BlockDefinition;
+use crate::blueprint::Blueprint;
+use crate::diagnostics::{DiagnosticSource, Diagnostics, LoadDiagnostic};
+use crate::persist::content_hash;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS automations (
+    id TEXT PRIMARY KEY,
+    version INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS automation_versions (
+    id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (id, version)
+);
+CREATE TABLE IF NOT EXISTS automation_scripts (
+    id TEXT PRIMARY KEY,
+    version INTEGER NOT NULL,
+    script TEXT NOT NULL,
+    hash TEXT
+);
+-- A NULL definition hides a shipped block of the same type
+CREATE TABLE IF NOT EXISTS blocks (
+    type TEXT PRIMARY KEY,
+    data TEXT
+);
+CREATE TABLE IF NOT EXISTS block_templates (
+    type TEXT PRIMARY KEY,
+    template TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS blueprints (
+    id TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+";
+
+/// A SQLite database shared by the automation, block and blueprint repositories.
+#[derive(Debug, Clone)]
+pub struct SqliteDatabase {
+    conn: Arc<Mutex<Connection>>,
+    path: Option<PathBuf>,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).map_err(Error::other)?;
+        // WAL keeps readers working during writes and survives power loss
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(Error::other)?;
+        Self::init(conn, Some(path.to_path_buf()))
+    }
+
+    /// A database that lives only as long as this value, e.g. for tests.
+    pub fn open_in_memory() -> std::io::Result<Self> {
+        Self::init(Connection::open_in_memory().map_err(Error::other)?, None)
+    }
+
+    fn init(conn: Connection, path: Option<PathBuf>) -> std::io::Result<Self> {
+        conn.execute_batch(SCHEMA).map_err(Error::other)?;
+        // Databases from before script hashes get the column; their scripts are rebuilt once
+        if conn.prepare("SELECT hash FROM automation_scripts").is_err() {
+            conn.execute_batch("ALTER TABLE automation_scripts ADD COLUMN hash TEXT")
+                .map_err(Error::other)?;
+        }
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            path,
+        })
+    }
+
+    /// Name used in diagnostics for a row of `table`.
+    fn row_path(&self, table: &str, key: &str) -> PathBuf {
+        let db = self
+            .path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| ":memory:".to_string());
+        PathBuf::from(format!("{}#{}/{}", db, table, key))
+    }
+
+    /// Run `f` with the connection on the blocking thread pool.
+    async fn call<T, F>(&self, f: F) -> std::io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap();
+            f(&conn)
+        })
+        .await
+        .map_err(Error::other)?
+        .map_err(Error::other)
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> std::io::Result<String> {
+    serde_json::to_string(value).map_err(Error::other)
+}
+
+#[derive(Debug)]
+pub struct SqliteAutomationRepository {
+    db: SqliteDatabase,
+    diagnostics: Diagnostics,
+}
+
+impl SqliteAutomationRepository {
+    pub fn new(db: SqliteDatabase) -> Self {
+        Self {
+            db,
+            diagnostics: Diagnostics::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl AutomationRepository for SqliteAutomationRepository {
+    async fn load(&self) -> std::io::Result<Vec<Automation>> {
+        let rows: Vec<(String, String)> = self
+            .db
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT id, data FROM automations")?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            })
+            .await?;
+
+        let mut automations = Vec::new();
+        for (id, data) in rows {
+            match serde_json::from_str::<Automation>(&data) {
+                Ok(automation) => automations.push(automation),
+                // The row is left alone; its id is unknown to the store so it is never overwritten
+                Err(e) => {
+                    let mut diagnostic = LoadDiagnostic::new(
+                        DiagnosticSource::Automation,
+                        &self.db.row_path("automations", &id),
+                        &e,
+                    );
+                    diagnostic.line = Some(e.line());
+                    diagnostic.column = Some(e.column());
+                    self.diagnostics.record(diagnostic);
+                }
+            }
+        }
+        Ok(automations)
+    }
+
+    async fn save(&self, automation: &Automation) -> std::io::Result<()> {
+        let data = to_json(automation)?;
+        let (id, version) = (automation.id.clone(), automation.version);
+        self.db
+            .call(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                tx.execute(
+                    "INSERT OR REPLACE INTO automations (id, version, data) VALUES (?1, ?2, ?3)",
+                    params![id, version, data],
+                )?;
+                tx.execute(
+                    "INSERT OR REPLACE INTO automation_versions (id, version, data) VALUES (?1, ?2, ?3)",
+                    params![id, version, data],
+                )?;
+                tx.commit()
+            })
+            .await
+    }
+
+    async fn delete(&self, id: &str) -> std::io::Result<()> {
+        let id = id.to_string();
+        self.db
+            .call(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                for table in ["automations", "automation_versions", "automation_scripts"] {
+                    tx.execute(&format!("DELETE FROM {} WHERE id = ?1", table), [&id])?;
+                }
+                tx.commit()
+            })
+            .await
+    }
+
+    async fn save_script(&self, automation: &Automation, script: &str) -> std::io::Result<()> {
+        let (id, version, script) = (
+            automation.id.clone(),
+            automation.version,
+            script.to_string(),
+        );
+        let hash = content_hash(script.as_bytes());
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO automation_scripts (id, version, script, hash) VALUES (?1, ?2, ?3, ?4)",
+                    params![id, version, script, hash],
+                )
+                .map(|_| ())
+            })
+            .await
+    }
+
+    async fn script_is_current(&self, automation: &Automation) -> std::io::Result<bool> {
+        let (id, version) = (automation.id.clone(), automation.version);
+        let stored: Option<(i32, String, Option<String>)> = self
+            .db
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT version, script, hash FROM automation_scripts WHERE id = ?1",
+                    [id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()
+            })
+            .await?;
+        Ok(matches!(
+            stored,
+            Some((stored_version, script, Some(hash)))
+                if stored_version == version && hash == content_hash(script.as_bytes())
+        ))
+    }
+
+    async fn retain_scripts(&self, ids: &HashSet<String>) -> std::io::Result<()> {
+        let ids = ids.clone();
+        self.db
+            .call(move |conn| {
+                let stored: Vec<String> = conn
+                    .prepare("SELECT id FROM automation_scripts")?
+                    .query_map([], |row| row.get(0))?
+                    .collect::<rusqlite::Result<_>>()?;
+                for id in stored.iter().filter(|id| !ids.contains(*id)) {
+                    conn.execute("DELETE FROM automation_scripts WHERE id = ?1", [id])?;
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    async fn versions(&self, id: &str) -> std::io::Result<Vec<i32>> {
+        let id = id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.prepare(
+                    "SELECT version FROM automation_versions WHERE id = ?1 ORDER BY version",
+                )?
+                .query_map([id], |row| row.get(0))?
+                .collect()
+            })
+            .await
+    }
+
+    async fn get_version(&self, id: &str, version: i32) -> std::io::Result<Option<Automation>> {
+        let id = id.to_string();
+        let data: Option<String> = self
+            .db
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT data FROM automation_versions WHERE id = ?1 AND version = ?2",
+                    params![id, version],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+        data.map(|data| {
+            serde_json::from_str(&data).map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .transpose()
+    }
+
+    async fn delete_version(&self, id: &str, version: i32) -> std::io::Result<()> {
+        let id = id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM automation_versions WHERE id = ?1 AND version = ?2",
+                    params![id, version],
+                )
+                .map(|_| ())
+            })
+            .await
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.diagnostics.list()
+    }
+}
+
+/// Blocks and templates in SQLite, on top of the blocks shipped in the blocks directory.
+#[derive(Debug)]
+pub struct SqliteBlockRepository {
+    db: SqliteDatabase,
+    shipped: Option<YamlBlockRepository>,
+    diagnostics: Diagnostics,
+}
+
+impl SqliteBlockRepository {
+    pub fn new(db: SqliteDatabase, shipped_blocks_dir: Option<PathBuf>) -> Self {
+        Self {
+            db,
+            shipped: shipped_blocks_dir.map(YamlBlockRepository::new),
+            diagnostics: Diagnostics::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl BlockRepository for SqliteBlockRepository {
+    async fn load(&self) -> std::io::Result<StoredBlocks> {
+        let mut stored = match &self.shipped {
+            Some(shipped) => shipped.load().await?,
+            None => StoredBlocks::default(),
+        };
+
+        let (blocks, templates) = self
+            .db
+            .call(|conn| {
+                let blocks: Vec<(String, Option<String>)> = conn
+                    .prepare("SELECT type, data FROM blocks")?
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<rusqlite::Result<_>>()?;
+                let templates: Vec<(String, String)> = conn
+                    .prepare("SELECT type, template FROM block_templates")?
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<rusqlite::Result<_>>()?;
+                Ok((blocks, templates))
+            })
+            .await?;
+
+        for (block_type, data) in blocks {
+            let Some(data) = data else {
+                stored.blocks.remove(&block_type);
+                continue;
+            };
+            match serde_json::from_str::<BlockDefinition>(&data) {
+                Ok(block) => {
+                    if let Err(e) = block.validate() {
+                        self.diagnostics.record(LoadDiagnostic::new(
+                            DiagnosticSource::Block,
+                            &self.db.row_path("blocks", &block_type),
+                            e,
+                        ));
+                    }
+                    stored.blocks.insert(block_type, block);
+                }
+                Err(e) => self.diagnostics.record(LoadDiagnostic::new(
+                    DiagnosticSource::Block,
+                    &self.db.row_path("blocks", &block_type),
+                    e,
+                )),
+            }
+        }
+        for (block_type, template) in templates {
+            stored.imported_templates.insert(block_type.clone());
+            stored.templates.insert(block_type, template);
+        }
+
+        Ok(stored)
+    }
+
+    async fn save_block(&self, block: &BlockDefinition) -> std::io::Result<()> {
+        let (block_type, data) = (block.r#type.clone(), to_json(block)?);
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO blocks (type, data) VALUES (?1, ?2)",
+                    params![block_type, data],
+                )
+                .map(|_| ())
+            })
+            .await
+    }
+
+    async fn delete_block(&self, block: &BlockDefinition) -> std::io::Result<()> {
+        let block_type = block.r#type.clone();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO blocks (type, data) VALUES (?1, NULL)",
+                    [block_type],
+                )
+                .map(|_| ())
+            })
+            .await
+    }
+
+    async fn save_template(&self, block_type: &str, template: &str) -> std::io::Result<()> {
+        let (block_type, template) = (block_type.to_string(), template.to_string());
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO block_templates (type, template) VALUES (?1, ?2)",
+                    params![block_type, template],
+                )
+                .map(|_| ())
+            })
+            .await
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        let mut diagnostics = self.diagnostics.list();
+        if let Some(shipped) = &self.shipped {
+            diagnostics.extend(shipped.diagnostics());
+        }
+        diagnostics
+    }
+}
+
+#[derive(Debug)]
+pub struct SqliteBlueprintRepository {
+    db: SqliteDatabase,
+    diagnostics: Diagnostics,
+}
+
+impl SqliteBlueprintRepository {
+    pub fn new(db: SqliteDatabase) -> Self {
+        Self {
+            db,
+            diagnostics: Diagnostics::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl BlueprintRepository for SqliteBlueprintRepository {
+    async fn load(&self) -> std::io::Result<Vec<Blueprint>> {
+        let rows: Vec<(String, String)> = self
+            .db
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT id, data FROM blueprints")?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            })
+            .await?;
+
+        let mut blueprints = Vec::new();
+        for (id, data) in rows {
+            match serde_json::from_str::<Blueprint>(&data) {
+                Ok(blueprint) => blueprints.push(blueprint),
+                Err(e) => {
+                    let mut diagnostic = LoadDiagnostic::new(
+                        DiagnosticSource::Blueprint,
+                        &self.db.row_path("blueprints", &id),
+                        &e,
+                    );
+                    diagnostic.line = Some(e.line());
+                    diagnostic.column = Some(e.column());
+                    self.diagnostics.record(diagnostic);
+                }
+            }
+        }
+        Ok(blueprints)
+    }
+
+    async fn save(&self, blueprint: &Blueprint) -> std::io::Result<()> {
+        let (id, data) = (blueprint.id.clone(), to_json(blueprint)?);
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO blueprints (id, data) VALUES (?1, ?2)",
+                    params![id, data],
+                )
+                .map(|_| ())
+            })
+            .await
+    }
+
+    async fn delete(&self, blueprint: &Blueprint) -> std::io::Result<()> {
+        let id = blueprint.id.clone();
+        self.db
+            .call(move |conn| {
+                conn.execute("DELETE FROM blueprints WHERE id = ?1", [id])
+                    .map(|_| ())
+            })
+            .await
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.diagnostics.list()
+    }
+}
@@ -0,0 +1,546 @@
+use super::{AutomationRepository, BlockRepository, BlueprintRepository, StoredBlocks};
+use crate::automation::Automation;
+use crate::blocks::BlockDefinition;
+use crate::blueprint::Blueprint;
+use crate::diagnostics::{quarantine, DiagnosticSource, Diagnostics, LoadDiagnostic};
+use crate::persist::{content_hash, remove_stale_temp_files, write_atomic};
+use async_trait::async_trait;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::RwLock;
+use walkdir::WalkDir;
+
+const MANIFEST_FILE: &str = "manifest.yaml";
+
+/// Ties each `<id>.rhai` script to the automation version it was generated from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ScriptManifest {
+    #[serde(default)]
+    scripts: BTreeMap<String, ScriptEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ScriptEntry {
+    version: i32,
+    hash: String,
+}
+
+/// One `<id>.yaml` and `<id>.rhai` per automation, with versions under `history/<id>/`.
+#[derive(Debug)]
+pub struct YamlAutomationRepository {
+    storage_path: PathBuf,
+    manifest: RwLock<ScriptManifest>,
+    diagnostics: Diagnostics,
+    /// Whether `save` keeps a snapshot under `history/`
+    keep_history: bool,
+}
+
+impl YamlAutomationRepository {
+    pub async fn open(storage_path: PathBuf) -> std::io::Result<Self> {
+        // Ensure the storage directory exists
+        tracing::debug!("Creating storage directory: {:?}", storage_path);
+        fs::create_dir_all(&storage_path).await?;
+        remove_stale_temp_files(&storage_path).await?;
+
+        let manifest = match fs::read_to_string(storage_path.join(MANIFEST_FILE)).await {
+            Ok(content) => serde_yaml::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring unreadable script manifest: {}", e);
+                ScriptManifest::default()
+            }),
+            Err(_) => ScriptManifest::default(),
+        };
+
+        Ok(Self {
+            storage_path,
+            manifest: RwLock::new(manifest),
+            diagnostics: Diagnostics::default(),
+            keep_history: true,
+        })
+    }
+
+    /// Leave version snapshots to something else, such as git.
+    pub fn without_history(mut self) -> Self {
+        self.keep_history = false;
+        self
+    }
+
+    pub fn storage_path(&self) -> &Path {
+        &self.storage_path
+    }
+
+    pub(super) fn automation_path(&self, id: &str) -> PathBuf {
+        self.storage_path.join(format!("{}.yaml", id))
+    }
+
+    fn history_dir(&self, id: &str) -> PathBuf {
+        self.storage_path.join("history").join(id)
+    }
+
+    fn script_path(&self, id: &str) -> PathBuf {
+        self.storage_path.join(format!("{}.rhai", id))
+    }
+
+    fn load_blocking(
+        storage_path: &Path,
+        diagnostics: &Diagnostics,
+    ) -> std::io::Result<Vec<Automation>> {
+        let mut automations = Vec::new();
+        for entry in std::fs::read_dir(storage_path)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "yaml") || path.ends_with(MANIFEST_FILE) {
+                continue;
+            }
+
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    diagnostics.record(LoadDiagnostic::new(DiagnosticSource::Automation, &path, e));
+                    continue;
+                }
+            };
+            match serde_yaml::from_str::<Automation>(&content) {
+                Ok(automation) => automations.push(automation),
+                Err(e) => {
+                    // Move the file aside so a later save cannot overwrite what is left of it
+                    diagnostics.record(quarantine(
+                        storage_path,
+                        LoadDiagnostic::from_yaml_error(DiagnosticSource::Automation, &path, &e),
+                    ));
+                }
+            }
+        }
+        Ok(automations)
+    }
+
+    async fn write_manifest(&self, manifest: &ScriptManifest) -> std::io::Result<()> {
+        let yaml = serde_yaml::to_string(manifest).map_err(Error::other)?;
+        write_atomic(self.storage_path.join(MANIFEST_FILE), yaml).await
+    }
+}
+
+#[async_trait]
+impl AutomationRepository for YamlAutomationRepository {
+    async fn load(&self) -> std::io::Result<Vec<Automation>> {
+        // Quarantining renames files, so the whole load runs off the async workers
+        let storage_path = self.storage_path.clone();
+        let diagnostics = self.diagnostics.clone();
+        tokio::task::spawn_blocking(move || Self::load_blocking(&storage_path, &diagnostics))
+            .await
+            .map_err(Error::other)?
+    }
+
+    async fn save(&self, automation: &Automation) -> std::io::Result<()> {
+        let yaml = serde_yaml::to_string(&automation).map_err(Error::other)?;
+
+        let file_path = self.automation_path(&automation.id);
+        tracing::debug!("Writing to file: {:?}", file_path);
+        write_atomic(&file_path, &yaml).await?;
+        if !self.keep_history {
+            return Ok(());
+        }
+
+        let history_dir = self.history_dir(&automation.id);
+        fs::create_dir_all(&history_dir).await?;
+        write_atomic(
+            history_dir.join(format!("{}.yaml", automation.version)),
+            yaml,
+        )
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> std::io::Result<()> {
+        // Delete YAML file
+        let yaml_path = self.automation_path(id);
+        if yaml_path.exists() {
+            fs::remove_file(&yaml_path).await?;
+        }
+
+        // Delete Rhai script
+        let rhai_path = self.script_path(id);
+        if rhai_path.exists() {
+            fs::remove_file(&rhai_path).await?;
+        }
+
+        // Delete stored versions
+        let history_dir = self.history_dir(id);
+        if history_dir.exists() {
+            fs::remove_dir_all(&history_dir).await?;
+        }
+
+        let mut manifest = self.manifest.write().await;
+        if manifest.scripts.remove(id).is_some() {
+            self.write_manifest(&manifest).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn save_script(&self, automation: &Automation, script: &str) -> std::io::Result<()> {
+        // Save the compiled script, then record which version it belongs to
+        let script_path = self.script_path(&automation.id);
+        tracing::debug!("Writing Rhai script to: {:?}", script_path);
+        write_atomic(script_path, script).await?;
+
+        let mut manifest = self.manifest.write().await;
+        manifest.scripts.insert(
+            automation.id.clone(),
+            ScriptEntry {
+                version: automation.version,
+                hash: content_hash(script.as_bytes()),
+            },
+        );
+        self.write_manifest(&manifest).await
+    }
+
+    async fn script_is_current(&self, automation: &Automation) -> std::io::Result<bool> {
+        let script_hash = fs::read(self.script_path(&automation.id))
+            .await
+            .ok()
+            .map(|script| content_hash(&script));
+        let manifest = self.manifest.read().await;
+
+        Ok(matches!(
+            (manifest.scripts.get(&automation.id), &script_hash),
+            (Some(entry), Some(hash)) if entry.version == automation.version && &entry.hash == hash
+        ))
+    }
+
+    async fn retain_scripts(&self, ids: &HashSet<String>) -> std::io::Result<()> {
+        let mut manifest = self.manifest.write().await;
+        let before = manifest.scripts.len();
+        manifest.scripts.retain(|id, _| ids.contains(id));
+        if manifest.scripts.len() != before {
+            self.write_manifest(&manifest).await?;
+        }
+        Ok(())
+    }
+
+    async fn versions(&self, id: &str) -> std::io::Result<Vec<i32>> {
+        let history_dir = self.history_dir(id);
+        let mut versions = Vec::new();
+        if !history_dir.exists() {
+            return Ok(versions);
+        }
+
+        let mut entries = fs::read_dir(&history_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "yaml") {
+                if let Some(version) = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(|s| s.parse().ok())
+                {
+                    versions.push(version);
+                }
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    async fn get_version(&self, id: &str, version: i32) -> std::io::Result<Option<Automation>> {
+        let path = self.history_dir(id).join(format!("{}.yaml", version));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path).await?;
+        let automation =
+            serde_yaml::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(Some(automation))
+    }
+
+    async fn delete_version(&self, id: &str, version: i32) -> std::io::Result<()> {
+        fs::remove_file(self.history_dir(id).join(format!("{}.yaml", version))).await
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.diagnostics.list()
+    }
+}
+
+/// Directory for the blocks of a category: its name in lower case, with path separators and
+/// characters Windows rejects replaced and surrounding dots removed, so any display name
+/// stays inside the blocks directory.
+fn category_dir_name(category: &str) -> String {
+    let name: String = category
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_control() || "/\\<>:\"|?*".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    // No hidden directories, and never `.` or `..`
+    let name = name.trim().trim_matches('.');
+    if name.is_empty() {
+        "custom".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Block definitions as `<category>/<type>.yaml` and built-in templates under `builtin/`.
+#[derive(Debug, Clone)]
+pub struct YamlBlockRepository {
+    blocks_dir: PathBuf,
+    diagnostics: Diagnostics,
+}
+
+impl YamlBlockRepository {
+    pub fn new(blocks_dir: PathBuf) -> Self {
+        Self {
+            blocks_dir,
+            diagnostics: Diagnostics::default(),
+        }
+    }
+
+    pub(super) fn imported_dir(&self) -> PathBuf {
+        self.blocks_dir.join("builtin").join("imported")
+    }
+
+    pub(super) fn block_path(&self, block: &BlockDefinition) -> PathBuf {
+        let category_dir = match &block.category {
+            Some(category) => self.blocks_dir.join(category_dir_name(category)),
+            None => self.blocks_dir.join("custom"),
+        };
+        category_dir.join(format!("{}.yaml", block.r#type))
+    }
+
+    fn load_templates(&self, stored: &mut StoredBlocks) {
+        let builtin_dir = self.blocks_dir.join("builtin");
+        if !builtin_dir.exists() {
+            return;
+        }
+
+        let imported_dir = self.imported_dir();
+        for entry in WalkDir::new(&builtin_dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if entry.path().extension().is_some_and(|ext| ext == "rhai") {
+                match std::fs::read_to_string(entry.path()) {
+                    Ok(content) => {
+                        let block_type = entry
+                            .path()
+                            .file_stem()
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string();
+                        info!(
+                            "Loaded built-in template: {} from {}",
+                            block_type,
+                            entry.path().display()
+                        );
+                        if entry.path().starts_with(&imported_dir) {
+                            stored.imported_templates.insert(block_type.clone());
+                        }
+                        stored.templates.insert(block_type, content);
+                    }
+                    Err(e) => {
+                        self.diagnostics.record(LoadDiagnostic::new(
+                            DiagnosticSource::BuiltinTemplate,
+                            entry.path(),
+                            e,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn load_blocking(&self) -> StoredBlocks {
+        let mut stored = StoredBlocks::default();
+        self.load_templates(&mut stored);
+
+        // Load custom block YAML files, skipping toolbox layouts and quarantined files
+        for entry in WalkDir::new(&self.blocks_dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|e| {
+                e.depth() != 1
+                    || (e.file_name() != crate::blockly::TOOLBOX_DIR
+                        && e.file_name() != crate::diagnostics::QUARANTINE_DIR)
+            })
+            .filter_map(|e| e.ok())
+        {
+            if entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "yaml" || ext == "yml")
+            {
+                self.load_block(entry.path(), &mut stored);
+            }
+        }
+
+        stored
+    }
+
+    fn load_block(&self, path: &Path, stored: &mut StoredBlocks) {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                self.diagnostics
+                    .record(LoadDiagnostic::new(DiagnosticSource::Block, path, e));
+                return;
+            }
+        };
+
+        match serde_yaml::from_str::<BlockDefinition>(&content) {
+            Ok(block) => {
+                // Still loaded so it can be fixed in the editor
+                if let Err(e) = block.validate() {
+                    self.diagnostics
+                        .record(LoadDiagnostic::new(DiagnosticSource::Block, path, e));
+                }
+                info!("Loaded block: {} from {}", block.r#type, path.display());
+                stored.blocks.insert(block.r#type.clone(), block);
+            }
+            Err(e) => {
+                self.diagnostics.record(quarantine(
+                    &self.blocks_dir,
+                    LoadDiagnostic::from_yaml_error(DiagnosticSource::Block, path, &e),
+                ));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BlockRepository for YamlBlockRepository {
+    async fn load(&self) -> std::io::Result<StoredBlocks> {
+        // Walking and reading the directory tree blocks, so it runs off the async workers
+        let files = self.clone();
+        tokio::task::spawn_blocking(move || files.load_blocking())
+            .await
+            .map_err(Error::other)
+    }
+
+    async fn save_block(&self, block: &BlockDefinition) -> std::io::Result<()> {
+        let file_path = self.block_path(block);
+        if let Some(category_dir) = file_path.parent() {
+            fs::create_dir_all(category_dir).await?;
+        }
+
+        let yaml = serde_yaml::to_string(&block)
+            .map_err(|e| Error::other(format!("Failed to serialize block to YAML: {}", e)))?;
+
+        write_atomic(file_path, yaml).await
+    }
+
+    async fn delete_block(&self, block: &BlockDefinition) -> std::io::Result<()> {
+        let file_path = self.block_path(block);
+        if file_path.exists() {
+            fs::remove_file(file_path).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores the template under `builtin/imported/`.
+    async fn save_template(&self, block_type: &str, template: &str) -> std::io::Result<()> {
+        let imported_dir = self.imported_dir();
+        fs::create_dir_all(&imported_dir).await?;
+        write_atomic(imported_dir.join(format!("{}.rhai", block_type)), template).await
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.diagnostics.list()
+    }
+}
+
+/// One `<id>.yaml` per blueprint.
+#[derive(Debug, Clone)]
+pub struct YamlBlueprintRepository {
+    blueprints_dir: PathBuf,
+    diagnostics: Diagnostics,
+}
+
+impl YamlBlueprintRepository {
+    pub async fn open(blueprints_dir: PathBuf) -> std::io::Result<Self> {
+        fs::create_dir_all(&blueprints_dir).await?;
+        remove_stale_temp_files(&blueprints_dir).await?;
+        Ok(Self {
+            blueprints_dir,
+            diagnostics: Diagnostics::default(),
+        })
+    }
+
+    pub fn blueprints_dir(&self) -> &Path {
+        &self.blueprints_dir
+    }
+
+    pub(super) fn blueprint_path(&self, id: &str) -> PathBuf {
+        self.blueprints_dir.join(format!("{}.yaml", id))
+    }
+
+    fn load_blocking(&self) -> std::io::Result<Vec<Blueprint>> {
+        let mut blueprints = Vec::new();
+        for entry in std::fs::read_dir(&self.blueprints_dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "yaml") {
+                continue;
+            }
+
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    self.diagnostics.record(LoadDiagnostic::new(
+                        DiagnosticSource::Blueprint,
+                        &path,
+                        e,
+                    ));
+                    continue;
+                }
+            };
+            match serde_yaml::from_str::<Blueprint>(&content) {
+                Ok(blueprint) => {
+                    info!("Loaded blueprint: {}", blueprint.name);
+                    blueprints.push(blueprint);
+                }
+                Err(e) => {
+                    self.diagnostics.record(quarantine(
+                        &self.blueprints_dir,
+                        LoadDiagnostic::from_yaml_error(DiagnosticSource::Blueprint, &path, &e),
+                    ));
+                }
+            }
+        }
+        Ok(blueprints)
+    }
+}
+
+#[async_trait]
+impl BlueprintRepository for YamlBlueprintRepository {
+    async fn load(&self) -> std::io::Result<Vec<Blueprint>> {
+        // Quarantining renames files, so the whole load runs off the async workers
+        let files = self.clone();
+        tokio::task::spawn_blocking(move || files.load_blocking())
+            .await
+            .map_err(Error::other)?
+    }
+
+    async fn save(&self, blueprint: &Blueprint) -> std::io::Result<()> {
+        let yaml = serde_yaml::to_string(blueprint)
+            .map_err(|e| Error::other(format!("Failed to serialize blueprint to YAML: {}", e)))?;
+        write_atomic(self.blueprint_path(&blueprint.id), yaml).await
+    }
+
+    async fn delete(&self, blueprint: &Blueprint) -> std::io::Result<()> {
+        match fs::remove_file(self.blueprint_path(&blueprint.id)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn diagnostics(&self) -> Vec<LoadDiagnostic> {
+        self.diagnostics.list()
+    }
+}
@@ -1,8 +1,16 @@
 #[cfg(test)]
-use crate::automation::{AutomationCreate, AutomationStore, AutomationUpdate};
+use crate::automation::{
+    AutomationCreate, AutomationFilter, AutomationStore, AutomationUpdate, CompileStatus,
+};
 #[cfg(test)]
 use crate::blocks::{BlockArgument, BlockDefinition, BlockStore};
 #[cfg(test)]
+use crate::error::{ApiError, AutomationError, CompileStage};
+#[cfg(test)]
+use crate::ha_backend::HaBackend;
+#[cfg(test)]
+use crate::ha_simulator::{HaSimulator, SimulatorConfig};
+#[cfg(test)]
 use serde_json::json;
 #[cfg(test)]
 use std::io::Result;
@@ -34,6 +42,7 @@ mod tests {
                         check: Some("Boolean".to_string()),
                         options: None,
                         default: None,
+                        ..Default::default()
                     },
                     BlockArgument {
                         r#type: "field_dropdown".to_string(),
@@ -44,6 +53,7 @@ mod tests {
                             vec!["or".to_string(), "OR".to_string()],
                         ]),
                         default: None,
+                        ..Default::default()
                     },
                     BlockArgument {
                         r#type: "input_value".to_string(),
@@ -51,6 +61,7 @@ mod tests {
                         check: Some("Boolean".to_string()),
                         options: None,
                         default: None,
+                        ..Default::default()
                     },
                 ]),
                 output: Some("Boolean".to_string()),
@@ -101,6 +112,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(automation).await;
@@ -131,6 +144,8 @@ mod tests {
                     }
                 }
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(automation).await;
@@ -156,6 +171,8 @@ mod tests {
             workspace: json!({
                 "blocks": []
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(automation).await;
@@ -204,6 +221,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(automation).await;
@@ -240,6 +259,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let initial = store.create(create_data).await?;
@@ -264,6 +285,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: None,
+            folder: None,
         };
 
         let updated = store.update(&initial.id, update_data).await?.unwrap();
@@ -292,6 +315,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(automation).await;
@@ -323,6 +348,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let initial = store.create(create_data).await?;
@@ -337,11 +364,191 @@ mod tests {
             triggers: vec![],
             conditions: vec![],
             workspace: initial.workspace.clone(),
+            tags: None,
+            folder: None,
         };
 
         let result = store.update(&initial.id, wrong_version_update).await;
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Version mismatch"));
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("Version mismatch"));
+        assert!(matches!(
+            error,
+            AutomationError::VersionConflict {
+                expected: 2,
+                current: 1
+            }
+        ));
+
+        // The API reports the conflict with the version to retry against
+        let api_error = ApiError::from(error);
+        assert_eq!(api_error.status, axum::http::StatusCode::CONFLICT);
+        assert_eq!(api_error.body.error, "version_conflict");
+        assert_eq!(api_error.body.current_version, Some(1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_version_history_and_rollback() -> Result<()> {
+        let (store, temp_dir) = setup_test_environment().await?;
+        let store = store.with_history_limit(Some(2));
+
+        let workspace = |op: &str| {
+            json!({
+                "blocks": [
+                    {
+                        "type": "logic_operation",
+                        "id": "block1",
+                        "fields": {
+                            "OP": {"value": op}
+                        }
+                    }
+                ]
+            })
+        };
+
+        let initial = store
+            .create(AutomationCreate {
+                name: "History Test".to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: workspace("AND"),
+                tags: vec![],
+                folder: None,
+            })
+            .await?;
+
+        let mut current = initial.clone();
+        for (name, op) in [("Second", "OR"), ("Third", "OR")] {
+            current = store
+                .update(
+                    &initial.id,
+                    AutomationUpdate {
+                        name: name.to_string(),
+                        description: None,
+                        enabled: true,
+                        version: current.version,
+                        triggers: vec![],
+                        conditions: vec![],
+                        workspace: workspace(op),
+                        tags: None,
+                        folder: None,
+                    },
+                )
+                .await?
+                .unwrap();
+        }
+        assert_eq!(current.version, 3);
+
+        // Only the newest two versions are kept
+        let versions = store.versions(&initial.id).await?.unwrap();
+        let numbers: Vec<i32> = versions.iter().map(|v| v.version).collect();
+        assert_eq!(numbers, vec![3, 2]);
+        assert!(store.get_version(&initial.id, 1).await?.is_none());
+
+        let diff = store.diff(&initial.id, 2, 3).await?.unwrap();
+        assert!(diff.properties.contains_key("name"));
+        assert!(diff.changed.is_empty());
+
+        // Rolling back saves the old content as a new version
+        let rolled_back = store.rollback(&initial.id, 2).await?.unwrap();
+        assert_eq!(rolled_back.version, 4);
+        assert_eq!(rolled_back.name, "Second");
+        assert!(store.rollback(&initial.id, 1).await?.is_none());
+
+        let history_dir = temp_dir.path().join("history").join(&initial.id);
+        assert!(history_dir.exists());
+        store.delete(&initial.id).await?;
+        assert!(!history_dir.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_script_is_recompiled_on_load() -> Result<()> {
+        let (store, temp_dir) = setup_test_environment().await?;
+
+        let automation = store
+            .create(AutomationCreate {
+                name: "Manifest Test".to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: json!({
+                    "blocks": [
+                        {
+                            "type": "logic_operation",
+                            "id": "block1",
+                            "fields": {
+                                "OP": {"value": "AND"}
+                            }
+                        }
+                    ]
+                }),
+                tags: vec![],
+                folder: None,
+            })
+            .await?;
+
+        let rhai_path = temp_dir.path().join(format!("{}.rhai", automation.id));
+        let script = tokio::fs::read_to_string(&rhai_path).await?;
+        assert!(temp_dir.path().join("manifest.yaml").exists());
+
+        // Simulate a crash that left a truncated script and an unfinished write
+        tokio::fs::write(&rhai_path, "AN").await?;
+        tokio::fs::write(temp_dir.path().join(".other.yaml.tmp"), "partial").await?;
+
+        let block_store = BlockStore::with_blocks_dir(temp_dir.path().join("blocks")).await?;
+        let reopened =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().to_path_buf()).await?;
+
+        assert_eq!(tokio::fs::read_to_string(&rhai_path).await?, script);
+        assert!(!temp_dir.path().join(".other.yaml.tmp").exists());
+        let loaded = reopened.get(&automation.id).await.unwrap();
+        assert!(loaded.compilation_error.is_none());
+        assert_eq!(reopened.list().await.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_broken_files_are_quarantined() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let blocks_dir = temp_dir.path().join("blocks");
+        let storage_dir = temp_dir.path().join("automations");
+        tokio::fs::create_dir_all(blocks_dir.join("custom")).await?;
+        tokio::fs::create_dir_all(&storage_dir).await?;
+
+        tokio::fs::write(
+            blocks_dir.join("custom").join("broken.yaml"),
+            "type: broken\nmessage0: [unclosed\n",
+        )
+        .await?;
+        tokio::fs::write(storage_dir.join("lost.yaml"), "id: lost\nname: {\n").await?;
+
+        let block_store = BlockStore::with_blocks_dir(blocks_dir.clone()).await?;
+        let block_diagnostics = block_store.diagnostics();
+        assert_eq!(block_diagnostics.len(), 1);
+        assert!(block_diagnostics[0].line.is_some());
+        assert!(block_diagnostics[0].quarantined.is_some());
+        assert!(!blocks_dir.join("custom").join("broken.yaml").exists());
+
+        let store = AutomationStore::with_storage_path(block_store, storage_dir.clone()).await?;
+        let diagnostics = store.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].path.ends_with("lost.yaml"));
+        assert!(diagnostics[0].line.is_some());
+        assert!(!storage_dir.join("lost.yaml").exists());
+
+        // The original content is kept for recovery
+        let mut quarantined = tokio::fs::read_dir(storage_dir.join("quarantine")).await?;
+        let entry = quarantined.next_entry().await?.unwrap();
+        assert_eq!(
+            tokio::fs::read_to_string(entry.path()).await?,
+            "id: lost\nname: {\n"
+        );
 
         Ok(())
     }
@@ -367,6 +574,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let automation = store.create(create_data).await?;
@@ -412,6 +621,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let automation = store.create(create_data).await?;
@@ -449,6 +660,7 @@ mod tests {
                         check: Some("Boolean".to_string()),
                         options: None,
                         default: None,
+                        ..Default::default()
                     },
                     BlockArgument {
                         r#type: "field_dropdown".to_string(),
@@ -459,6 +671,7 @@ mod tests {
                             vec!["or".to_string(), "OR".to_string()],
                         ]),
                         default: None,
+                        ..Default::default()
                     },
                     BlockArgument {
                         r#type: "input_value".to_string(),
@@ -466,6 +679,7 @@ mod tests {
                         check: Some("Boolean".to_string()),
                         options: None,
                         default: None,
+                        ..Default::default()
                     },
                 ]),
                 output: Some("Boolean".to_string()),
@@ -488,6 +702,7 @@ mod tests {
                         check: Some("Boolean".to_string()),
                         options: None,
                         default: None,
+                        ..Default::default()
                     },
                     BlockArgument {
                         r#type: "input_statement".to_string(),
@@ -495,6 +710,7 @@ mod tests {
                         check: None,
                         options: None,
                         default: None,
+                        ..Default::default()
                     },
                 ]),
                 output: None,
@@ -546,6 +762,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(basic_if).await;
@@ -613,6 +831,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(if_with_else).await;
@@ -678,6 +898,8 @@ mod tests {
                     }
                 ]
             }),
+            tags: vec![],
+            folder: None,
         };
 
         let result = store.create(automation).await;
@@ -687,4 +909,350 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_compile_error_reports_position() -> Result<()> {
+        let (_store, temp_dir) = setup_test_environment().await?;
+
+        let blocks_dir = temp_dir.path().join("blocks_unparsable");
+        tokio::fs::create_dir_all(&blocks_dir).await?;
+        let block_store = BlockStore::with_blocks_dir(blocks_dir).await?;
+        block_store
+            .create_or_update(BlockDefinition {
+                r#type: "unparsable".to_string(),
+                message0: "Unparsable".to_string(),
+                args0: Some(vec![]),
+                output: None,
+                colour: 0,
+                tooltip: String::new(),
+                rhai_template: Some("let x = 1;\nlet = ;".to_string()),
+                ..Default::default()
+            })
+            .await?;
+
+        let store =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().to_path_buf()).await?;
+
+        let result = store
+            .create(AutomationCreate {
+                name: "Unparsable".to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: json!({
+                    "blocks": [
+                        {
+                            "type": "unparsable",
+                            "id": "block1",
+                            "fields": {}
+                        }
+                    ]
+                }),
+                tags: vec![],
+                folder: None,
+            })
+            .await;
+
+        let diagnostic = match result {
+            Err(AutomationError::Compile(diagnostic)) => diagnostic,
+            other => panic!("Expected a compile error, got {:?}", other.map(|a| a.id)),
+        };
+        assert_eq!(diagnostic.stage, CompileStage::Compile);
+        assert!(diagnostic.line.is_some());
+
+        let api_error = ApiError::from(AutomationError::Compile(diagnostic));
+        assert_eq!(
+            api_error.status,
+            axum::http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(api_error.body.error, "compile_error");
+        assert_eq!(api_error.body.diagnostics.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_tag_folder_and_entity() -> Result<()> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let blocks_dir = temp_dir.path().join("blocks");
+        tokio::fs::create_dir_all(&blocks_dir).await?;
+        let block_store = BlockStore::with_blocks_dir(blocks_dir).await?;
+        block_store
+            .create_or_update(BlockDefinition {
+                r#type: "turn_on".to_string(),
+                message0: "turn on %1".to_string(),
+                args0: Some(vec![BlockArgument {
+                    r#type: "field_entity".to_string(),
+                    name: "ENTITY_ID".to_string(),
+                    ..Default::default()
+                }]),
+                colour: 0,
+                tooltip: String::new(),
+                rhai_template: Some("\"{{ ENTITY_ID }}\";".to_string()),
+                ..Default::default()
+            })
+            .await?;
+        let store =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().to_path_buf()).await?;
+
+        let create =
+            |name: &str, entity: &str, tags: &[&str], folder: Option<&str>| AutomationCreate {
+                name: name.to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: json!({
+                    "blocks": [{
+                        "type": "turn_on",
+                        "id": "block1",
+                        "fields": {"ENTITY_ID": entity}
+                    }]
+                }),
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+                folder: folder.map(str::to_string),
+            };
+
+        let kitchen = store
+            .create(create(
+                "Kitchen",
+                "light.kitchen",
+                &[" lights", "lights", "evening "],
+                Some("/downstairs//kitchen/"),
+            ))
+            .await?;
+        assert_eq!(kitchen.tags, vec!["evening", "lights"]);
+        assert_eq!(kitchen.folder.as_deref(), Some("downstairs/kitchen"));
+
+        let hall = store
+            .create(create(
+                "Hall",
+                "light.hall",
+                &["lights"],
+                Some("downstairs"),
+            ))
+            .await?;
+        store.toggle(&hall.id, false).await?;
+        store
+            .create(create("Garden", "switch.pump", &["garden"], None))
+            .await?;
+
+        let names = |automations: Vec<crate::automation::Automation>| {
+            automations.into_iter().map(|a| a.name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(store.search(&AutomationFilter::default(), None).await),
+            vec!["Garden", "Hall", "Kitchen"]
+        );
+        assert_eq!(
+            names(
+                store
+                    .search(
+                        &AutomationFilter {
+                            tag: Some("Lights".to_string()),
+                            ..Default::default()
+                        },
+                        None
+                    )
+                    .await
+            ),
+            vec!["Hall", "Kitchen"]
+        );
+        assert_eq!(
+            names(
+                store
+                    .search(
+                        &AutomationFilter {
+                            folder: Some("downstairs".to_string()),
+                            enabled: Some(true),
+                            ..Default::default()
+                        },
+                        None
+                    )
+                    .await
+            ),
+            vec!["Kitchen"]
+        );
+        assert_eq!(
+            names(
+                store
+                    .search(
+                        &AutomationFilter {
+                            entity: Some("switch.pump".to_string()),
+                            status: Some(CompileStatus::Ok),
+                            ..Default::default()
+                        },
+                        None
+                    )
+                    .await
+            ),
+            vec!["Garden"]
+        );
+        assert!(store
+            .search(
+                &AutomationFilter {
+                    status: Some(CompileStatus::Error),
+                    ..Default::default()
+                },
+                None
+            )
+            .await
+            .is_empty());
+        assert_eq!(store.tags().await, vec!["evening", "garden", "lights"]);
+        assert_eq!(
+            store.folders().await,
+            vec!["downstairs", "downstairs/kitchen"]
+        );
+
+        // An update without tags or folder keeps them
+        let update =
+            |body: serde_json::Value| serde_json::from_value::<AutomationUpdate>(body).unwrap();
+        let workspace = kitchen.workspace.clone();
+        let renamed = store
+            .update(
+                &kitchen.id,
+                update(json!({
+                    "name": "Kitchen lights",
+                    "enabled": true,
+                    "version": kitchen.version,
+                    "triggers": [],
+                    "conditions": [],
+                    "workspace": workspace
+                })),
+            )
+            .await?
+            .unwrap();
+        assert_eq!(renamed.tags, vec!["evening", "lights"]);
+        assert_eq!(renamed.folder.as_deref(), Some("downstairs/kitchen"));
+
+        // Explicitly empty tags and a null folder clear them
+        let cleared = store
+            .update(
+                &kitchen.id,
+                update(json!({
+                    "name": "Kitchen lights",
+                    "enabled": true,
+                    "version": renamed.version,
+                    "triggers": [],
+                    "conditions": [],
+                    "workspace": workspace,
+                    "tags": [],
+                    "folder": null
+                })),
+            )
+            .await?
+            .unwrap();
+        assert!(cleared.tags.is_empty());
+        assert_eq!(cleared.folder, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_entity_in_service_data_and_areas() -> Result<()> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let blocks_dir = temp_dir.path().join("blocks");
+        tokio::fs::create_dir_all(&blocks_dir).await?;
+        let block_store = BlockStore::with_blocks_dir(blocks_dir).await?;
+        for yaml in [
+            include_str!("../../blocks/actions/ha_call_service.yaml"),
+            include_str!("../../blocks/actions/ha_area_action.yaml"),
+        ] {
+            block_store
+                .create_or_update(serde_yaml::from_str(yaml).unwrap())
+                .await?;
+        }
+        block_store
+            .create_or_update(BlockDefinition {
+                r#type: "check_sensor".to_string(),
+                message0: "check %1".to_string(),
+                args0: Some(vec![BlockArgument {
+                    r#type: "field_entity".to_string(),
+                    name: "SENSOR".to_string(),
+                    ..Default::default()
+                }]),
+                colour: 0,
+                tooltip: String::new(),
+                rhai_template: Some("\"{{ SENSOR }}\";".to_string()),
+                ..Default::default()
+            })
+            .await?;
+        let store =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().to_path_buf()).await?;
+
+        let create = |name: &str, block: serde_json::Value| AutomationCreate {
+            name: name.to_string(),
+            description: None,
+            triggers: vec![],
+            conditions: vec![],
+            workspace: json!({ "blocks": [block] }),
+            tags: vec![],
+            folder: None,
+        };
+        store
+            .create(create(
+                "Sensor",
+                json!({
+                    "type": "check_sensor",
+                    "id": "block1",
+                    "fields": {"SENSOR": "sensor.outdoor_temperature"}
+                }),
+            ))
+            .await?;
+        store
+            .create(create(
+                "Service data",
+                json!({
+                    "type": "ha_call_service",
+                    "id": "block1",
+                    "fields": {
+                        "SERVICE": "light.turn_on",
+                        "DATA": r#"{"entity_id": ["light.hall", "light.porch"], "brightness": 128}"#
+                    }
+                }),
+            ))
+            .await?;
+        store
+            .create(create(
+                "Area",
+                json!({
+                    "type": "ha_area_action",
+                    "id": "block1",
+                    "fields": {"SERVICE": "switch.turn_off", "DOMAIN": "switch", "AREA": "Kitchen"}
+                }),
+            ))
+            .await?;
+
+        let simulator = HaSimulator::new(SimulatorConfig::demo()).unwrap();
+        let registries = simulator.get_registries().await;
+        let search = |entity: &str, registries| {
+            let filter = AutomationFilter {
+                entity: Some(entity.to_string()),
+                ..Default::default()
+            };
+            let store = &store;
+            async move {
+                store
+                    .search(&filter, registries)
+                    .await
+                    .into_iter()
+                    .map(|a| a.name)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            search("sensor.outdoor_temperature", None).await,
+            vec!["Sensor"]
+        );
+        assert_eq!(search("light.porch", None).await, vec!["Service data"]);
+        assert_eq!(
+            search("switch.coffee_maker", Some(&registries)).await,
+            vec!["Area"]
+        );
+        // Without registries the entities of an area are unknown
+        assert!(search("switch.coffee_maker", None).await.is_empty());
+
+        Ok(())
+    }
 }
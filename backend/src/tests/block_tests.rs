@@ -0,0 +1,260 @@
+#[cfg(test)]
+use crate::automation::{AutomationCreate, AutomationStore};
+#[cfg(test)]
+use crate::blocks::{BlockArgument, BlockDefinition, BlockStore, BlockTestCase, MessageRow};
+#[cfg(test)]
+use crate::codegen::CodeGenerator;
+#[cfg(test)]
+use serde_json::json;
+#[cfg(test)]
+use std::io::Result;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTIFY_BLOCK: &str = r#"
+type: notify_with_actions
+message0: "Notify %1"
+args0:
+  - type: field_input
+    name: TARGET
+message1: "title %1 message %2"
+args1:
+  - type: field_input
+    name: TITLE
+  - type: field_input
+    name: MESSAGE
+message2: "action %1"
+args2:
+  - type: field_input
+    name: ACTION
+previous_statement: true
+next_statement: true
+rhai_template: 'notify("{{TARGET}}", "{{TITLE}}", "{{MESSAGE}}", "{{ACTION}}");'
+"#;
+
+    fn field(name: &str) -> BlockArgument {
+        BlockArgument {
+            r#type: "field_input".to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_message_rows_roundtrip() {
+        let block: BlockDefinition = serde_yaml::from_str(NOTIFY_BLOCK).unwrap();
+        assert_eq!(block.extra_rows.len(), 2);
+        assert_eq!(block.extra_rows[0].message, "title %1 message %2");
+        assert_eq!(block.extra_rows[1].args.as_ref().unwrap()[0].name, "ACTION");
+
+        let names: Vec<_> = block.arguments().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["TARGET", "TITLE", "MESSAGE", "ACTION"]);
+        assert!(block.validate().is_ok());
+
+        // Rows serialize back to Blockly's messageN/argsN keys
+        let value = serde_json::to_value(&block).unwrap();
+        assert_eq!(value["message2"], "action %1");
+        assert_eq!(value["args1"][1]["name"], "MESSAGE");
+    }
+
+    #[test]
+    fn test_message_rows_must_be_contiguous() {
+        let result = serde_yaml::from_str::<BlockDefinition>(
+            "type: gap\nmessage0: ''\nmessage2: 'late %1'\nargs2:\n  - type: field_input\n    name: X\n",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_validate_placeholders() {
+        let block = BlockDefinition {
+            r#type: "mismatch".to_string(),
+            message0: "%1".to_string(),
+            args0: Some(vec![field("A")]),
+            extra_rows: vec![MessageRow {
+                message: "%1 %2".to_string(),
+                args: Some(vec![field("B")]),
+            }],
+            ..Default::default()
+        };
+        let error = block.validate().unwrap_err();
+        assert!(error.contains("message1"), "Unexpected error: {}", error);
+    }
+
+    #[tokio::test]
+    async fn test_codegen_with_multiple_rows() -> Result<()> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let blocks_dir = temp_dir.path().join("blocks");
+        tokio::fs::create_dir_all(&blocks_dir).await?;
+
+        let block_store = BlockStore::with_blocks_dir(blocks_dir).await?;
+        block_store
+            .create_or_update(serde_yaml::from_str(NOTIFY_BLOCK).unwrap())
+            .await?;
+
+        let store =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().to_path_buf()).await?;
+
+        // Only some fields are set; fields from later rows must still render
+        let automation = store
+            .create(AutomationCreate {
+                name: "Notify".to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: json!({
+                    "blocks": [
+                        {
+                            "type": "notify_with_actions",
+                            "id": "notify",
+                            "fields": {
+                                "TARGET": {"value": "phone"},
+                                "MESSAGE": {"value": "hello"}
+                            }
+                        }
+                    ]
+                }),
+                tags: vec![],
+                folder: None,
+            })
+            .await;
+        assert!(
+            automation.is_ok(),
+            "Failed to create multi-row automation: {:?}",
+            automation.err()
+        );
+
+        let rhai_path = temp_dir
+            .path()
+            .join(format!("{}.rhai", automation.unwrap().id));
+        let script = tokio::fs::read_to_string(rhai_path).await?;
+        assert_eq!(script, r#"notify("phone", "", "hello", "");"#);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embedded_block_examples() -> Result<()> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let block_store = BlockStore::with_blocks_dir(temp_dir.path().to_path_buf()).await?;
+        block_store
+            .create_or_update(BlockDefinition {
+                r#type: "math_double".to_string(),
+                message0: "double %1".to_string(),
+                args0: Some(vec![BlockArgument {
+                    r#type: "field_number".to_string(),
+                    name: "NUM".to_string(),
+                    ..Default::default()
+                }]),
+                output: Some("Number".to_string()),
+                rhai_template: Some("{{NUM}} * 2".to_string()),
+                tests: Some(vec![
+                    BlockTestCase {
+                        name: Some("doubles".to_string()),
+                        fields: [("NUM".to_string(), json!(21))].into_iter().collect(),
+                        expected: Some("21 * 2".to_string()),
+                        expected_result: Some(json!(42)),
+                        ..Default::default()
+                    },
+                    BlockTestCase {
+                        fields: [("NUM".to_string(), json!(1))].into_iter().collect(),
+                        expected_result: Some(json!(3)),
+                        ..Default::default()
+                    },
+                ]),
+                ..Default::default()
+            })
+            .await?;
+
+        let generator = CodeGenerator::new(block_store);
+        let outcomes = generator.run_block_tests(Some("math_double")).await;
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes[0].passed, "Unexpected failure: {:?}", outcomes[0]);
+        assert_eq!(outcomes[0].result, Some(json!(42)));
+        assert!(!outcomes[1].passed);
+        assert_eq!(outcomes[1].name, "#1");
+
+        assert!(generator.run_block_tests(Some("missing")).await.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rhai_string_literal() -> Result<()> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let block_store = BlockStore::with_blocks_dir(temp_dir.path().to_path_buf()).await?;
+        let generator = CodeGenerator::new(block_store);
+        let text = "say \"hi\" to `${name}` at C:\\Users\n\u{8}\u{c}";
+        let code = generator
+            .render_template(
+                "{{rhai_string TEXT}}",
+                &[("TEXT".to_string(), json!(text))].into_iter().collect(),
+            )
+            .unwrap();
+        assert_eq!(
+            rhai::Engine::new().eval::<String>(&code).unwrap(),
+            text,
+            "{}",
+            code
+        );
+
+        Ok(())
+    }
+
+    /// Runs the examples shipped in `blocks/`; set `BLOCK_TEST=<type>` to check a single block.
+    #[tokio::test]
+    async fn test_shipped_block_examples() -> Result<()> {
+        let block_store = BlockStore::with_blocks_dir("blocks".into()).await?;
+        let generator = CodeGenerator::new(block_store);
+
+        let block_type = std::env::var("BLOCK_TEST").ok();
+        let outcomes = generator.run_block_tests(block_type.as_deref()).await;
+        assert!(!outcomes.is_empty(), "No block examples found");
+        let failures: Vec<_> = outcomes.iter().filter(|o| !o.passed).collect();
+        assert!(
+            failures.is_empty(),
+            "Block examples failed: {:#?}",
+            failures
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_category_is_a_display_name() -> Result<()> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let block_store = BlockStore::with_blocks_dir(temp_dir.path().to_path_buf()).await?;
+        let block = |r#type: &str, category: &str| BlockDefinition {
+            r#type: r#type.to_string(),
+            message0: "do".to_string(),
+            category: Some(category.to_string()),
+            rhai_template: Some("do();".to_string()),
+            ..Default::default()
+        };
+
+        block_store
+            .create_or_update(block("lamp", "Living Room"))
+            .await?;
+        block_store
+            .create_or_update(block("escape", "../.."))
+            .await?;
+        block_store
+            .create_or_update(block("nested", "Rooms/Hall"))
+            .await?;
+
+        assert!(temp_dir.path().join("living room/lamp.yaml").exists());
+        assert!(temp_dir.path().join("_/escape.yaml").exists());
+        assert!(temp_dir.path().join("rooms_hall/nested.yaml").exists());
+
+        let reloaded = BlockStore::with_blocks_dir(temp_dir.path().to_path_buf()).await?;
+        assert!(reloaded.diagnostics().is_empty());
+        assert_eq!(
+            reloaded.get("lamp").await.unwrap().category.as_deref(),
+            Some("Living Room")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+#[cfg(test)]
+use crate::automation::AutomationStore;
+#[cfg(test)]
+use crate::blocks::{BlockArgument, BlockDefinition, BlockStore};
+#[cfg(test)]
+use crate::blueprint::{
+    self, BlueprintCreate, BlueprintStore, BlueprintUpdate, InstantiateRequest,
+};
+#[cfg(test)]
+use crate::diagnostics::DiagnosticSource;
+#[cfg(test)]
+use crate::error::BlueprintError;
+#[cfg(test)]
+use serde_json::{json, Value};
+#[cfg(test)]
+use std::collections::BTreeMap;
+#[cfg(test)]
+use std::io::Result;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    async fn setup() -> Result<(BlueprintStore, AutomationStore, TempDir)> {
+        let temp_dir = tempfile::tempdir()?;
+        let block_store = BlockStore::with_blocks_dir(temp_dir.path().join("blocks")).await?;
+        block_store
+            .create_or_update(BlockDefinition {
+                r#type: "dim_light".to_string(),
+                message0: "dim %1 to %2".to_string(),
+                args0: Some(vec![
+                    BlockArgument {
+                        r#type: "field_entity".to_string(),
+                        name: "ENTITY_ID".to_string(),
+                        ..Default::default()
+                    },
+                    BlockArgument {
+                        r#type: "field_number".to_string(),
+                        name: "BRIGHTNESS".to_string(),
+                        ..Default::default()
+                    },
+                ]),
+                colour: 0,
+                tooltip: String::new(),
+                rhai_template: Some(
+                    "let light = \"{{ ENTITY_ID }}\";\nlet brightness = {{ BRIGHTNESS }};"
+                        .to_string(),
+                ),
+                ..Default::default()
+            })
+            .await?;
+
+        let automations =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().join("automations"))
+                .await?;
+        let blueprints =
+            BlueprintStore::with_blueprints_dir(temp_dir.path().join("blueprints")).await?;
+        Ok((blueprints, automations, temp_dir))
+    }
+
+    fn dim_workspace(brightness: &str) -> Value {
+        json!({
+            "blocks": [{
+                "type": "dim_light",
+                "id": "dim",
+                "fields": {
+                    "ENTITY_ID": "!input light",
+                    "BRIGHTNESS": brightness
+                }
+            }]
+        })
+    }
+
+    fn light_input() -> Value {
+        json!({"entity": {"domain": "light"}})
+    }
+
+    fn inputs(values: &[(&str, Value)]) -> BTreeMap<String, Value> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_instances_follow_blueprint_changes() -> Result<()> {
+        let (blueprints, automations, _temp_dir) = setup().await?;
+
+        let created = blueprints
+            .create(BlueprintCreate {
+                name: "Dim light".to_string(),
+                description: None,
+                inputs: serde_json::from_value(json!([
+                    {"name": "light", "selector": light_input()},
+                    {"name": "brightness", "selector": {"number": {"min": 0, "max": 255}}, "default": 128}
+                ]))
+                .unwrap(),
+                workspace: dim_workspace("!input brightness"),
+            })
+            .await
+            .unwrap();
+
+        let kitchen = blueprint::instantiate(
+            &blueprints,
+            &automations,
+            &created.id,
+            InstantiateRequest {
+                name: "Kitchen".to_string(),
+                description: None,
+                inputs: inputs(&[("light", json!("light.kitchen"))]),
+                tags: vec![],
+                folder: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            kitchen.workspace["blocks"][0]["fields"],
+            json!({"ENTITY_ID": "light.kitchen", "BRIGHTNESS": 128})
+        );
+        let instance = kitchen.blueprint.as_ref().unwrap();
+        assert_eq!(instance.blueprint_id, created.id);
+        assert_eq!(instance.blueprint_version, 1);
+
+        // Values are checked against the selectors
+        let wrong_domain = blueprint::instantiate(
+            &blueprints,
+            &automations,
+            &created.id,
+            InstantiateRequest {
+                name: "Fan".to_string(),
+                description: None,
+                inputs: inputs(&[("light", json!("fan.ceiling"))]),
+                tags: vec![],
+                folder: None,
+            },
+        )
+        .await;
+        assert!(matches!(
+            wrong_domain,
+            Err(BlueprintError::InvalidInput { ref input, .. }) if input == "light"
+        ));
+
+        // Editing the blueprint re-renders and recompiles every instance
+        let change = blueprint::update(
+            &blueprints,
+            &automations,
+            &created.id,
+            BlueprintUpdate {
+                name: "Dim light".to_string(),
+                description: None,
+                inputs: serde_json::from_value(
+                    json!([{"name": "light", "selector": light_input()}]),
+                )
+                .unwrap(),
+                workspace: dim_workspace("10"),
+                version: created.version,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(change.instances.updated, vec![kitchen.id.clone()]);
+        assert!(change.instances.failed.is_empty());
+
+        let kitchen = automations.get(&kitchen.id).await.unwrap();
+        assert_eq!(kitchen.version, 2);
+        assert_eq!(kitchen.workspace["blocks"][0]["fields"]["BRIGHTNESS"], "10");
+        assert_eq!(kitchen.blueprint.as_ref().unwrap().blueprint_version, 2);
+
+        // Blueprints in use cannot be deleted
+        let deleted = blueprint::delete(&blueprints, &automations, &created.id).await;
+        assert!(matches!(deleted, Err(BlueprintError::InUse(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_instance_inputs() -> Result<()> {
+        let (blueprints, automations, _temp_dir) = setup().await?;
+
+        let created = blueprints
+            .create(BlueprintCreate {
+                name: "Dim light".to_string(),
+                description: None,
+                inputs: serde_json::from_value(json!([
+                    {"name": "light", "selector": light_input()},
+                    {"name": "brightness", "selector": {"number": {"min": 0, "max": 255}}}
+                ]))
+                .unwrap(),
+                workspace: dim_workspace("!input brightness"),
+            })
+            .await
+            .unwrap();
+        let hall = blueprint::instantiate(
+            &blueprints,
+            &automations,
+            &created.id,
+            InstantiateRequest {
+                name: "Hall".to_string(),
+                description: None,
+                inputs: inputs(&[("light", json!("light.hall")), ("brightness", json!(50))]),
+                tags: vec![],
+                folder: None,
+            },
+        )
+        .await
+        .unwrap();
+        blueprint::set_inputs(
+            &blueprints,
+            &automations,
+            &hall.id,
+            inputs(&[("light", json!("light.hall")), ("brightness", json!(200))]),
+        )
+        .await
+        .unwrap();
+
+        let rolled_back = automations.rollback(&hall.id, hall.version).await?.unwrap();
+        assert_eq!(rolled_back.blueprint, hall.blueprint);
+
+        // The next propagation renders from the restored inputs
+        blueprint::update(
+            &blueprints,
+            &automations,
+            &created.id,
+            BlueprintUpdate {
+                name: "Dim light".to_string(),
+                description: Some("Renamed".to_string()),
+                inputs: created.inputs.clone(),
+                workspace: created.workspace.clone(),
+                version: created.version,
+            },
+        )
+        .await
+        .unwrap();
+        let hall = automations.get(&hall.id).await.unwrap();
+        assert_eq!(
+            hall.workspace["blocks"][0]["fields"],
+            json!({"ENTITY_ID": "light.hall", "BRIGHTNESS": 50})
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blueprint_requires_declared_inputs() -> Result<()> {
+        let (blueprints, automations, temp_dir) = setup().await?;
+
+        let undeclared = blueprints
+            .create(BlueprintCreate {
+                name: "Broken".to_string(),
+                description: None,
+                inputs: vec![],
+                workspace: dim_workspace("!input brightness"),
+            })
+            .await;
+        assert!(matches!(undeclared, Err(BlueprintError::Invalid(_))));
+
+        let created = blueprints
+            .create(BlueprintCreate {
+                name: "Dim light".to_string(),
+                description: None,
+                inputs: serde_json::from_value(
+                    json!([{"name": "light", "selector": light_input()}]),
+                )
+                .unwrap(),
+                workspace: dim_workspace("50"),
+            })
+            .await
+            .unwrap();
+        let missing = blueprint::instantiate(
+            &blueprints,
+            &automations,
+            &created.id,
+            InstantiateRequest {
+                name: "Hall".to_string(),
+                description: None,
+                inputs: BTreeMap::new(),
+                tags: vec![],
+                folder: None,
+            },
+        )
+        .await;
+        assert!(matches!(missing, Err(BlueprintError::InvalidInput { .. })));
+        assert!(automations.list().await.is_empty());
+
+        // Reloading keeps the blueprint and reports the one that no longer parses
+        let blueprints_dir = temp_dir.path().join("blueprints");
+        tokio::fs::write(blueprints_dir.join("broken.yaml"), "name: [unclosed").await?;
+        let reloaded = BlueprintStore::with_blueprints_dir(blueprints_dir.clone()).await?;
+        assert_eq!(reloaded.get(&created.id).await.unwrap().name, "Dim light");
+        let diagnostics = reloaded.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].source, DiagnosticSource::Blueprint);
+        assert!(diagnostics[0].path.ends_with("broken.yaml"));
+        assert!(diagnostics[0].line.is_some());
+        assert!(diagnostics[0].quarantined.is_some());
+        assert!(!blueprints_dir.join("broken.yaml").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+#[cfg(test)]
+use crate::error::{ApiError, HaRestError};
+#[cfg(test)]
+use crate::ha_client::HaEndpoint;
+#[cfg(test)]
+use crate::ha_rest::HaRestClient;
+#[cfg(test)]
+use crate::rhai::{register_ha_api, HaApi};
+#[cfg(test)]
+use axum::{
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+#[cfg(test)]
+use serde_json::{json, Value};
+#[cfg(test)]
+use std::collections::HashMap;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{TimeZone, Utc};
+
+    fn authorized(headers: &HeaderMap) -> bool {
+        headers
+            .get("authorization")
+            .is_some_and(|value| value == "Bearer mock_token")
+    }
+
+    async fn history(
+        headers: HeaderMap,
+        Path(start): Path<String>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        if !authorized(&headers) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        let series: Vec<Value> = query["filter_entity_id"]
+            .split(',')
+            .map(|entity_id| {
+                json!([
+                    {
+                        "entity_id": entity_id,
+                        "state": "18.5",
+                        "attributes": {"unit_of_measurement": "°C"},
+                        "last_changed": start,
+                        "last_updated": start
+                    },
+                    {
+                        "entity_id": entity_id,
+                        "state": "19.0",
+                        "attributes": {"unit_of_measurement": "°C"},
+                        "last_changed": query.get("end_time").cloned().unwrap_or_default(),
+                        "last_updated": query.get("end_time").cloned().unwrap_or_default()
+                    }
+                ])
+            })
+            .collect();
+        Json(series).into_response()
+    }
+
+    async fn logbook(
+        headers: HeaderMap,
+        Path(start): Path<String>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        if !authorized(&headers) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        Json(json!([{
+            "when": start,
+            "name": "Front door",
+            "message": "turned on",
+            "entity_id": query.get("entity"),
+            "state": "on",
+            "domain": "binary_sensor",
+            "context_user_id": null
+        }]))
+        .into_response()
+    }
+
+    async fn template(headers: HeaderMap, Json(body): Json<Value>) -> Response {
+        if !authorized(&headers) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        let template = body["template"].as_str().unwrap_or_default();
+        if template.contains("{{ bad") {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"message": "Error rendering template: unexpected end"})),
+            )
+                .into_response();
+        }
+        format!("{} for {}", template, body["variables"]["name"]).into_response()
+    }
+
+    /// Stub of the HA REST API on a local port.
+    async fn start_stub() -> String {
+        let app = Router::new()
+            .route("/api/history/period/{start}", get(history))
+            .route("/api/logbook/{start}", get(logbook))
+            .route("/api/template", post(template));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn client(url: &str, token: &str) -> HaRestClient {
+        HaRestClient::from_endpoint(&HaEndpoint::parse(url).unwrap(), token.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_base_url() {
+        let base = |url: &str| client(url, "token").base_url().to_string();
+        assert_eq!(
+            base("homeassistant.local:8123"),
+            "http://homeassistant.local:8123/"
+        );
+        assert_eq!(
+            base("wss://ha.example.com/api/websocket"),
+            "https://ha.example.com/"
+        );
+        let supervisor = HaRestClient::from_endpoint(&HaEndpoint::supervisor(), "token".into());
+        assert_eq!(
+            supervisor.unwrap().base_url().as_str(),
+            "http://supervisor/core"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_history_and_logbook() {
+        let url = start_stub().await;
+        let rest = client(&url, "mock_token");
+        let start = Utc.with_ymd_and_hms(2024, 1, 26, 9, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 26, 10, 0, 0).unwrap();
+
+        let history = rest
+            .history(
+                &[
+                    "sensor.kitchen_temperature".to_string(),
+                    "sensor.outdoor".to_string(),
+                ],
+                start,
+                Some(end),
+            )
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        let kitchen = &history["sensor.kitchen_temperature"];
+        assert_eq!(kitchen.len(), 2);
+        assert_eq!(kitchen[0].state, "18.5");
+        assert_eq!(kitchen[0].last_changed, "2024-01-26T09:00:00Z");
+        assert_eq!(kitchen[1].last_changed, "2024-01-26T10:00:00Z");
+
+        let state = rest.state_at("sensor.outdoor", start).await.unwrap();
+        assert_eq!(state.unwrap().state, "18.5");
+        assert!(matches!(
+            rest.history(&[], start, None).await,
+            Err(HaRestError::InvalidQuery(_))
+        ));
+
+        let logbook = rest
+            .logbook(start, Some(end), Some("binary_sensor.front_door"))
+            .await
+            .unwrap();
+        assert_eq!(logbook.len(), 1);
+        assert_eq!(logbook[0].when, "2024-01-26T09:00:00Z");
+        assert_eq!(
+            logbook[0].entity_id.as_deref(),
+            Some("binary_sensor.front_door")
+        );
+
+        // The token is sent with every request
+        let error = client(&url, "wrong_token")
+            .history(&["sensor.outdoor".to_string()], start, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, HaRestError::Status { status: 401, .. }));
+        assert_eq!(ApiError::from(error).status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_render_template() {
+        let url = start_stub().await;
+        let rest = client(&url, "mock_token");
+
+        let result = rest
+            .render_template("{{ name }}", Some(json!({"name": "Anna"})))
+            .await
+            .unwrap();
+        assert_eq!(result, "{{ name }} for \"Anna\"");
+
+        let error = rest.render_template("{{ bad", None).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Home Assistant returned 400: Error rendering template: unexpected end"
+        );
+        assert_eq!(ApiError::from(error).status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_script_functions() {
+        let url = start_stub().await;
+        let mut engine = rhai::Engine::new();
+        register_ha_api(
+            &mut engine,
+            HaApi::default().with_rest(std::sync::Arc::new(client(&url, "mock_token"))),
+        );
+
+        // The stub reports 18.5 at the start of the window and 19.0 at its end
+        assert_eq!(
+            engine
+                .eval::<String>(r#"state_ago("sensor.temperature", 3600)"#)
+                .unwrap(),
+            "18.5"
+        );
+        assert_eq!(
+            engine
+                .eval::<String>(
+                    r#"let states = history("sensor.temperature", 3600); `${states.len()} ${states[0].state} ${states[1].state} ${states[1].attributes.unit_of_measurement}`"#
+                )
+                .unwrap(),
+            "2 18.5 19.0 °C"
+        );
+        assert!(engine
+            .eval::<rhai::Dynamic>(r#"state_ago("sensor.temperature", -60)"#)
+            .is_err());
+
+        assert_eq!(
+            engine
+                .eval::<String>(r#"render_template("{{ states('sun.sun') }}")"#)
+                .unwrap(),
+            "{{ states('sun.sun') }} for null"
+        );
+        let error = engine
+            .eval::<String>(r#"render_template("{{ bad")"#)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Home Assistant returned 400: Error rendering template: unexpected end"));
+
+        // A wrong token surfaces as a script error
+        let mut engine = rhai::Engine::new();
+        register_ha_api(
+            &mut engine,
+            HaApi::default().with_rest(std::sync::Arc::new(client(&url, "wrong"))),
+        );
+        assert!(engine
+            .eval::<rhai::Dynamic>(r#"state_ago("sensor.temperature", 60)"#)
+            .is_err());
+    }
+}
@@ -0,0 +1,191 @@
+#[cfg(test)]
+use crate::ha_backend::HaBackend;
+#[cfg(test)]
+use crate::ha_simulator::{HaSimulator, SimulatorConfig};
+#[cfg(test)]
+use crate::ha_subscription::{EntityFilter, StateUpdate};
+#[cfg(test)]
+use crate::rhai::{register_ha_api, HaApi};
+#[cfg(test)]
+use serde_json::json;
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulator(yaml: &str) -> HaSimulator {
+        HaSimulator::new(serde_yaml::from_str::<SimulatorConfig>(yaml).unwrap()).unwrap()
+    }
+
+    fn next_change(update: Option<StateUpdate>) -> (String, String, String) {
+        match update {
+            Some(StateUpdate::Changed(change)) => (
+                change.entity_id,
+                change.old_state.unwrap().state,
+                change.new_state.state,
+            ),
+            other => panic!("expected a state change, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scripted_entities() {
+        let simulator = simulator(
+            r#"
+entities:
+  - entity_id: binary_sensor.door
+    state: "off"
+    repeat: true
+    script:
+      - { after: 60, state: "on" }
+      - { after: 10, state: "off" }
+  - entity_id: sensor.temperature
+    state: "20"
+    script:
+      - { after: 65, state: "21", attributes: { trend: rising } }
+"#,
+        );
+        let mut changes = simulator.subscribe_states(EntityFilter::all());
+        let start = simulator.now();
+
+        simulator.advance(Duration::from_secs(59));
+        assert!(changes.try_recv().is_none());
+
+        // Steps apply in the order they fall due, even within one advance
+        simulator.advance(Duration::from_secs(21));
+        assert_eq!(
+            next_change(changes.try_recv()),
+            ("binary_sensor.door".into(), "off".into(), "on".into())
+        );
+        assert_eq!(
+            next_change(changes.try_recv()),
+            ("sensor.temperature".into(), "20".into(), "21".into())
+        );
+        assert_eq!(
+            next_change(changes.try_recv()),
+            ("binary_sensor.door".into(), "on".into(), "off".into())
+        );
+        assert!(changes.try_recv().is_none());
+        assert_eq!(simulator.now() - start, chrono::Duration::seconds(80));
+
+        let temperature = simulator.get_state("sensor.temperature").await.unwrap();
+        assert_eq!(temperature.attributes["trend"], "rising");
+        let changed_at = (start + chrono::Duration::seconds(65)).to_rfc3339();
+        assert_eq!(temperature.last_changed, changed_at);
+
+        // The door repeats its script, the temperature does not
+        simulator.advance(Duration::from_secs(3600));
+        assert_eq!(
+            simulator
+                .get_state("sensor.temperature")
+                .await
+                .unwrap()
+                .state,
+            "21"
+        );
+        assert!(changes.try_recv().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_services_change_states() {
+        let simulator = simulator(
+            r#"
+entities:
+  - entity_id: light.kitchen
+    state: "off"
+    area: Kitchen
+  - entity_id: switch.fan
+    state: "on"
+"#,
+        );
+        let mut changes = simulator.subscribe_states(EntityFilter::new(["light"]));
+        let mut calls = simulator.subscribe_events("call_service").await.unwrap();
+
+        simulator
+            .call_service(
+                "light",
+                "turn_on",
+                json!({"entity_id": "light.kitchen", "brightness": 128}),
+            )
+            .await
+            .unwrap();
+        let light = simulator.get_state("light.kitchen").await.unwrap();
+        assert_eq!(light.state, "on");
+        assert_eq!(light.attributes["brightness"], 128);
+        assert_eq!(
+            next_change(changes.try_recv()),
+            ("light.kitchen".into(), "off".into(), "on".into())
+        );
+        let call = calls.recv().await.unwrap();
+        assert_eq!(call.data["service"], "turn_on");
+        assert_eq!(light.context.unwrap().id, call.context.unwrap().id);
+
+        // `homeassistant` services act on any domain
+        simulator
+            .call_service(
+                "homeassistant",
+                "toggle",
+                json!({"entity_id": ["light.kitchen", "switch.fan"]}),
+            )
+            .await
+            .unwrap();
+        let states = simulator.get_all_states().await;
+        assert_eq!(states["light.kitchen"].state, "off");
+        assert_eq!(states["switch.fan"].state, "off");
+
+        let error = simulator
+            .call_service("light", "explode", json!({"entity_id": "light.kitchen"}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Service light.explode not found");
+        let error = simulator
+            .call_service("light", "turn_on", json!({"entity_id": "switch.fan"}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "light.turn_on cannot act on switch.fan");
+
+        let actions = simulator.get_all_actions().await;
+        assert!(actions["input_number.set_value"]
+            .fields
+            .contains_key("value"));
+        let registries = simulator.get_registries().await;
+        assert_eq!(registries.entity_area_id("light.kitchen"), Some("kitchen"));
+        assert_eq!(
+            simulator.status().state,
+            crate::ha_health::ConnectionState::Connected
+        );
+    }
+
+    #[test]
+    fn test_demo_config() {
+        let simulator = HaSimulator::new(SimulatorConfig::demo()).unwrap();
+        simulator.advance(Duration::from_secs(24 * 3600));
+
+        let error = HaSimulator::new(
+            serde_yaml::from_str("entities: [{entity_id: kitchen, state: 'on'}]").unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid entity id kitchen");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_template_blocks_report_missing_home_assistant() {
+        let mut engine = rhai::Engine::new();
+        register_ha_api(
+            &mut engine,
+            HaApi::default()
+                .with_backend(Arc::new(HaSimulator::new(SimulatorConfig::demo()).unwrap())),
+        );
+
+        let error = engine
+            .eval::<bool>(r#"template_is_true("{{ is_state('sun.sun', 'below_horizon') }}")"#)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Templates need a real Home Assistant"));
+    }
+}
@@ -0,0 +1,316 @@
+#[cfg(test)]
+use crate::automation::AutomationStore;
+#[cfg(test)]
+use crate::blocks::BlockStore;
+#[cfg(test)]
+use crate::ha_yaml::{
+    import_automations, parse_automations, to_yaml, HaAutomation, HaImportOptions,
+};
+#[cfg(test)]
+use serde_json::json;
+#[cfg(test)]
+use std::io::Result;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+    use tempfile::TempDir;
+
+    const AUTOMATIONS: &str = r#"
+- id: "1700000000001"
+  alias: Evening lights
+  description: Turn on the lights when it gets dark
+  triggers:
+    - trigger: state
+      entity_id: sun.sun
+      from: above_horizon
+      to: below_horizon
+    - trigger: numeric_state
+      entity_id: sensor.outdoor_lux
+      below: 20
+    - trigger: time
+      at: "21:00:00"
+    - trigger: event
+      event_type: zha_event
+      event_data:
+        command: "on"
+    - trigger: template
+      value_template: "{{ states('sensor.outdoor_lux') | int(0) < 10 }}"
+  conditions:
+    - condition: state
+      entity_id: binary_sensor.someone_home
+      state: "on"
+    - condition: or
+      conditions:
+        - condition: time
+          after: "17:00"
+        - condition: numeric_state
+          entity_id: sensor.indoor_lux
+          below: 50
+    - condition: template
+      value_template: "{{ not is_state('media_player.tv', 'playing') }}"
+  actions:
+    - action: light.turn_on
+      target:
+        entity_id: light.living_room
+    - service: light.turn_on
+      entity_id: light.kitchen
+      data:
+        brightness: 128
+  mode: single
+- alias: Notify when away
+  trigger:
+    platform: state
+    entity_id: person.anna
+    from: home
+    for: "00:10:00"
+  action:
+    - delay: "00:05:00"
+"#;
+
+    async fn setup_store() -> Result<(AutomationStore, TempDir)> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let block_store = BlockStore::with_blocks_dir("blocks".into()).await?;
+        let store =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().to_path_buf()).await?;
+        Ok((store, temp_dir))
+    }
+
+    fn block_types(block: &Value, types: &mut Vec<String>) {
+        if let Some(t) = block.get("type").and_then(|t| t.as_str()) {
+            types.push(t.to_string());
+        }
+        for key in ["inputs", "statements"] {
+            if let Some(inputs) = block.get(key).and_then(|i| i.as_object()) {
+                for input in inputs.values() {
+                    block_types(&input["block"], types);
+                }
+            }
+        }
+        if let Some(next) = block.get("next") {
+            block_types(&next["block"], types);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_ha_automations() -> Result<()> {
+        let (store, _temp_dir) = setup_store().await?;
+
+        let report = import_automations(&store, AUTOMATIONS, HaImportOptions::default()).await?;
+        assert_eq!(report.imported.len(), 1, "{:#?}", report);
+        assert_eq!(report.skipped, vec!["Notify when away".to_string()]);
+        assert!(report.errors.is_empty());
+
+        // One stack per trigger, each guarding the actions with the conditions
+        let imported = &report.imported[0];
+        let stacks = imported.workspace["blocks"]["blocks"].as_array().unwrap();
+        assert_eq!(stacks.len(), 5);
+        assert_eq!(stacks[4]["type"], "ha_template_trigger");
+        assert_eq!(stacks[3]["type"], "ha_event_trigger");
+        assert_eq!(stacks[3]["fields"]["DATA"], r#"{"command":"on"}"#);
+        let mut types = Vec::new();
+        block_types(&stacks[1], &mut types);
+        assert_eq!(
+            types,
+            vec![
+                "ha_numeric_state_trigger",
+                "controls_if",
+                "logic_operation",
+                "logic_operation",
+                "ha_state_condition",
+                "logic_operation",
+                "ha_time_condition",
+                "ha_numeric_state_condition",
+                "ha_template_condition",
+                "ha_trigger_action",
+                "ha_call_service",
+            ]
+        );
+
+        let automation = store.get(imported.id.as_ref().unwrap()).await.unwrap();
+        assert_eq!(automation.name, "Evening lights");
+        assert!(
+            automation.compilation_error.is_none(),
+            "Imported automation does not compile: {:?}",
+            automation.compilation_error
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_reports_unsupported_constructs() -> Result<()> {
+        let (store, _temp_dir) = setup_store().await?;
+
+        let options = HaImportOptions {
+            dry_run: true,
+            allow_partial: true,
+        };
+        let report = import_automations(&store, AUTOMATIONS, options).await?;
+        assert_eq!(report.imported.len(), 2);
+        assert!(report.imported.iter().all(|i| i.id.is_none()));
+        assert!(store.list().await.is_empty());
+
+        let paths: Vec<_> = report
+            .issues
+            .iter()
+            .map(|i| (i.automation.as_str(), i.path.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("Notify when away", "triggers[0]"),
+                ("Notify when away", "actions[0]"),
+            ]
+        );
+        assert!(report.issues[0].message.contains("`for`"));
+        assert!(report.issues[1].message.contains("`delay`"));
+
+        assert!(parse_automations("- alias: [unclosed").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_round_trip() -> Result<()> {
+        let (store, _temp_dir) = setup_store().await?;
+        let report = import_automations(&store, AUTOMATIONS, HaImportOptions::default()).await?;
+        let automation = store
+            .get(report.imported[0].id.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        let exported = HaAutomation::from_automation(&automation).unwrap();
+        assert_eq!(exported.alias.as_deref(), Some("Evening lights"));
+        assert_eq!(
+            exported.triggers,
+            vec![
+                json!({"trigger": "state", "entity_id": "sun.sun", "from": "above_horizon", "to": "below_horizon"}),
+                json!({"trigger": "numeric_state", "entity_id": "sensor.outdoor_lux", "below": 20}),
+                json!({"trigger": "time", "at": "21:00:00"}),
+                json!({"trigger": "event", "event_type": "zha_event", "event_data": {"command": "on"}}),
+                json!({"trigger": "template", "value_template": "{{ states('sensor.outdoor_lux') | int(0) < 10 }}"}),
+            ]
+        );
+        assert_eq!(
+            exported.conditions,
+            vec![
+                json!({"condition": "state", "entity_id": "binary_sensor.someone_home", "state": "on"}),
+                json!({"condition": "or", "conditions": [
+                    {"condition": "time", "after": "17:00", "before": "23:59:59"},
+                    {"condition": "numeric_state", "entity_id": "sensor.indoor_lux", "below": 50},
+                ]}),
+                json!({"condition": "template", "value_template": "{{ not is_state('media_player.tv', 'playing') }}"}),
+            ]
+        );
+        assert_eq!(
+            exported.actions,
+            vec![
+                json!({"action": "light.turn_on", "target": {"entity_id": "light.living_room"}}),
+                json!({
+                    "action": "light.turn_on",
+                    "target": {"entity_id": "light.kitchen"},
+                    "data": {"brightness": 128},
+                }),
+            ]
+        );
+
+        // The exported YAML imports back into the same workspace
+        let yaml = to_yaml(&[exported])?;
+        let reimported = parse_automations(&yaml)?;
+        let converted = reimported[0].to_workspace(0);
+        assert!(converted.issues.is_empty(), "{:?}", converted.issues);
+        assert_eq!(converted.workspace, automation.workspace);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_keeps_and_inside_or() -> Result<()> {
+        let (store, _temp_dir) = setup_store().await?;
+        let yaml = r#"
+- alias: Someone left a window open
+  triggers:
+    - trigger: state
+      entity_id: sun.sun
+      to: below_horizon
+  conditions:
+    - condition: or
+      conditions:
+        - condition: state
+          entity_id: [binary_sensor.window, binary_sensor.door]
+          state: "on"
+        - condition: template
+          value_template: "{{ is_state('alarm_panel.home', 'armed_away') }}"
+  actions:
+    - action: light.turn_on
+      target:
+        entity_id: light.hall
+"#;
+        let report = import_automations(&store, yaml, HaImportOptions::default()).await?;
+        let automation = store
+            .get(report.imported[0].id.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        let exported = HaAutomation::from_automation(&automation).unwrap();
+        assert_eq!(
+            exported.conditions,
+            vec![json!({"condition": "or", "conditions": [
+                {"condition": "and", "conditions": [
+                    {"condition": "state", "entity_id": "binary_sensor.window", "state": "on"},
+                    {"condition": "state", "entity_id": "binary_sensor.door", "state": "on"},
+                ]},
+                {"condition": "template", "value_template": "{{ is_state('alarm_panel.home', 'armed_away') }}"},
+            ]})]
+        );
+
+        let reimported = parse_automations(&to_yaml(&[exported])?)?;
+        let converted = reimported[0].to_workspace(0);
+        assert!(converted.issues.is_empty(), "{:?}", converted.issues);
+        assert_eq!(converted.workspace, automation.workspace);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_reports_inexpressible_blocks() -> Result<()> {
+        let (store, _temp_dir) = setup_store().await?;
+        let automation = store
+            .create(crate::automation::AutomationCreate {
+                name: "Mixed".to_string(),
+                description: None,
+                triggers: vec![],
+                workspace: json!({"blocks": {"languageVersion": 0, "blocks": [
+                    {
+                        "type": "ha_state_trigger",
+                        "id": "trigger",
+                        "fields": {"ENTITY_ID": "sun.sun", "STATE": "below_horizon"},
+                        "next": {"block": {
+                            "type": "ha_set_state",
+                            "id": "set",
+                            "fields": {"ENTITY_ID": "input_boolean.dark", "STATE": "on"},
+                        }},
+                    },
+                    {
+                        "type": "ha_call_service",
+                        "id": "loose",
+                        "fields": {"SERVICE": "light.turn_on", "DATA": "{}"},
+                    },
+                ]}}),
+                conditions: vec![],
+                tags: vec![],
+                folder: None,
+            })
+            .await?;
+
+        let issues = HaAutomation::from_automation(&automation).unwrap_err();
+        let blocks: Vec<_> = issues.iter().map(|i| i.block_id.as_deref()).collect();
+        assert_eq!(blocks, vec![Some("set"), Some("loose")]);
+        assert!(issues[0].to_string().starts_with("ha_set_state (set): "));
+
+        Ok(())
+    }
+}
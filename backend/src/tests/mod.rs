@@ -1,16 +1,27 @@
 use futures::{SinkExt, StreamExt};
 use serde_json::json;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tokio_tungstenite::tungstenite::Message;
 
 mod automation_tests;
+mod block_tests;
+mod blueprint_tests;
+mod ha_rest_tests;
+mod ha_simulator_tests;
+mod ha_yaml_tests;
+mod pack_tests;
+mod storage_tests;
 
 pub struct MockHaServer {
     addr: SocketAddr,
     shutdown: Arc<Mutex<bool>>,
+    /// Ignore every message, like a half-open connection
+    muted: Arc<AtomicBool>,
+    entity_lists: Arc<AtomicUsize>,
 }
 
 impl MockHaServer {
@@ -19,9 +30,15 @@ impl MockHaServer {
         let addr = listener.local_addr().unwrap();
         let shutdown = Arc::new(Mutex::new(false));
         let shutdown_clone = shutdown.clone();
+        let muted = Arc::new(AtomicBool::new(false));
+        let muted_clone = muted.clone();
+        let entity_lists = Arc::new(AtomicUsize::new(0));
+        let entity_lists_clone = entity_lists.clone();
 
         tokio::spawn(async move {
+            let mut connections = 0;
             while let Ok((stream, _)) = listener.accept().await {
+                connections += 1;
                 let ws_stream = tokio_tungstenite::accept_async(stream).await.unwrap();
                 let (mut write, mut read) = ws_stream.split();
 
@@ -38,11 +55,19 @@ impl MockHaServer {
                     .await
                     .unwrap();
 
+                // The area registry gains a room once it has been listed
+                let mut area_lists = 0;
+                // and the services an integration after the first `get_services`
+                let mut service_lists = 0;
+
                 // Handle the WebSocket connection
                 while let Some(Ok(msg)) = read.next().await {
                     if *shutdown_clone.lock().await {
                         break;
                     }
+                    if muted_clone.load(Ordering::Relaxed) {
+                        continue;
+                    }
 
                     match msg {
                         Message::Text(text) => {
@@ -50,11 +75,15 @@ impl MockHaServer {
 
                             match msg["type"].as_str() {
                                 Some("auth") => {
-                                    // Send auth_ok response
+                                    let auth = if msg["access_token"] == "invalid_token" {
+                                        "auth_invalid"
+                                    } else {
+                                        "auth_ok"
+                                    };
                                     write
                                         .send(Message::Text(
                                             json!({
-                                                "type": "auth_ok",
+                                                "type": auth,
                                                 "ha_version": "2024.1.0"
                                             })
                                             .to_string()
@@ -79,6 +108,43 @@ impl MockHaServer {
                                         .await
                                         .unwrap();
 
+                                    // Send a mock event of the requested type, entity registry
+                                    // updates in a burst like while Home Assistant starts
+                                    if msg["event_type"] != "state_changed" {
+                                        let event = json!({
+                                            "id": msg["id"],
+                                            "type": "event",
+                                            "event": {
+                                                "data": {
+                                                    "device_ieee": "00:0d:6f:00:0a:90:69:e7",
+                                                    "command": "on",
+                                                    "args": []
+                                                },
+                                                "event_type": msg["event_type"],
+                                                "time_fired": "2024-01-26T10:45:00Z",
+                                                "origin": "LOCAL",
+                                                "context": {
+                                                    "id": "01HN5ZRJX8KR6MQPN2VMBKF4XN",
+                                                    "parent_id": null,
+                                                    "user_id": null
+                                                }
+                                            }
+                                        });
+                                        let burst =
+                                            if msg["event_type"] == "entity_registry_updated" {
+                                                20
+                                            } else {
+                                                1
+                                            };
+                                        for _ in 0..burst {
+                                            write
+                                                .send(Message::Text(event.to_string().into()))
+                                                .await
+                                                .unwrap();
+                                        }
+                                        continue;
+                                    }
+
                                     // Send a mock state change event
                                     write
                                         .send(Message::Text(
@@ -88,6 +154,20 @@ impl MockHaServer {
                                                 "event": {
                                                     "data": {
                                                         "entity_id": "light.living_room",
+                                                        "old_state": {
+                                                            "state": "off",
+                                                            "attributes": {
+                                                                "friendly_name": "Living Room Light"
+                                                            },
+                                                            "last_changed": "2024-01-26T09:30:00Z",
+                                                            "last_updated": "2024-01-26T09:30:00Z",
+                                                            "last_reported": "2024-01-26T09:30:00Z",
+                                                            "context": {
+                                                                "id": "01HN5VQ2C3T9D3ZPHN8R5V2K1A",
+                                                                "parent_id": null,
+                                                                "user_id": null
+                                                            }
+                                                        },
                                                         "new_state": {
                                                             "state": "on",
                                                             "attributes": {
@@ -96,10 +176,11 @@ impl MockHaServer {
                                                             },
                                                             "last_changed": "2024-01-26T10:45:00Z",
                                                             "last_updated": "2024-01-26T10:45:00Z",
+                                                            "last_reported": "2024-01-26T10:45:00Z",
                                                             "context": {
                                                                 "id": "01HN5ZRJX8KR6MQPN2VMBKF4XM",
                                                                 "parent_id": null,
-                                                                "user_id": null
+                                                                "user_id": "6b6fb1c4d0f54a2b9a7e3c2f1d0e9a8b"
                                                             }
                                                         }
                                                     },
@@ -115,7 +196,12 @@ impl MockHaServer {
                                         .unwrap();
                                 }
                                 Some("get_states") => {
-                                    // Send mock states
+                                    // The light was turned off before any reconnect
+                                    let (state, changed) = if connections == 1 {
+                                        ("on", "2024-01-26T10:45:00Z")
+                                    } else {
+                                        ("off", "2024-01-26T11:00:00Z")
+                                    };
                                     write
                                         .send(Message::Text(
                                             json!({
@@ -124,13 +210,13 @@ impl MockHaServer {
                                                 "success": true,
                                                 "result": [{
                                                     "entity_id": "light.living_room",
-                                                    "state": "on",
+                                                    "state": state,
                                                     "attributes": {
                                                         "brightness": 255,
                                                         "friendly_name": "Living Room Light"
                                                     },
-                                                    "last_changed": "2024-01-26T10:45:00Z",
-                                                    "last_updated": "2024-01-26T10:45:00Z",
+                                                    "last_changed": changed,
+                                                    "last_updated": changed,
                                                     "context": {
                                                         "id": "01HN5ZRJX8KR6MQPN2VMBKF4XM",
                                                         "parent_id": null,
@@ -144,70 +230,249 @@ impl MockHaServer {
                                         .await
                                         .unwrap();
                                 }
-                                Some("get_services") => {
-                                    // Send mock services
-                                    write.send(Message::Text(json!({
+                                Some("render_template") => {
+                                    let template = msg["template"].as_str().unwrap_or_default();
+                                    if template.contains("{% if") && !template.contains("endif") {
+                                        write
+                                            .send(Message::Text(
+                                                json!({
+                                                    "id": msg["id"],
+                                                    "type": "result",
+                                                    "success": false,
+                                                    "error": {
+                                                        "code": "template_error",
+                                                        "message": "TemplateSyntaxError: Unexpected end of template"
+                                                    }
+                                                })
+                                                .to_string()
+                                                .into(),
+                                            ))
+                                            .await
+                                            .unwrap();
+                                        continue;
+                                    }
+
+                                    // The first render, then renders as the sun sets and rises
+                                    let mut messages = vec![json!({
                                         "id": msg["id"],
                                         "type": "result",
                                         "success": true,
-                                        "result": {
-                                            "tts": {
-                                                "google_translate_say": {
-                                                    "name": "Say a TTS message with google_translate",
-                                                    "description": "Say something using text-to-speech",
-                                                    "fields": {
-                                                        "entity_id": {
-                                                            "name": "Entity ID",
-                                                            "required": true,
-                                                            "selector": {
-                                                                "entity": {
-                                                                    "domain": "media_player"
-                                                                }
-                                                            }
-                                                        },
-                                                        "message": {
-                                                            "name": "Message",
-                                                            "required": true,
-                                                            "selector": {
-                                                                "text": null
+                                        "result": null
+                                    })];
+                                    for event in [
+                                        json!({"result": true, "listeners": {"entities": ["sun.sun"]}}),
+                                        json!({"error": "UndefinedError: 'sun' is undefined", "level": "ERROR"}),
+                                        json!({"result": "off", "listeners": {"entities": ["sun.sun"]}}),
+                                        json!({"result": "on", "listeners": {"entities": ["sun.sun"]}}),
+                                    ] {
+                                        messages.push(json!({
+                                            "id": msg["id"],
+                                            "type": "event",
+                                            "event": event
+                                        }));
+                                    }
+                                    for message in messages {
+                                        write
+                                            .send(Message::Text(message.to_string().into()))
+                                            .await
+                                            .unwrap();
+                                    }
+                                }
+                                Some("config/area_registry/list") => {
+                                    area_lists += 1;
+                                    let mut areas = vec![json!({
+                                        "area_id": "living_room",
+                                        "name": "Living Room",
+                                        "aliases": ["Lounge"],
+                                        "floor_id": null,
+                                        "icon": null
+                                    })];
+                                    if area_lists > 1 {
+                                        areas.push(json!({
+                                            "area_id": "garage",
+                                            "name": "Garage",
+                                            "aliases": []
+                                        }));
+                                    }
+                                    write
+                                        .send(Message::Text(
+                                            json!({
+                                                "id": msg["id"],
+                                                "type": "result",
+                                                "success": true,
+                                                "result": areas
+                                            })
+                                            .to_string()
+                                            .into(),
+                                        ))
+                                        .await
+                                        .unwrap();
+                                }
+                                Some("config/device_registry/list") => {
+                                    write
+                                        .send(Message::Text(
+                                            json!({
+                                                "id": msg["id"],
+                                                "type": "result",
+                                                "success": true,
+                                                "result": [{
+                                                    "id": "4b5d1c3e",
+                                                    "name": "Hue bulb",
+                                                    "name_by_user": "Reading lamp",
+                                                    "area_id": "living_room",
+                                                    "manufacturer": "Signify",
+                                                    "model": "LCA001",
+                                                    "disabled_by": null
+                                                }]
+                                            })
+                                            .to_string()
+                                            .into(),
+                                        ))
+                                        .await
+                                        .unwrap();
+                                }
+                                Some("config/entity_registry/list") => {
+                                    entity_lists_clone.fetch_add(1, Ordering::Relaxed);
+                                    write
+                                        .send(Message::Text(
+                                            json!({
+                                                "id": msg["id"],
+                                                "type": "result",
+                                                "success": true,
+                                                "result": [{
+                                                    "entity_id": "light.living_room",
+                                                    "device_id": "4b5d1c3e",
+                                                    "area_id": null,
+                                                    "platform": "hue"
+                                                }, {
+                                                    "entity_id": "sensor.living_room_temperature",
+                                                    "device_id": null,
+                                                    "area_id": "living_room",
+                                                    "platform": "template"
+                                                }, {
+                                                    "entity_id": "light.garage",
+                                                    "device_id": null,
+                                                    "area_id": "garage",
+                                                    "platform": "hue",
+                                                    "disabled_by": "user"
+                                                }]
+                                            })
+                                            .to_string()
+                                            .into(),
+                                        ))
+                                        .await
+                                        .unwrap();
+                                }
+                                Some("get_services") => {
+                                    service_lists += 1;
+                                    let mut services = json!({
+                                        "tts": {
+                                            "google_translate_say": {
+                                                "name": "Say a TTS message with google_translate",
+                                                "description": "Say something using text-to-speech",
+                                                "fields": {
+                                                    "entity_id": {
+                                                        "name": "Entity ID",
+                                                        "required": true,
+                                                        "selector": {
+                                                            "entity": {
+                                                                "domain": "media_player"
                                                             }
                                                         }
+                                                    },
+                                                    "message": {
+                                                        "name": "Message",
+                                                        "required": true,
+                                                        "selector": {
+                                                            "text": null
+                                                        }
                                                     }
-                                                },
-                                                "cloud_say": {
-                                                    "description": "Say something using cloud TTS",
-                                                    "fields": {
-                                                        "entity_id": {
-                                                            "name": "Entity ID",
-                                                            "required": true,
-                                                            "selector": {
-                                                                "entity": {
-                                                                    "domain": "media_player"
-                                                                }
+                                                }
+                                            },
+                                            "cloud_say": {
+                                                "description": "Say something using cloud TTS",
+                                                "fields": {
+                                                    "entity_id": {
+                                                        "name": "Entity ID",
+                                                        "required": true,
+                                                        "selector": {
+                                                            "entity": {
+                                                                "domain": "media_player"
                                                             }
                                                         }
                                                     }
                                                 }
-                                            },
-                                            "light": {
-                                                "turn_on": {
-                                                    "name": "Turn on",
-                                                    "description": "Turn on one or more lights",
-                                                    "fields": {
-                                                        "entity_id": {
-                                                            "name": "Entity ID",
-                                                            "required": true,
-                                                            "selector": {
-                                                                "entity": {
-                                                                    "domain": "light"
-                                                                }
+                                            }
+                                        },
+                                        "light": {
+                                            "turn_on": {
+                                                "name": "Turn on",
+                                                "description": "Turn on one or more lights",
+                                                "fields": {
+                                                    "entity_id": {
+                                                        "name": "Entity ID",
+                                                        "required": true,
+                                                        "selector": {
+                                                            "entity": {
+                                                                "domain": "light"
                                                             }
                                                         }
                                                     }
                                                 }
                                             }
                                         }
-                                    }).to_string().into())).await.unwrap();
+                                    });
+                                    if service_lists > 1 {
+                                        services["notify"] = json!({
+                                            "mobile_app_phone": {
+                                                "name": "Send a notification via mobile_app_phone",
+                                                "description": "Sends a notification message",
+                                                "fields": {}
+                                            }
+                                        });
+                                    }
+                                    write
+                                        .send(Message::Text(
+                                            json!({
+                                                "id": msg["id"],
+                                                "type": "result",
+                                                "success": true,
+                                                "result": services
+                                            })
+                                            .to_string()
+                                            .into(),
+                                        ))
+                                        .await
+                                        .unwrap();
+                                }
+                                Some("ping") => {
+                                    write
+                                        .send(Message::Text(
+                                            json!({ "id": msg["id"], "type": "pong" })
+                                                .to_string()
+                                                .into(),
+                                        ))
+                                        .await
+                                        .unwrap();
+                                }
+                                Some(_) if msg["id"].is_number() => {
+                                    // Like Home Assistant, reject commands it does not know
+                                    write
+                                        .send(Message::Text(
+                                            json!({
+                                                "id": msg["id"],
+                                                "type": "result",
+                                                "success": false,
+                                                "error": {
+                                                    "code": "unknown_command",
+                                                    "message": "Unknown command."
+                                                }
+                                            })
+                                            .to_string()
+                                            .into(),
+                                        ))
+                                        .await
+                                        .unwrap();
                                 }
                                 _ => {}
                             }
@@ -218,7 +483,21 @@ impl MockHaServer {
             }
         });
 
-        Self { addr, shutdown }
+        Self {
+            addr,
+            shutdown,
+            muted,
+            entity_lists,
+        }
+    }
+
+    /// How often the entity registry was listed, over all connections.
+    pub fn entity_registry_lists(&self) -> usize {
+        self.entity_lists.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
     }
 
     pub fn host(&self) -> String {
@@ -233,7 +512,13 @@ impl MockHaServer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ha_client::HaClient;
+    use crate::ha_client::{
+        template_result_is_true, value_contains, EntityState, HaClient, HaEndpoint, StateChange,
+        TemplateUpdate,
+    };
+    use crate::ha_health::ConnectionState;
+    use crate::ha_subscription::{EntityFilter, EntityPattern, StateSubscribers, StateUpdate};
+    use crate::rhai::{register_ha_api, HaApi};
     use std::time::Duration;
     use tokio::time::sleep;
 
@@ -358,7 +643,7 @@ mod tests {
         let client = HaClient::new();
 
         // Subscribe to states before connecting
-        let mut state_rx = client.subscribe_to_states();
+        let mut states = client.subscribe_states(EntityFilter::new(["light"]));
 
         // Connect to mock server
         client
@@ -367,13 +652,19 @@ mod tests {
             .unwrap();
 
         // Wait for state update
-        if let Ok((entity_id, state)) =
-            tokio::time::timeout(Duration::from_secs(1), state_rx.recv())
+        if let StateUpdate::Changed(change) =
+            tokio::time::timeout(Duration::from_secs(1), states.recv())
                 .await
                 .unwrap()
         {
-            assert_eq!(entity_id, "light.living_room");
+            assert_eq!(change.entity_id, "light.living_room");
+            let state = &change.new_state;
             assert_eq!(state.state, "on");
+            assert_eq!(state.last_changed, "2024-01-26T10:45:00Z");
+            assert_eq!(state.last_reported.as_deref(), Some("2024-01-26T10:45:00Z"));
+            assert_eq!(change.old_state.as_ref().unwrap().state, "off");
+            assert_eq!(change.context().unwrap().id, "01HN5ZRJX8KR6MQPN2VMBKF4XM");
+            assert!(change.is_user_change());
             assert_eq!(
                 state
                     .attributes
@@ -390,4 +681,504 @@ mod tests {
         // Clean up
         mock_server.stop().await;
     }
+
+    #[tokio::test]
+    async fn test_event_subscription() {
+        let mock_server = MockHaServer::start().await;
+        let client = HaClient::new();
+
+        // Registered before connecting, sent once connected
+        let mut zha = client.subscribe_events("zha_event").await.unwrap();
+        let mut filtered = client
+            .subscribe_events("zha_event")
+            .await
+            .unwrap()
+            .with_data(json!({"command": "off"}));
+
+        client
+            .connect(mock_server.host(), "mock_token".to_string())
+            .await
+            .unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(1), zha.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.event_type, "zha_event");
+        assert_eq!(event.data["command"], "on");
+        assert_eq!(event.origin.as_deref(), Some("LOCAL"));
+        assert_eq!(event.context.unwrap().id, "01HN5ZRJX8KR6MQPN2VMBKF4XN");
+
+        // Subscribing while connected sends the subscription right away
+        let mut tag = client.subscribe_events("tag_scanned").await.unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(1), tag.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.event_type, "tag_scanned");
+
+        // The data filter drops the "on" command
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), filtered.recv())
+                .await
+                .is_err()
+        );
+
+        mock_server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_registries() {
+        let mock_server = MockHaServer::start().await;
+        let client = HaClient::new();
+        client
+            .connect(mock_server.host(), "mock_token".to_string())
+            .await
+            .unwrap();
+
+        // Loaded after connecting, then reloaded on `area_registry_updated`
+        let registries = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                let registries = client.get_registries().await;
+                if registries.areas.contains_key("garage") && !registries.entities.is_empty() {
+                    return registries;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // The light is in the area of its device, the sensor placed directly
+        assert_eq!(
+            registries.entity_area_id("light.living_room"),
+            Some("living_room")
+        );
+        assert_eq!(
+            registries
+                .entity_device("light.living_room")
+                .and_then(|device| device.display_name()),
+            Some("Reading lamp")
+        );
+        assert_eq!(
+            registries.find_area("lounge").unwrap().area_id,
+            "living_room"
+        );
+        assert_eq!(
+            registries.area_entities("living_room", None),
+            vec!["light.living_room", "sensor.living_room_temperature"]
+        );
+        assert_eq!(
+            registries.area_entities("living_room", Some("light")),
+            vec!["light.living_room"]
+        );
+
+        // Disabled entities are left out
+        let areas = registries.areas_with_entities();
+        assert_eq!(areas[0].area.name, "Garage");
+        assert!(areas[0].entities.is_empty());
+
+        let error = client
+            .request(json!({"type": "config/floor_registry/list"}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Unknown command.");
+
+        // A burst of entity registry updates is one reload after the initial load
+        sleep(Duration::from_millis(400)).await;
+        assert_eq!(mock_server.entity_registry_lists(), 2);
+
+        mock_server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_services_reload() {
+        let mock_server = MockHaServer::start().await;
+        let client = HaClient::new();
+        let mut services = client.watch_services();
+
+        client
+            .connect(mock_server.host(), "mock_token".to_string())
+            .await
+            .unwrap();
+
+        // Fetched after connecting, then again on `service_registered`
+        let actions = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                services.changed().await.unwrap();
+                let actions = client.get_all_actions().await;
+                if actions.contains_key("notify.mobile_app_phone") {
+                    return actions;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(actions.contains_key("light.turn_on"));
+        assert_eq!(
+            actions["notify.mobile_app_phone"].domain.as_deref(),
+            Some("notify")
+        );
+        assert_eq!(*services.borrow(), 2);
+
+        // Unchanged catalogs do not notify
+        client.load_services().await.unwrap();
+        assert!(!services.has_changed().unwrap());
+
+        mock_server.stop().await;
+    }
+
+    async fn wait_for_state(client: &HaClient, state: ConnectionState) {
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while client.status().state != state {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("never reached {:?}: {:?}", state, client.status()));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_reconnect() {
+        let mock_server = MockHaServer::start().await;
+        let client =
+            HaClient::new().with_heartbeat(Duration::from_millis(50), Duration::from_millis(100));
+        let endpoint = HaEndpoint::parse(&mock_server.host()).unwrap();
+        client.keep_connected(endpoint, "mock_token".to_string());
+
+        wait_for_state(&client, ConnectionState::Connected).await;
+        sleep(Duration::from_millis(120)).await;
+        let status = client.status();
+        assert!(status.ping_ms.is_some());
+        assert!(status.pings_sent >= 1);
+        assert!(status.events_received >= 1);
+        assert!(status.last_event_at.is_some());
+        assert_eq!(status.reconnects, 0);
+
+        let mut lights = client.subscribe_states(EntityFilter::new(["light"]));
+        let mut template = client
+            .subscribe_template("{{ is_state('sun.sun', 'below_horizon') }}", None)
+            .await
+            .unwrap();
+        for _ in 0..4 {
+            tokio::time::timeout(Duration::from_secs(1), template.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        // An unanswered ping closes the half-open connection
+        mock_server.set_muted(true);
+        wait_for_state(&client, ConnectionState::Reconnecting).await;
+        assert!(client.status().pings_failed >= 1);
+
+        mock_server.set_muted(false);
+        wait_for_state(&client, ConnectionState::Connected).await;
+        assert_eq!(client.status().reconnects, 1);
+
+        // The template is subscribed again and rendered from the start
+        let render = tokio::time::timeout(Duration::from_secs(1), template.recv())
+            .await
+            .unwrap();
+        assert_eq!(render, Some(TemplateUpdate::Rendered(json!(true))));
+
+        // The light turned off while disconnected; subscribers get that as a gap
+        let missed = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                if let StateUpdate::Lagged { changes, skipped } = lights.recv().await {
+                    return (changes, skipped);
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let (changes, skipped) = missed;
+        assert_eq!(skipped, 0);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].entity_id, "light.living_room");
+        assert_eq!(changes[0].old_state.as_ref().unwrap().state, "on");
+        assert_eq!(changes[0].new_state.state, "off");
+        assert_eq!(
+            client.get_state("light.living_room").await.unwrap().state,
+            "off"
+        );
+
+        mock_server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_auth_failed() {
+        let mock_server = MockHaServer::start().await;
+        let client = HaClient::new();
+        let endpoint = HaEndpoint::parse(&mock_server.host()).unwrap();
+
+        // Rejected tokens are not retried
+        let connection = client.keep_connected(endpoint, "invalid_token".to_string());
+        tokio::time::timeout(Duration::from_secs(1), connection)
+            .await
+            .unwrap()
+            .unwrap();
+        let status = client.status();
+        assert_eq!(status.state, ConnectionState::AuthFailed);
+        assert_eq!(status.last_error.as_deref(), Some("Authentication failed"));
+
+        mock_server.stop().await;
+    }
+
+    fn state_change(entity_id: &str, old: &str, new: &str) -> StateChange {
+        let state = |state: &str| EntityState {
+            state: state.to_string(),
+            attributes: Default::default(),
+            last_changed: String::new(),
+            last_updated: String::new(),
+            last_reported: None,
+            context: None,
+        };
+        StateChange {
+            entity_id: entity_id.to_string(),
+            old_state: Some(state(old)),
+            new_state: state(new),
+        }
+    }
+
+    #[test]
+    fn test_entity_filter() {
+        assert_eq!(
+            EntityPattern::parse("light"),
+            EntityPattern::Domain("light".to_string())
+        );
+        let filter = EntityFilter::new(["light", "sensor.*_temperature", "switch.fan"]);
+        assert!(filter.matches("light.kitchen"));
+        assert!(filter.matches("sensor.living_room_temperature"));
+        assert!(filter.matches("switch.fan"));
+        assert!(!filter.matches("switch.fan_2"));
+        assert!(!filter.matches("sensor.living_room_humidity"));
+        assert!(!filter.matches("lightbulb.kitchen"));
+        assert!(EntityFilter::all().matches("anything.at_all"));
+    }
+
+    #[tokio::test]
+    async fn test_state_subscription_lag() {
+        let subscribers = StateSubscribers::default();
+        let mut lights = subscribers.subscribe(EntityFilter::new(["light"]), 2);
+        let mut doors = subscribers.subscribe(EntityFilter::new(["binary_sensor.door"]), 2);
+
+        // A burst larger than the queue
+        subscribers.dispatch(&state_change("light.kitchen", "off", "on"));
+        subscribers.dispatch(&state_change("light.hall", "off", "on"));
+        subscribers.dispatch(&state_change("light.kitchen", "on", "off"));
+        subscribers.dispatch(&state_change("binary_sensor.door", "off", "on"));
+        subscribers.dispatch(&state_change("light.kitchen", "off", "on"));
+        subscribers.dispatch(&state_change("light.porch", "off", "on"));
+
+        let mut updates = Vec::new();
+        while let Some(update) = lights.try_recv() {
+            updates.push(update);
+        }
+        assert_eq!(updates.len(), 3);
+        assert!(matches!(&updates[0], StateUpdate::Changed(c) if c.entity_id == "light.kitchen"));
+        assert!(matches!(&updates[1], StateUpdate::Changed(c) if c.entity_id == "light.hall"));
+
+        // The rest is merged into one change per entity, nothing is lost
+        let StateUpdate::Lagged { changes, skipped } = &updates[2] else {
+            panic!("Expected a gap, got {:?}", updates[2]);
+        };
+        assert_eq!(*skipped, 1);
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|c| {
+                (
+                    c.entity_id.as_str(),
+                    c.old_state.as_ref().unwrap().state.as_str(),
+                    c.new_state.state.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![("light.kitchen", "on", "on"), ("light.porch", "off", "on")]
+        );
+
+        // Other subscribers are not affected
+        let update = tokio::time::timeout(Duration::from_secs(1), doors.recv())
+            .await
+            .unwrap();
+        assert!(matches!(update, StateUpdate::Changed(c) if c.new_state.state == "on"));
+        assert!(doors.try_recv().is_none());
+
+        // Dropped subscriptions stop receiving
+        drop(lights);
+        subscribers.dispatch(&state_change("light.kitchen", "on", "off"));
+        assert!(doors.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_template_subscription() {
+        let mock_server = MockHaServer::start().await;
+        let client = HaClient::new();
+        client
+            .connect(mock_server.host(), "mock_token".to_string())
+            .await
+            .unwrap();
+
+        let error = client
+            .subscribe_template("{% if is_state('sun.sun', 'below_horizon') %}", None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("TemplateSyntaxError"));
+
+        let mut template = client
+            .subscribe_template("{{ is_state('sun.sun', 'below_horizon') }}", None)
+            .await
+            .unwrap();
+        let first = tokio::time::timeout(Duration::from_secs(1), template.recv())
+            .await
+            .unwrap();
+        assert_eq!(first, Some(TemplateUpdate::Rendered(json!(true))));
+        let error = tokio::time::timeout(Duration::from_secs(1), template.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(&error, TemplateUpdate::Error(e) if e.contains("UndefinedError")));
+        assert!(!error.is_true());
+
+        // Only a change from false to true counts
+        let update = tokio::time::timeout(Duration::from_secs(1), template.becomes_true())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(update, TemplateUpdate::Rendered(json!("on")));
+
+        mock_server.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_template_script_functions() {
+        let mock_server = MockHaServer::start().await;
+        let client = HaClient::new();
+        client
+            .connect(mock_server.host(), "mock_token".to_string())
+            .await
+            .unwrap();
+        let mut engine = rhai::Engine::new();
+        register_ha_api(
+            &mut engine,
+            HaApi::default().with_backend(std::sync::Arc::new(client)),
+        );
+
+        // The mock renders true first
+        assert!(engine
+            .eval::<bool>(r#"template_is_true("{{ is_state('sun.sun', 'below_horizon') }}")"#)
+            .unwrap());
+        assert!(engine
+            .eval::<bool>(r#"template_is_true("{% if true %}")"#)
+            .unwrap_err()
+            .to_string()
+            .contains("TemplateSyntaxError"));
+
+        // The callback only runs once the template went from false to true (true, error,
+        // "off", then "on"); it keeps running until the script stops it
+        let runs = engine
+            .eval::<i64>(
+                r#"
+                let runs = 0;
+                try {
+                    on_template_true("{{ is_state('sun.sun', 'below_horizon') }}", || {
+                        runs += 1;
+                        throw "stop";
+                    });
+                } catch (error) {
+                    if error != "stop" { throw error; }
+                }
+                runs
+                "#,
+            )
+            .unwrap();
+        assert_eq!(runs, 1);
+
+        mock_server.stop().await;
+    }
+
+    #[test]
+    fn test_template_result_is_true() {
+        for result in [
+            json!(true),
+            json!(1),
+            json!(0.5),
+            json!("on"),
+            json!(" True "),
+        ] {
+            assert!(template_result_is_true(&result), "{}", result);
+        }
+        for result in [json!(false), json!(0), json!("off"), json!(""), json!(null)] {
+            assert!(!template_result_is_true(&result), "{}", result);
+        }
+    }
+
+    #[test]
+    fn test_event_data_matching() {
+        let actual = json!({"device": {"id": "abc", "model": "remote"}, "command": "on"});
+        assert!(value_contains(&actual, &json!({})));
+        assert!(value_contains(&actual, &json!({"command": "on"})));
+        assert!(value_contains(&actual, &json!({"device": {"id": "abc"}})));
+        assert!(!value_contains(&actual, &json!({"command": "off"})));
+        assert!(!value_contains(&actual, &json!({"missing": null})));
+    }
+
+    #[test]
+    fn test_endpoint_parsing() {
+        let url = |address: &str| HaEndpoint::parse(address).unwrap().url.to_string();
+
+        assert_eq!(url("localhost:8123"), "ws://localhost:8123/api/websocket");
+        assert_eq!(
+            url("http://homeassistant.local:8123/"),
+            "ws://homeassistant.local:8123/api/websocket"
+        );
+        assert_eq!(
+            url("https://ha.example.com"),
+            "wss://ha.example.com/api/websocket"
+        );
+        assert_eq!(
+            url("wss://ha.example.com/custom/websocket"),
+            "wss://ha.example.com/custom/websocket"
+        );
+        assert_eq!(
+            HaEndpoint::supervisor().url.as_str(),
+            "ws://supervisor/core/websocket"
+        );
+        assert!(HaEndpoint::parse("ftp://ha.example.com").is_err());
+    }
+
+    #[test]
+    fn test_tls_connector() {
+        let plain = HaEndpoint::parse("localhost:8123").unwrap();
+        assert!(!plain.is_tls());
+        assert!(plain.tls_connector().unwrap().is_none());
+
+        let tls = HaEndpoint::parse("https://ha.example.com").unwrap();
+        assert!(tls.tls_connector().unwrap().is_some());
+
+        // A configured CA that cannot be read is an error rather than silently ignored
+        let missing_ca = tls.with_ca_cert("/nonexistent/ca.pem");
+        assert!(missing_ca.tls_connector().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_url() {
+        let mock_server = MockHaServer::start().await;
+        let client = HaClient::new();
+
+        client
+            .connect(
+                format!("http://{}", mock_server.host()),
+                "mock_token".to_string(),
+            )
+            .await
+            .unwrap();
+
+        sleep(Duration::from_millis(100)).await;
+        assert!(!client.get_all_states().await.is_empty());
+
+        mock_server.stop().await;
+    }
 }
@@ -0,0 +1,283 @@
+#[cfg(test)]
+use crate::blockly::{LayoutCategory, ToolboxItem, ToolboxLayout, ToolboxStore};
+#[cfg(test)]
+use crate::blocks::{BlockArgument, BlockDefinition, BlockStore};
+#[cfg(test)]
+use crate::packs::{BlockPack, ExportOptions, ImportOptions};
+#[cfg(test)]
+use std::io::Result;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    async fn setup_store() -> Result<(BlockStore, ToolboxStore, TempDir)> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let blocks_dir = temp_dir.path().join("blocks");
+        tokio::fs::create_dir_all(blocks_dir.join("builtin")).await?;
+        tokio::fs::write(blocks_dir.join("builtin").join("say.rhai"), "say();").await?;
+
+        let block_store = BlockStore::with_blocks_dir(blocks_dir.clone()).await?;
+        let toolbox_store = ToolboxStore::with_layouts_dir(blocks_dir.join("toolbox")).await?;
+        Ok((block_store, toolbox_store, temp_dir))
+    }
+
+    fn notify_block() -> BlockDefinition {
+        BlockDefinition {
+            r#type: "notify".to_string(),
+            message0: "notify %1".to_string(),
+            args0: Some(vec![BlockArgument {
+                r#type: "field_input".to_string(),
+                name: "MESSAGE".to_string(),
+                ..Default::default()
+            }]),
+            rhai_template: Some("notify(\"{{MESSAGE}}\");".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_pack() -> Result<()> {
+        let (block_store, toolbox_store, _temp_dir) = setup_store().await?;
+        block_store.create_or_update(notify_block()).await?;
+        toolbox_store
+            .save(
+                "default",
+                ToolboxLayout {
+                    categories: vec![LayoutCategory {
+                        name: "Notify".to_string(),
+                        include_user_blocks: true,
+                        ..Default::default()
+                    }],
+                    hidden: vec![],
+                },
+            )
+            .await?;
+
+        let pack = BlockPack::export(
+            &block_store,
+            Some(&toolbox_store),
+            ExportOptions {
+                name: "notifications".to_string(),
+                namespace: Some("acme".to_string()),
+                block_types: vec!["notify".to_string(), "say".to_string()],
+                category: Some("Notify".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        assert_eq!(pack.blocks.len(), 1);
+        assert!(pack.blocks[0].id.is_none(), "Export must drop block ids");
+        assert_eq!(pack.templates.get("say").unwrap(), "say();");
+        assert_eq!(pack.toolbox_category.as_ref().unwrap().name, "Notify");
+
+        let missing = BlockPack::export(
+            &block_store,
+            None,
+            ExportOptions {
+                name: "missing".to_string(),
+                block_types: vec!["nope".to_string()],
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(missing.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_pack_with_namespace() -> Result<()> {
+        let (block_store, toolbox_store, _temp_dir) = setup_store().await?;
+        toolbox_store
+            .save("default", ToolboxLayout::default())
+            .await?;
+
+        let mut pack = BlockPack {
+            name: "notifications".to_string(),
+            namespace: Some("acme".to_string()),
+            blocks: vec![notify_block()],
+            toolbox_category: Some(LayoutCategory {
+                name: "Acme".to_string(),
+                contents: vec![ToolboxItem::Block {
+                    r#type: "notify".to_string(),
+                    disabled: None,
+                    gap: None,
+                    fields: None,
+                    inputs: None,
+                    mutation: None,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        pack.templates
+            .insert("shout".to_string(), "shout();".to_string());
+
+        // The bundle survives a YAML round trip
+        let pack = BlockPack::from_yaml(&pack.to_yaml()?)?;
+
+        // A dry run reports what would happen without writing anything
+        let report = pack
+            .clone()
+            .import(
+                &block_store,
+                Some(&toolbox_store),
+                ImportOptions {
+                    dry_run: true,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert!(!report.applied);
+        assert_eq!(report.blocks, vec!["acme_notify".to_string()]);
+        assert_eq!(report.templates, vec!["acme_shout".to_string()]);
+        assert!(report.conflicts.is_empty());
+        assert!(!block_store.contains("acme_notify").await);
+
+        let report = pack
+            .clone()
+            .import(&block_store, Some(&toolbox_store), ImportOptions::default())
+            .await?;
+        assert!(report.applied, "Import was not applied: {:?}", report);
+        assert!(block_store.get("acme_notify").await.unwrap().id.is_some());
+        assert_eq!(
+            block_store.builtin_template("acme_shout").await.unwrap(),
+            "shout();"
+        );
+
+        let layout = toolbox_store.get("default").await.unwrap();
+        match &layout.categories[0].contents[0] {
+            ToolboxItem::Block { r#type, .. } => assert_eq!(r#type, "acme_notify"),
+            other => panic!("Unexpected toolbox item: {:?}", other),
+        }
+
+        // Importing again conflicts unless overwriting
+        let report = pack
+            .clone()
+            .import(&block_store, Some(&toolbox_store), ImportOptions::default())
+            .await?;
+        assert!(!report.applied);
+        assert_eq!(report.conflicts.len(), 2);
+
+        let report = pack
+            .import(
+                &block_store,
+                Some(&toolbox_store),
+                ImportOptions {
+                    overwrite: true,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert!(report.applied);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_pack_rejects_unsafe_names() -> Result<()> {
+        let (block_store, _toolbox_store, temp_dir) = setup_store().await?;
+
+        let mut escaping_type = notify_block();
+        escaping_type.r#type = "../../outside".to_string();
+        let mut pack = BlockPack {
+            name: "escape".to_string(),
+            blocks: vec![escaping_type],
+            ..Default::default()
+        };
+        pack.templates
+            .insert("../escape".to_string(), "escape();".to_string());
+
+        let report = pack
+            .import(&block_store, None, ImportOptions::default())
+            .await?;
+        assert!(!report.applied);
+        assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+        assert!(report.errors[0].contains("Invalid block type \"../../outside\""));
+        assert!(report.errors[1].contains("Invalid template name \"../escape\""));
+        assert!(!temp_dir.path().join("outside.yaml").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_pack_cannot_replace_shipped_templates() -> Result<()> {
+        let (block_store, _toolbox_store, _temp_dir) = setup_store().await?;
+
+        let mut pack = BlockPack {
+            name: "override".to_string(),
+            ..Default::default()
+        };
+        pack.templates
+            .insert("say".to_string(), "whisper();".to_string());
+
+        let report = pack
+            .import(
+                &block_store,
+                None,
+                ImportOptions {
+                    overwrite: true,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert!(!report.applied);
+        assert_eq!(report.conflicts[0].block_type, "say");
+        assert_eq!(block_store.builtin_template("say").await.unwrap(), "say();");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pack_category_uses_built_in_default_layout() -> Result<()> {
+        let (block_store, toolbox_store, _temp_dir) = setup_store().await?;
+        block_store.create_or_update(notify_block()).await?;
+
+        // Nothing saved yet, so the built-in default layout is used
+        let pack = BlockPack::export(
+            &block_store,
+            Some(&toolbox_store),
+            ExportOptions {
+                name: "logic".to_string(),
+                block_types: vec!["notify".to_string()],
+                category: Some("Logic".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(pack.toolbox_category.as_ref().unwrap().name, "Logic");
+
+        let pack = BlockPack {
+            name: "notifications".to_string(),
+            namespace: Some("acme".to_string()),
+            blocks: vec![notify_block()],
+            toolbox_category: Some(LayoutCategory {
+                name: "Acme".to_string(),
+                include_user_blocks: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let report = pack
+            .import(&block_store, Some(&toolbox_store), ImportOptions::default())
+            .await?;
+        assert!(report.applied, "Import was not applied: {:?}", report);
+
+        let names: Vec<_> = toolbox_store
+            .get("default")
+            .await
+            .unwrap()
+            .categories
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names.first().map(String::as_str), Some("Logic"));
+        assert_eq!(names.last().map(String::as_str), Some("Acme"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+#[cfg(test)]
+use crate::automation::{AutomationCreate, AutomationStore, AutomationUpdate};
+#[cfg(test)]
+use crate::blockly::{ToolboxLayout, ToolboxStore};
+#[cfg(test)]
+use crate::blocks::{BlockDefinition, BlockStore};
+#[cfg(test)]
+use crate::blueprint::{self, BlueprintCreate, BlueprintStore, BlueprintUpdate};
+#[cfg(test)]
+use crate::storage::{
+    GitAutomationRepository, GitBlockRepository, GitBlueprintRepository,
+    SqliteAutomationRepository, SqliteBlockRepository, SqliteBlueprintRepository, SqliteDatabase,
+};
+#[cfg(test)]
+use serde_json::json;
+#[cfg(test)]
+use std::io::Result;
+#[cfg(test)]
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn say_block() -> BlockDefinition {
+        BlockDefinition {
+            r#type: "say".to_string(),
+            message0: "say".to_string(),
+            previous_statement: Some(true),
+            next_statement: Some(true),
+            rhai_template: Some("print(\"hi\");".to_string()),
+            ..Default::default()
+        }
+    }
+
+    async fn open_stores(database: &SqliteDatabase) -> Result<(BlockStore, AutomationStore)> {
+        let block_store = BlockStore::with_repository(Arc::new(SqliteBlockRepository::new(
+            database.clone(),
+            None,
+        )))
+        .await?;
+        let automation_store = AutomationStore::with_repository(
+            block_store.clone(),
+            Arc::new(SqliteAutomationRepository::new(database.clone())),
+        )
+        .await?;
+        Ok((block_store, automation_store))
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_backend_roundtrip() -> Result<()> {
+        let database = SqliteDatabase::open_in_memory()?;
+        let (block_store, store) = open_stores(&database).await?;
+        block_store.create_or_update(say_block()).await?;
+        block_store.save_builtin_template("wave", "wave();").await?;
+
+        let workspace = json!({"blocks": [{"type": "say", "id": "b1"}]});
+        let created = store
+            .create(AutomationCreate {
+                name: "SQLite".to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: workspace.clone(),
+                tags: vec![],
+                folder: None,
+            })
+            .await?;
+        let updated = store
+            .update(
+                &created.id,
+                AutomationUpdate {
+                    name: "SQLite renamed".to_string(),
+                    description: None,
+                    enabled: true,
+                    version: created.version,
+                    triggers: vec![],
+                    conditions: vec![],
+                    workspace,
+                    tags: None,
+                    folder: None,
+                },
+            )
+            .await?
+            .unwrap();
+
+        // A second store on the same database sees everything written by the first
+        let (block_store, store) = open_stores(&database).await?;
+        assert!(block_store.get("say").await.is_some());
+        assert!(block_store.is_imported_template("wave"));
+        assert_eq!(
+            store.get(&created.id).await.unwrap().version,
+            updated.version
+        );
+        let versions = store.versions(&created.id).await?.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(
+            store.get_version(&created.id, 1).await?.unwrap().name,
+            "SQLite"
+        );
+
+        assert!(block_store.delete("say").await?);
+        assert!(store.delete(&created.id).await?);
+        let (block_store, store) = open_stores(&database).await?;
+        assert!(block_store.get("say").await.is_none());
+        assert!(store.list().await.is_empty());
+        assert!(store.get_version(&created.id, 1).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_mismatched_script_is_recompiled_on_load() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("automations.db");
+        let script = |conn: &rusqlite::Connection| -> String {
+            conn.query_row("SELECT script FROM automation_scripts", [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+
+        // A database from before scripts carried a hash
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE automation_scripts (id TEXT PRIMARY KEY, version INTEGER NOT NULL, script TEXT NOT NULL);",
+        )
+        .unwrap();
+
+        let database = SqliteDatabase::open(&path)?;
+        let (block_store, store) = open_stores(&database).await?;
+        block_store.create_or_update(say_block()).await?;
+        store
+            .create(AutomationCreate {
+                name: "Hashed".to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: json!({"blocks": [{"type": "say", "id": "b1"}]}),
+                tags: vec![],
+                folder: None,
+            })
+            .await?;
+        let compiled = script(&conn);
+
+        // Simulate a crash that left the row with a truncated script
+        conn.execute("UPDATE automation_scripts SET script = 'pri'", [])
+            .unwrap();
+        open_stores(&database).await?;
+        assert_eq!(script(&conn), compiled);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_hides_deleted_shipped_blocks() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        tokio::fs::create_dir_all(temp_dir.path().join("custom")).await?;
+        tokio::fs::write(
+            temp_dir.path().join("custom").join("say.yaml"),
+            serde_yaml::to_string(&say_block()).unwrap(),
+        )
+        .await?;
+
+        let database = SqliteDatabase::open_in_memory()?;
+        let open = || async {
+            BlockStore::with_repository(Arc::new(SqliteBlockRepository::new(
+                database.clone(),
+                Some(temp_dir.path().to_path_buf()),
+            )))
+            .await
+        };
+
+        let block_store = open().await?;
+        assert!(block_store.get("say").await.is_some());
+        block_store.delete("say").await?;
+
+        // The shipped file is left alone but stays hidden
+        assert!(temp_dir.path().join("custom").join("say.yaml").exists());
+        assert!(open().await?.get("say").await.is_none());
+
+        Ok(())
+    }
+
+    /// Commit subjects on `HEAD`, newest first.
+    fn commit_messages(dir: &std::path::Path) -> Vec<String> {
+        let repo = git2::Repository::open(dir).unwrap();
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        walk.map(|oid| {
+            let commit = repo.find_commit(oid.unwrap()).unwrap();
+            commit.summary().unwrap().to_string()
+        })
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn test_git_backend_commits_changes() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let blocks_dir = temp_dir.path().join("blocks");
+        let automations_dir = temp_dir.path().join("automations");
+
+        let block_store = BlockStore::with_repository(Arc::new(
+            GitBlockRepository::open(blocks_dir.clone()).await?,
+        ))
+        .await?;
+        block_store.create_or_update(say_block()).await?;
+        block_store.create_or_update(say_block()).await?;
+
+        let store = AutomationStore::with_repository(
+            block_store.clone(),
+            Arc::new(GitAutomationRepository::open(automations_dir.clone()).await?),
+        )
+        .await?;
+        let workspace = json!({"blocks": [{"type": "say", "id": "b1"}]});
+        let created = store
+            .create(AutomationCreate {
+                name: "Git".to_string(),
+                description: None,
+                triggers: vec![],
+                conditions: vec![],
+                workspace: workspace.clone(),
+                tags: vec![],
+                folder: None,
+            })
+            .await?;
+        let updated = store
+            .update(
+                &created.id,
+                AutomationUpdate {
+                    name: "Git".to_string(),
+                    description: Some("Tracked".to_string()),
+                    enabled: true,
+                    version: created.version,
+                    triggers: vec![],
+                    conditions: vec![],
+                    workspace,
+                    tags: None,
+                    folder: None,
+                },
+            )
+            .await?
+            .unwrap();
+        store.toggle(&created.id, false).await?;
+
+        // History and diffs come from the commits
+        let versions: Vec<_> = store
+            .versions(&created.id)
+            .await?
+            .unwrap()
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, vec![updated.version, created.version]);
+        let diff = store
+            .diff(&created.id, created.version, updated.version)
+            .await?
+            .unwrap();
+        assert!(diff.properties.contains_key("description"));
+        assert!(!automations_dir.join("history").exists());
+
+        store.delete(&created.id).await?;
+        assert_eq!(
+            commit_messages(&automations_dir),
+            vec![
+                "Delete automation 'Git'".to_string(),
+                "Disable automation 'Git'".to_string(),
+                format!("Update automation 'Git' to version {}", updated.version),
+                "Create automation 'Git'".to_string(),
+                "Start tracking automations".to_string(),
+            ]
+        );
+        assert_eq!(
+            commit_messages(&blocks_dir),
+            vec![
+                "Update block 'say'".to_string(),
+                "Create block 'say'".to_string(),
+                "Start tracking blocks".to_string(),
+            ]
+        );
+
+        // Files edited by hand are committed on the next start
+        tokio::fs::write(blocks_dir.join("notes.txt"), "hello").await?;
+        GitBlockRepository::open(blocks_dir.clone()).await?;
+        assert_eq!(
+            commit_messages(&blocks_dir)[0],
+            "Record changes made outside the add-on"
+        );
+        assert_eq!(commit_messages(&blocks_dir).len(), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_git_backend_commits_toolbox_layouts() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let blocks_dir = temp_dir.path().join("blocks");
+        let repository = GitBlockRepository::open(blocks_dir.clone()).await?;
+        let toolbox_store = ToolboxStore::with_layouts_dir(blocks_dir.join("toolbox"))
+            .await?
+            .with_git(repository.work_tree());
+
+        let layout = |hidden: &[&str]| ToolboxLayout {
+            hidden: hidden.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        toolbox_store.save("compact", layout(&[])).await?;
+        toolbox_store.save("compact", layout(&["say"])).await?;
+        assert!(toolbox_store.delete("compact").await?);
+        assert!(!toolbox_store.delete("compact").await?);
+
+        assert_eq!(
+            commit_messages(&blocks_dir),
+            vec![
+                "Delete toolbox layout 'compact'".to_string(),
+                "Update toolbox layout 'compact'".to_string(),
+                "Create toolbox layout 'compact'".to_string(),
+                "Start tracking blocks".to_string(),
+            ]
+        );
+        let repo = git2::Repository::open(&blocks_dir).unwrap();
+        assert!(repo.statuses(None).unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blueprints_follow_the_storage_backend() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let block_store = BlockStore::with_blocks_dir(temp_dir.path().join("blocks")).await?;
+        block_store.create_or_update(say_block()).await?;
+        let automations =
+            AutomationStore::with_storage_path(block_store, temp_dir.path().join("automations"))
+                .await?;
+        let create = || BlueprintCreate {
+            name: "Greeting".to_string(),
+            description: None,
+            inputs: vec![],
+            workspace: json!({"blocks": [{"type": "say", "id": "b1"}]}),
+        };
+
+        // SQLite keeps them in the database
+        let database = SqliteDatabase::open_in_memory()?;
+        let open_sqlite = || async {
+            BlueprintStore::with_repository(Arc::new(SqliteBlueprintRepository::new(
+                database.clone(),
+            )))
+            .await
+        };
+        let created = open_sqlite().await?.create(create()).await.unwrap();
+        assert_eq!(
+            open_sqlite().await?.get(&created.id).await.unwrap().name,
+            "Greeting"
+        );
+
+        // Git commits every change
+        let blueprints_dir = temp_dir.path().join("blueprints");
+        let blueprints = BlueprintStore::with_repository(Arc::new(
+            GitBlueprintRepository::open(blueprints_dir.clone()).await?,
+        ))
+        .await?;
+        let created = blueprints.create(create()).await.unwrap();
+        let mut update = create();
+        update.description = Some("Says hi".to_string());
+        blueprints
+            .update(
+                &created.id,
+                BlueprintUpdate {
+                    name: update.name,
+                    description: update.description,
+                    inputs: update.inputs,
+                    workspace: update.workspace,
+                    version: created.version,
+                },
+            )
+            .await
+            .unwrap();
+        blueprint::delete(&blueprints, &automations, &created.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            commit_messages(&blueprints_dir),
+            vec![
+                "Delete blueprint 'Greeting'".to_string(),
+                "Update blueprint 'Greeting' to version 2".to_string(),
+                "Create blueprint 'Greeting'".to_string(),
+                "Start tracking blueprints".to_string(),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -26,6 +26,32 @@ pub struct AutomationEditTemplate {
 #[template(path = "automations/list.html")]
 pub struct AutomationsListTemplate {
     pub automations: Vec<AutomationViewModel>,
+    pub diagnostics: Vec<crate::diagnostics::LoadDiagnostic>,
+    pub tags: Vec<String>,
+    pub folders: Vec<String>,
+}
+
+/// Cards of the automations matching a search, swapped into the list.
+#[derive(Template)]
+#[template(path = "automations/cards.html")]
+pub struct AutomationCardsTemplate {
+    pub automations: Vec<AutomationViewModel>,
+}
+
+#[derive(Template)]
+#[template(path = "automations/card.html")]
+pub struct AutomationCardTemplate {
+    pub automation: AutomationViewModel,
+}
+
+#[derive(Template)]
+#[template(path = "automations/import.html")]
+pub struct AutomationImportTemplate {
+    pub yaml: String,
+    pub dry_run: bool,
+    pub allow_partial: bool,
+    pub report: Option<crate::ha_yaml::HaImportReport>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -37,6 +63,9 @@ pub struct AutomationViewModel {
     pub version: i32,
     pub updated_at: DateTime<Utc>,
     pub workspace: Value,
+    pub tags: Vec<String>,
+    pub folder: Option<String>,
+    pub compilation_error: Option<String>,
 }
 
 impl AutomationViewModel {
@@ -49,6 +78,9 @@ impl AutomationViewModel {
             version: automation.version,
             updated_at: automation.updated_at,
             workspace: automation.workspace.clone(),
+            tags: automation.tags,
+            folder: automation.folder,
+            compilation_error: automation.compilation_error,
         }
     }
 }
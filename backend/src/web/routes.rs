@@ -11,14 +11,13 @@ use serde::Deserialize;
 use std::sync::Arc;
 
 use super::{
-    AutomationCreateTemplate, AutomationEditTemplate, AutomationViewModel, AutomationsListTemplate,
+    AutomationCardTemplate, AutomationCardsTemplate, AutomationCreateTemplate,
+    AutomationEditTemplate, AutomationImportTemplate, AutomationViewModel, AutomationsListTemplate,
+};
+use crate::{
+    automation::{AutomationFilter, AutomationUpdate},
+    AppState,
 };
-use crate::{automation::AutomationUpdate, AppState};
-
-#[derive(Deserialize)]
-pub struct SearchQuery {
-    q: Option<String>,
-}
 
 use serde_json::Value;
 
@@ -40,6 +39,8 @@ pub fn router() -> Router<Arc<AppState>> {
         .route("/automations/{id}/toggle", post(toggle_automation))
         .route("/automations/{id}", delete(delete_automation))
         .route("/automations/new", get(new_automation))
+        .route("/automations/import", get(import_form))
+        .route("/automations/import", post(import_automations))
         .route("/automations", post(create_automation))
         .route("/automations/analyze", post(analyze_automation))
         .route("/automations/{id}/edit", get(edit_automation))
@@ -52,6 +53,18 @@ pub struct CreateAutomationRequest {
     name: String,
     description: Option<String>,
     workspace: String,
+    #[serde(default)]
+    tags: String,
+    folder: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportAutomationsRequest {
+    yaml: String,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    allow_partial: bool,
 }
 
 #[derive(Deserialize)]
@@ -59,6 +72,13 @@ pub struct UpdateAutomationRequest {
     name: String,
     description: Option<String>,
     workspace: String,
+    tags: Option<String>,
+    folder: Option<String>,
+}
+
+/// Tags are entered as a comma-separated list.
+fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',').map(str::to_string).collect()
 }
 
 #[axum::debug_handler]
@@ -128,6 +148,8 @@ async fn create_automation(
         workspace,
         triggers: Vec::new(),
         conditions: Vec::new(),
+        tags: parse_tags(&payload.tags),
+        folder: payload.folder,
     };
 
     if let Err(_) = state.automation_store.create(automation_create).await {
@@ -144,15 +166,60 @@ async fn create_automation(
         .into_response()
 }
 
+async fn import_form() -> impl IntoResponse {
+    HtmlTemplate(AutomationImportTemplate {
+        yaml: String::new(),
+        dry_run: false,
+        allow_partial: false,
+        report: None,
+        error: None,
+    })
+}
+
+async fn import_automations(
+    State(state): State<Arc<AppState>>,
+    Form(payload): Form<ImportAutomationsRequest>,
+) -> impl IntoResponse {
+    let options = crate::ha_yaml::HaImportOptions {
+        dry_run: payload.dry_run,
+        allow_partial: payload.allow_partial,
+    };
+    let (report, error) =
+        match crate::ha_yaml::import_automations(&state.automation_store, &payload.yaml, options)
+            .await
+        {
+            Ok(report) => (Some(report), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+    HtmlTemplate(AutomationImportTemplate {
+        yaml: payload.yaml,
+        dry_run: payload.dry_run,
+        allow_partial: payload.allow_partial,
+        report,
+        error,
+    })
+}
+
 async fn list_automations(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let automations = state.automation_store.list().await;
+    let automations = state
+        .automation_store
+        .search(&AutomationFilter::default(), None)
+        .await;
     let view_models: Vec<AutomationViewModel> = automations
-        .iter()
-        .map(|a| AutomationViewModel::from_automation(a.clone()))
+        .into_iter()
+        .map(AutomationViewModel::from_automation)
         .collect();
 
+    let mut diagnostics = state.automation_store.diagnostics();
+    diagnostics.extend(state.block_store.diagnostics());
+    diagnostics.extend(state.blueprint_store.diagnostics());
+
     let template = AutomationsListTemplate {
         automations: view_models,
+        diagnostics,
+        tags: state.automation_store.tags().await,
+        folders: state.automation_store.folders().await,
     };
 
     HtmlTemplate(template)
@@ -160,28 +227,20 @@ async fn list_automations(State(state): State<Arc<AppState>>) -> impl IntoRespon
 
 async fn search_automations(
     State(state): State<Arc<AppState>>,
-    Query(query): Query<SearchQuery>,
+    Query(filter): Query<AutomationFilter>,
 ) -> impl IntoResponse {
-    let search_term = query.q.unwrap_or_default().to_lowercase();
-    let automations = state.automation_store.list().await;
-
-    let filtered: Vec<AutomationViewModel> = automations
-        .iter()
-        .filter(|a| {
-            a.name.to_lowercase().contains(&search_term)
-                || a.description
-                    .as_ref()
-                    .map(|d| d.to_lowercase().contains(&search_term))
-                    .unwrap_or(false)
-        })
-        .map(|a| AutomationViewModel::from_automation(a.clone()))
-        .collect();
-
-    let template = AutomationsListTemplate {
-        automations: filtered,
-    };
-
-    HtmlTemplate(template)
+    let registries = state.ha.get_registries().await;
+    let automations = state
+        .automation_store
+        .search(&filter, Some(&registries))
+        .await;
+
+    HtmlTemplate(AutomationCardsTemplate {
+        automations: automations
+            .into_iter()
+            .map(AutomationViewModel::from_automation)
+            .collect(),
+    })
 }
 
 async fn toggle_automation(
@@ -192,60 +251,17 @@ async fn toggle_automation(
         Some(automation) => {
             let enabled = !automation.enabled;
             match state.automation_store.toggle(&id, enabled).await {
-                Ok(Some(updated)) => {
-                    let view_model = AutomationViewModel::from_automation(updated);
-                    Html(format!(
-                        r##"<div class="card automation-card" id="automation-{}">
-                            <div class="card-content">
-                                <md-headline4>{}</md-headline4>
-                                {}
-                                <div style="margin-top: 8px;">
-                                    <md-caption>Version: {}</md-caption>
-                                    <md-caption>Last updated: {}</md-caption>
-                                </div>
-                            </div>
-                            <div class="card-actions">
-                                <md-switch
-                                    selected="{}"
-                                    hx-post="/automations/{}/toggle"
-                                    hx-target="#automation-{}"
-                                    hx-swap="outerHTML">
-                                </md-switch>
-                                <div>
-                                    <md-icon-button href="/automations/{}/edit">
-                                        <md-icon>edit</md-icon>
-                                    </md-icon-button>
-                                    <md-icon-button
-                                        hx-delete="/automations/{}"
-                                        hx-confirm="Are you sure you want to delete this automation?"
-                                        hx-target="#automation-{}"
-                                        hx-swap="outerHTML">
-                                        <md-icon>delete</md-icon>
-                                    </md-icon-button>
-                                </div>
-                            </div>
-                        </div>"##,
-                        view_model.id,
-                        view_model.name,
-                        view_model
-                            .description
-                            .map(|d| format!("<md-body>{}</md-body>", d))
-                            .unwrap_or_default(),
-                        view_model.version,
-                        view_model.updated_at,
-                        view_model.enabled,
-                        view_model.id,
-                        view_model.id,
-                        view_model.id,
-                        view_model.id,
-                        view_model.id
-                    ))
-                }
-                Ok(None) => Html("<div class='error'>Automation not found</div>".to_string()),
-                Err(_) => Html("<div class='error'>Failed to toggle automation</div>".to_string()),
+                Ok(Some(updated)) => HtmlTemplate(AutomationCardTemplate {
+                    automation: AutomationViewModel::from_automation(updated),
+                })
+                .into_response(),
+                Ok(None) => Html("<div class='error'>Automation not found</div>".to_string())
+                    .into_response(),
+                Err(_) => Html("<div class='error'>Failed to toggle automation</div>".to_string())
+                    .into_response(),
             }
         }
-        None => Html("<div class='error'>Automation not found</div>".to_string()),
+        None => Html("<div class='error'>Automation not found</div>".to_string()).into_response(),
     }
 }
 
@@ -378,6 +394,7 @@ async fn edit_automation(
                 version: 0,
                 updated_at: Utc::now(),
                 workspace: serde_json::json!({}),
+                ..Default::default()
             },
             toolbox: None,
         }),
@@ -416,6 +433,8 @@ async fn update_automation(
         version: current.version,
         triggers: current.triggers,
         conditions: current.conditions,
+        tags: payload.tags.as_deref().map(parse_tags),
+        folder: payload.folder.map(Some),
     };
 
     // Update automation